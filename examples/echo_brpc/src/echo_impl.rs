@@ -4,10 +4,7 @@ use async_trait::async_trait;
 use protobuf::Message;
 use tracing::instrument;
 
-use server_kit::{
-    protocol::{Brpc, Protocol},
-    Result, Service, ServiceDescriptor,
-};
+use server_kit::{Context, MethodDescriptor, Result, Service, ServiceDescriptor};
 
 use crate::{
     echo::{EchoRequest, EchoResponse},
@@ -50,12 +47,21 @@ where
 {
     fn descriptor(&self) -> ServiceDescriptor {
         ServiceDescriptor {
-            protocol: Box::new(Brpc::default()),
             full_name: "example.echo_brpc",
+            methods: vec![
+                MethodDescriptor::new("echo").with_message_types::<EchoRequest, EchoResponse>(),
+                MethodDescriptor::new("another_echo")
+                    .with_message_types::<EchoRequest, EchoResponse>(),
+            ],
         }
     }
 
-    async fn call_method(&self, method: &str, req: &[u8]) -> server_kit::Result<Vec<u8>> {
+    async fn call_method(
+        &self,
+        _ctx: &mut Context,
+        method: &str,
+        req: &[u8],
+    ) -> server_kit::Result<Vec<u8>> {
         let mut echo_req = EchoRequest::new();
         echo_req.merge_from_bytes(req).unwrap();
 
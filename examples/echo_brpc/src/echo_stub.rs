@@ -1,12 +1,12 @@
+use std::sync::Arc;
+
 use async_trait::async_trait;
 use protobuf::{Message, MessageField};
 use tracing::{debug, instrument};
 
 use server_kit::{
-    channel::Channel,
-    message::CommonMsg,
-    protocol::{Brpc, Protocol},
-    Result, Service, ServiceDescriptor,
+    channel::Channel, message::CommonMsg, protocol::Protocol, Context, Controller,
+    MethodDescriptor, Result, Service, ServiceDescriptor,
 };
 use server_kit_protocol::baidu_rpc_meta::{RpcMeta, RpcRequestMeta};
 
@@ -17,16 +17,16 @@ use crate::{
 
 pub struct EchoStub<P>
 where
-    P: Protocol + Sync + Send + 'static,
+    P: Protocol + ?Sized,
 {
-    channel: Channel<P>,
+    channel: Arc<Channel<P>>,
 }
 
 impl<P> EchoStub<P>
 where
-    P: Protocol + Sync + Send + 'static,
+    P: Protocol + ?Sized,
 {
-    pub fn new(channel: Channel<P>) -> Self {
+    pub fn new(channel: Arc<Channel<P>>) -> Self {
         Self { channel }
     }
 }
@@ -34,7 +34,7 @@ where
 #[async_trait]
 impl<P> EchoService for EchoStub<P>
 where
-    P: Protocol + Sync + Send + 'static,
+    P: Protocol + ?Sized,
 {
     #[instrument(skip_all)]
     async fn echo(&self, req: EchoRequest) -> Result<EchoResponse> {
@@ -44,14 +44,18 @@ where
         let method_name = "echo".to_string();
         debug!("set service name[{svc_name}], method name[{method_name}]");
         req_meta.set_service_name(svc_name.to_string());
-        req_meta.set_method_name(method_name);
+        req_meta.set_method_name(method_name.clone());
         meta.request = MessageField::some(req_meta);
 
         let req = req.write_to_bytes()?;
         let mut msg = CommonMsg::new(req);
         msg.with_meta(meta.write_to_bytes()?);
 
-        let resp = self.channel.process(msg).await?;
+        let mut cntl = Controller::new();
+        if let Some(method) = self.descriptor().method(&method_name) {
+            cntl.set_request_compress_type(method.request_compression);
+        }
+        let resp = self.channel.call(&mut cntl, msg).await?;
 
         Ok(EchoResponse::parse_from_bytes(&resp)?)
     }
@@ -64,14 +68,18 @@ where
         let method_name = "another_echo".to_string();
         debug!("set service name[{svc_name}], method name[{method_name}]");
         req_meta.set_service_name(svc_name.to_string());
-        req_meta.set_method_name(method_name);
+        req_meta.set_method_name(method_name.clone());
         meta.request = MessageField::some(req_meta);
 
         let req = req.write_to_bytes()?;
         let mut msg = CommonMsg::new(req);
         msg.with_meta(meta.write_to_bytes()?);
 
-        let resp = self.channel.process(msg).await?;
+        let mut cntl = Controller::new();
+        if let Some(method) = self.descriptor().method(&method_name) {
+            cntl.set_request_compress_type(method.request_compression);
+        }
+        let resp = self.channel.call(&mut cntl, msg).await?;
 
         Ok(EchoResponse::parse_from_bytes(&resp)?)
     }
@@ -80,16 +88,25 @@ where
 #[async_trait]
 impl<P> Service for EchoStub<P>
 where
-    P: Protocol + Sync + Send + 'static,
+    P: Protocol + ?Sized,
 {
     fn descriptor(&self) -> ServiceDescriptor {
         ServiceDescriptor {
-            protocol: Box::new(Brpc::default()),
             full_name: "example.echo_brpc",
+            methods: vec![
+                MethodDescriptor::new("echo").with_message_types::<EchoRequest, EchoResponse>(),
+                MethodDescriptor::new("another_echo")
+                    .with_message_types::<EchoRequest, EchoResponse>(),
+            ],
         }
     }
 
-    async fn call_method(&self, _method: &str, _req: &[u8]) -> server_kit::Result<Vec<u8>> {
+    async fn call_method(
+        &self,
+        _ctx: &mut Context,
+        _method: &str,
+        _req: &[u8],
+    ) -> server_kit::Result<Vec<u8>> {
         unimplemented!()
     }
 }
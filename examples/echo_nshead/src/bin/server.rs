@@ -3,6 +3,7 @@ use tracing::{debug, instrument};
 
 use echo_nshead::echo::{EchoRequest, EchoResponse};
 use echo_nshead::EchoServiceImpl;
+use server_kit::protocol::Nshead;
 use server_kit::{global, Server};
 
 #[tokio::main]
@@ -13,6 +14,7 @@ async fn main() -> Result<()> {
 
     let service = EchoServiceImpl::new(echo);
     server.add_service(service)?;
+    server.add_protocol(Nshead::with_service("example.echo_nshead", "echo"))?;
 
     server.start().await?;
 
@@ -4,10 +4,7 @@ use async_trait::async_trait;
 use protobuf::Message;
 use tracing::instrument;
 
-use server_kit::{
-    protocol::{Nshead, Protocol},
-    Result, Service, ServiceDescriptor,
-};
+use server_kit::{Context, MethodDescriptor, Result, Service, ServiceDescriptor};
 
 use crate::{
     echo::{EchoRequest, EchoResponse},
@@ -52,12 +49,17 @@ where
 {
     fn descriptor(&self) -> ServiceDescriptor {
         ServiceDescriptor {
-            protocol: Box::new(Nshead::default()),
-            full_name: "nshead",
+            full_name: "example.echo_nshead",
+            methods: vec![MethodDescriptor::new("echo")],
         }
     }
 
-    async fn call_method(&self, _method: &str, req: &[u8]) -> server_kit::Result<Vec<u8>> {
+    async fn call_method(
+        &self,
+        _ctx: &mut Context,
+        _method: &str,
+        req: &[u8],
+    ) -> server_kit::Result<Vec<u8>> {
         let mut echo_req = EchoRequest::new();
         echo_req.merge_from_bytes(req).unwrap();
 
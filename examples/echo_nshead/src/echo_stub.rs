@@ -1,12 +1,12 @@
+use std::sync::Arc;
+
 use async_trait::async_trait;
 use protobuf::{Message, MessageField};
 use tracing::{debug, instrument};
 
 use server_kit::{
-    channel::Channel,
-    message::CommonMsg,
-    protocol::{Nshead, Protocol},
-    Result, Service, ServiceDescriptor,
+    channel::Channel, message::CommonMsg, protocol::Protocol, Context, MethodDescriptor, Result,
+    Service, ServiceDescriptor,
 };
 use server_kit_protocol::baidu_rpc_meta::{RpcMeta, RpcRequestMeta};
 
@@ -17,16 +17,16 @@ use crate::{
 
 pub struct EchoStub<P>
 where
-    P: Protocol + Sync + Send + 'static,
+    P: Protocol + ?Sized,
 {
-    channel: Channel<P>,
+    channel: Arc<Channel<P>>,
 }
 
 impl<P> EchoStub<P>
 where
-    P: Protocol + Sync + Send + 'static,
+    P: Protocol + ?Sized,
 {
-    pub fn new(channel: Channel<P>) -> Self {
+    pub fn new(channel: Arc<Channel<P>>) -> Self {
         Self { channel }
     }
 }
@@ -34,7 +34,7 @@ where
 #[async_trait]
 impl<P> EchoService for EchoStub<P>
 where
-    P: Protocol + Sync + Send + 'static,
+    P: Protocol + ?Sized,
 {
     #[instrument(skip_all)]
     async fn echo(&self, req: EchoRequest) -> Result<EchoResponse> {
@@ -60,16 +60,21 @@ where
 #[async_trait]
 impl<P> Service for EchoStub<P>
 where
-    P: Protocol + Sync + Send + 'static,
+    P: Protocol + ?Sized,
 {
     fn descriptor(&self) -> ServiceDescriptor {
         ServiceDescriptor {
-            protocol: Box::new(Nshead::default()),
-            full_name: "nshead",
+            full_name: "example.echo_nshead",
+            methods: vec![MethodDescriptor::new("echo")],
         }
     }
 
-    async fn call_method(&self, _method: &str, _req: &[u8]) -> server_kit::Result<Vec<u8>> {
+    async fn call_method(
+        &self,
+        _ctx: &mut Context,
+        _method: &str,
+        _req: &[u8],
+    ) -> server_kit::Result<Vec<u8>> {
         unimplemented!()
     }
 }
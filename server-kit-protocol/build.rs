@@ -61,7 +61,10 @@ fn main() {
     println!("cargo:rerun-if-changed=proto");
     println!("cargo:rerun-if-changed=proto/brpc");
     println!("cargo:rerun-if-changed=proto/brpc/baidu_rpc_meta.proto");
+    println!("cargo:rerun-if-changed=proto/brpc/hulu_pbrpc_meta.proto");
     println!("cargo:rerun-if-changed=proto/brpc/options.proto");
+    println!("cargo:rerun-if-changed=proto/brpc/public_pbrpc_meta.proto");
+    println!("cargo:rerun-if-changed=proto/brpc/sofa_pbrpc_meta.proto");
     println!("cargo:rerun-if-changed=proto/brpc/streaming_rpc_meta.proto");
 
     cleanup();
@@ -0,0 +1,62 @@
+use std::net::SocketAddr;
+
+use async_trait::async_trait;
+
+use crate::error::AuthErr;
+use crate::Result;
+
+/// Verifies the credential of requests on the server side, the
+/// `authentication_data` of brpc requests or the Authorization header of HTTP ones.
+#[async_trait]
+pub trait Authenticator: Send + Sync + 'static {
+    /// Returns the identity proven by `auth_data`, or an error to reject the request.
+    async fn verify(&self, auth_data: &[u8], remote_addr: SocketAddr) -> Result<String>;
+
+    /// Verify every request rather than only the first one of each connection.
+    fn verify_every_request(&self) -> bool {
+        false
+    }
+}
+
+/// Generates the `authentication_data` of requests on the channel side.
+pub trait CredentialGenerator: Send + Sync + 'static {
+    fn generate(&self) -> Result<Vec<u8>>;
+}
+
+/// A token known to both sides, which proves `identity` to the server.
+pub struct SharedToken {
+    identity: String,
+    token: Vec<u8>,
+}
+
+impl SharedToken {
+    pub fn new(identity: impl Into<String>, token: impl Into<Vec<u8>>) -> Self {
+        Self {
+            identity: identity.into(),
+            token: token.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl Authenticator for SharedToken {
+    async fn verify(&self, auth_data: &[u8], remote_addr: SocketAddr) -> Result<String> {
+        // compare all bytes to not tell how much of the token is right
+        let same = auth_data.len() == self.token.len()
+            && auth_data
+                .iter()
+                .zip(&self.token)
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0;
+        match same {
+            true => Ok(self.identity.clone()),
+            false => Err(AuthErr::Rejected(remote_addr.to_string()).into()),
+        }
+    }
+}
+
+impl CredentialGenerator for SharedToken {
+    fn generate(&self) -> Result<Vec<u8>> {
+        Ok(self.token.clone())
+    }
+}
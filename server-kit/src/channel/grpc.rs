@@ -0,0 +1,155 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use bytes::Bytes;
+use http::{HeaderValue, Method, Request, Uri};
+use protobuf::MessageFull;
+use tracing::{instrument, warn};
+
+use super::{is_retryable, Channel};
+use crate::error::{HttpErr, RpcErr};
+use crate::message::CommonMsg;
+use crate::protocol::{grpc, grpc_status, Grpc, Protocol};
+use crate::{Controller, Error, Result};
+
+impl Channel<Grpc> {
+    /// Unary call of `method_name` of the grpc service `svc_name`, as in
+    /// "package.Service", with the timeouts, retries and load balancing of the channel.
+    ///
+    /// Statuses other than OK fail with `RpcErr::Failed` of `grpc-status` and `grpc-message`.
+    #[instrument(name = "channel", skip_all, fields(ns_url = %self.ns_url))]
+    pub async fn unary(
+        &self,
+        cntl: &mut Controller,
+        svc_name: &str,
+        method_name: &str,
+        req: Vec<u8>,
+    ) -> Result<Vec<u8>> {
+        self.prepare(cntl)?;
+        let path = format!("/{svc_name}/{method_name}");
+        let body = Bytes::from(self.protocol.pack_request(cntl, CommonMsg::new(req))?);
+
+        let timeout = self.options.timeout;
+        let idempotent = cntl.idempotent();
+        let send = async {
+            let mut retry = 0;
+            loop {
+                let addr = self.lb.select(&self.servers);
+                let sent = AtomicBool::new(false);
+                let res = self.send_grpc(cntl, addr, &path, body.clone(), &sent).await;
+                let sent = sent.load(Ordering::Relaxed);
+                match res {
+                    Err(e)
+                        if retry < self.options.max_retry && is_retryable(&e, sent, idempotent) =>
+                    {
+                        retry += 1;
+                        warn!("retry {retry} after err:{e}");
+                    }
+                    res => return res,
+                }
+            }
+        };
+        let msg = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, send)
+                .await
+                .map_err(|_| RpcErr::Timeout(timeout))??,
+            None => send.await?,
+        };
+
+        self.protocol.process_response(cntl, msg).await
+    }
+
+    /// Unary call with protobuf messages, see `unary`.
+    pub async fn call_method<Req, Resp>(
+        &self,
+        cntl: &mut Controller,
+        svc_name: &str,
+        method_name: &str,
+        req: &Req,
+    ) -> Result<Resp>
+    where
+        Req: MessageFull,
+        Resp: MessageFull,
+    {
+        let resp = self
+            .unary(cntl, svc_name, method_name, req.write_to_bytes()?)
+            .await?;
+        Ok(Resp::parse_from_bytes(&resp)?)
+    }
+
+    async fn send_grpc(
+        &self,
+        cntl: &mut Controller,
+        addr: &str,
+        path: &str,
+        body: Bytes,
+        sent: &AtomicBool,
+    ) -> Result<CommonMsg> {
+        let (sender, remote_addr) = self.pool.h2(addr).await?;
+        cntl.set_remote_addr(remote_addr);
+
+        let mut req = Request::new(());
+        *req.method_mut() = Method::POST;
+        *req.uri_mut() = Uri::try_from(format!("http://{addr}{path}"))
+            .map_err(|e| HttpErr::InvalidBody(e.to_string()))?;
+        let headers = req.headers_mut();
+        grpc::insert_headers(headers, cntl.request_metadata());
+        headers.insert("content-type", HeaderValue::from_static(grpc::CONTENT_TYPE));
+        headers.insert("te", HeaderValue::from_static("trailers"));
+        headers.insert(
+            "grpc-accept-encoding",
+            HeaderValue::from_static(grpc::ACCEPT_ENCODING),
+        );
+        if let Some(encoding) = grpc::encoding_name(cntl.request_compress_type())? {
+            headers.insert("grpc-encoding", HeaderValue::from_static(encoding));
+        }
+        if let Some(timeout) = self.options.timeout {
+            let timeout = HeaderValue::from_str(&grpc::format_timeout(timeout)).unwrap();
+            headers.insert("grpc-timeout", timeout);
+        }
+        if !cntl.authentication_data().is_empty() {
+            let credential = HeaderValue::from_bytes(cntl.authentication_data())
+                .map_err(|e| HttpErr::InvalidBody(e.to_string()))?;
+            headers.insert("authorization", credential);
+        }
+
+        // a connection failing on the way is not used again
+        let exchange = async {
+            let mut sender = sender.ready().await?;
+            sent.store(true, Ordering::Relaxed);
+            let (resp, mut stream) = sender.send_request(req, false)?;
+            stream.send_data(body, true)?;
+            let (parts, mut body) = resp.await?.into_parts();
+            let data = grpc::read_body(&mut body).await?;
+            let trailers = body.trailers().await?;
+            Ok::<_, Error>((parts, data, trailers))
+        };
+        let (parts, data, trailers) = match exchange.await {
+            Ok(res) => res,
+            Err(e) => {
+                self.pool.remove_h2(addr);
+                return Err(e);
+            }
+        };
+        // trailers-only responses carry the status in the headers
+        let mut metadata = grpc::to_metadata(&parts.headers);
+        if let Some(trailers) = trailers {
+            for (name, value) in grpc::to_metadata(&trailers).iter() {
+                metadata.insert(name, value);
+            }
+        }
+        let status = metadata.get("grpc-status").map(str::parse::<i32>);
+        let message = grpc::percent_decode(metadata.get("grpc-message").unwrap_or_default());
+        cntl.set_response_compress_type(grpc::compress_type(metadata.get("grpc-encoding"))?);
+        cntl.set_response_metadata(metadata);
+        match status {
+            Some(Ok(grpc_status::OK)) => {}
+            Some(Ok(code)) => return Err(RpcErr::Failed(code, message).into()),
+            _ => {
+                let text = format!("http status {} without grpc-status", parts.status);
+                return Err(RpcErr::Failed(grpc_status::UNKNOWN, text).into());
+            }
+        }
+
+        Ok(CommonMsg::new(data))
+    }
+}
@@ -0,0 +1,49 @@
+use protobuf::MessageFull;
+
+use super::Channel;
+use crate::error::HttpErr;
+use crate::protocol::{read_http_response, BodyFormat, Http, HttpRequest, HttpResponse};
+use crate::{Controller, Result};
+
+impl Channel<Http> {
+    /// Send `req` with the timeouts, retries and load balancing of the channel,
+    /// addressed to the server of each attempt unless it has a `Host`. Responses
+    /// of any status are returned, see `HttpResponse::into_success`.
+    pub async fn request(&self, cntl: &mut Controller, req: HttpRequest) -> Result<HttpResponse> {
+        let mut msgs = self.send_request(cntl, req.try_into()?, 1).await?;
+        read_http_response(cntl, msgs.pop().unwrap_or_default())
+    }
+
+    /// Call `method_name` of the server-kit service `svc_name` served over
+    /// http, with the messages encoded in `format`.
+    pub async fn call_method<Req, Resp>(
+        &self,
+        cntl: &mut Controller,
+        svc_name: &str,
+        method_name: &str,
+        format: BodyFormat,
+        req: &Req,
+    ) -> Result<Resp>
+    where
+        Req: MessageFull,
+        Resp: MessageFull,
+    {
+        let body = match format {
+            BodyFormat::Json => protobuf_json_mapping::print_to_string(req)
+                .map_err(HttpErr::from)?
+                .into_bytes(),
+            BodyFormat::Proto => req.write_to_bytes()?,
+        };
+        let req = HttpRequest::call_method(svc_name, method_name, format, body);
+        let body = self.request(cntl, req).await?.into_success()?;
+
+        match format {
+            BodyFormat::Json => {
+                let json =
+                    std::str::from_utf8(&body).map_err(|e| HttpErr::InvalidBody(e.to_string()))?;
+                Ok(protobuf_json_mapping::parse_from_str(json).map_err(HttpErr::from)?)
+            }
+            BodyFormat::Proto => Ok(Resp::parse_from_bytes(&body)?),
+        }
+    }
+}
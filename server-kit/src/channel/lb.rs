@@ -0,0 +1,29 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rand::Rng;
+
+use crate::error::ConfErr;
+use crate::Result;
+
+pub(crate) enum LoadBalancer {
+    RoundRobin(AtomicUsize),
+    Random,
+}
+
+impl LoadBalancer {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "" | "rr" => Ok(LoadBalancer::RoundRobin(AtomicUsize::new(0))),
+            "random" => Ok(LoadBalancer::Random),
+            _ => Err(ConfErr::Invalid("lb_name", name.to_string()).into()),
+        }
+    }
+
+    pub fn select<'a>(&self, servers: &'a [String]) -> &'a str {
+        let idx = match self {
+            LoadBalancer::RoundRobin(next) => next.fetch_add(1, Ordering::Relaxed),
+            LoadBalancer::Random => rand::thread_rng().gen_range(0..servers.len()),
+        };
+        &servers[idx % servers.len()]
+    }
+}
@@ -0,0 +1,61 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::conf::{self, ChannelConf, ClientConf};
+use crate::error::ConfErr;
+use crate::Result;
+
+use super::{Channel, ChannelProtocol};
+
+/// Named channels configured by the `[channels.<name>]` sections of a client conf.
+///
+/// A channel is built on first use and shared afterwards, so its connections
+/// are pooled across callers.
+#[derive(Default)]
+pub struct ChannelManager {
+    confs: HashMap<String, ChannelConf>,
+    channels: Mutex<HashMap<String, Arc<dyn Any + Send + Sync>>>,
+}
+
+impl ChannelManager {
+    pub async fn new(conf: impl AsRef<Path>) -> Result<Self> {
+        let conf: ClientConf = conf::read_conf(conf).await?;
+        Ok(Self::from_confs(conf.channels))
+    }
+
+    pub fn from_confs(confs: HashMap<String, ChannelConf>) -> Self {
+        Self {
+            confs,
+            channels: Default::default(),
+        }
+    }
+
+    pub fn get<P>(&self, name: &str) -> Result<Arc<Channel<P>>>
+    where
+        P: ChannelProtocol + ?Sized,
+    {
+        let mut channels = self.channels.lock().unwrap();
+        let channel = match channels.get(name) {
+            Some(channel) => Arc::clone(channel),
+            None => {
+                let conf = self
+                    .confs
+                    .get(name)
+                    .ok_or_else(|| ConfErr::ChannelNotExist(name.to_string()))?;
+                let channel = Arc::new(Channel::<P>::from_conf(conf)?);
+                channels.insert(name.to_string(), channel.clone());
+                channel
+            }
+        };
+
+        channel.downcast().map_err(|_| {
+            ConfErr::Invalid(
+                "protocol_name",
+                format!("{name} requested as another protocol"),
+            )
+            .into()
+        })
+    }
+}
@@ -0,0 +1,79 @@
+use protobuf::MessageFull;
+
+use super::Channel;
+use crate::mcpack::{pb, Format};
+use crate::message::CommonMsg;
+use crate::protocol::{NsheadMcpack, Protocol, UbrpcCompack, UbrpcMcpack2};
+use crate::{Controller, Result};
+
+impl Channel<NsheadMcpack> {
+    /// Send `req` as a mcpack body, with the timeouts, retries and load
+    /// balancing of the channel, and get the mcpack response as `Resp`.
+    pub async fn call_message<Req, Resp>(&self, cntl: &mut Controller, req: &Req) -> Result<Resp>
+    where
+        Req: MessageFull,
+        Resp: MessageFull,
+    {
+        call_message(self, cntl, None, req, Format::Mcpack2).await
+    }
+}
+
+impl Channel<UbrpcCompack> {
+    /// Call the method of the service with `req` as its params, and get its
+    /// result params as `Resp`.
+    pub async fn call_method<Req, Resp>(
+        &self,
+        cntl: &mut Controller,
+        svc_name: &str,
+        method_name: &str,
+        req: &Req,
+    ) -> Result<Resp>
+    where
+        Req: MessageFull,
+        Resp: MessageFull,
+    {
+        let method_name = format!("{svc_name}.{method_name}");
+        call_message(self, cntl, Some(&method_name), req, Format::Compack).await
+    }
+}
+
+impl Channel<UbrpcMcpack2> {
+    /// Call the method of the service with `req` as its params, and get its
+    /// result params as `Resp`.
+    pub async fn call_method<Req, Resp>(
+        &self,
+        cntl: &mut Controller,
+        svc_name: &str,
+        method_name: &str,
+        req: &Req,
+    ) -> Result<Resp>
+    where
+        Req: MessageFull,
+        Resp: MessageFull,
+    {
+        let method_name = format!("{svc_name}.{method_name}");
+        call_message(self, cntl, Some(&method_name), req, Format::Mcpack2).await
+    }
+}
+
+async fn call_message<P, Req, Resp>(
+    ch: &Channel<P>,
+    cntl: &mut Controller,
+    method_name: Option<&str>,
+    req: &Req,
+    format: Format,
+) -> Result<Resp>
+where
+    P: Protocol,
+    Req: MessageFull,
+    Resp: MessageFull,
+{
+    let mut msg = CommonMsg::new(pb::message_to_value(req).encode(format));
+    if let Some(method_name) = method_name {
+        msg.with_meta(method_name.as_bytes().to_vec());
+    }
+    let resp = ch.call(cntl, msg).await?;
+    let resp = pb::mcpack_to_pb(&Resp::descriptor(), &resp)?;
+
+    Ok(Resp::parse_from_bytes(&resp)?)
+}
@@ -0,0 +1,29 @@
+use super::Channel;
+use crate::message::CommonMsg;
+use crate::protocol::{Memcache, MemcacheReply, MemcacheRequest};
+use crate::{Controller, Result};
+
+impl Channel<Memcache> {
+    /// Send the operations of `req` pipelined on one connection, with the
+    /// timeouts, retries and load balancing of the channel.
+    ///
+    /// Responses are in the order of the operations.
+    pub async fn pipeline(
+        &self,
+        cntl: &mut Controller,
+        req: MemcacheRequest,
+    ) -> Result<Vec<MemcacheReply>> {
+        let count = req.len();
+        let msg = CommonMsg::try_from(req)?;
+        if count == 0 {
+            return Ok(vec![]);
+        }
+        let replies = self.call_pipelined(cntl, msg, count).await?;
+
+        replies
+            .iter()
+            .enumerate()
+            .map(|(i, reply)| MemcacheReply::parse(reply, i))
+            .collect()
+    }
+}
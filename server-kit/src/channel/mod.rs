@@ -0,0 +1,707 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::BytesMut;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::{debug, instrument, warn};
+
+use server_kit_protocol::options::{ChannelAttribute, ConnectionType, MeshState};
+
+use crate::auth::CredentialGenerator;
+use crate::conf::{ChannelConf, ChannelTlsConf};
+use crate::error::{ConfErr, ParseErr, ProtocolErr, RpcErr, StreamErr};
+use crate::global::BUF_SIZE;
+use crate::message::{Chunks, CommonMsg};
+use crate::protocol::{self, Protocol, Streaming};
+use crate::socket::Connection;
+use crate::stream::{self, Stream, StreamOptions};
+use crate::tls::ClientTls;
+use crate::{Controller, Error, Result};
+
+use lb::LoadBalancer;
+use pool::{Conn, Pool};
+
+pub use manager::ChannelManager;
+
+mod grpc;
+mod http;
+mod lb;
+mod manager;
+mod mcpack;
+mod memcache;
+mod pool;
+mod redis;
+mod thrift;
+
+/// Sidecar of channels made by `from_attribute` in mesh mode, unless configured otherwise.
+pub const DEFAULT_MESH_ADDR: &str = "127.0.0.1:15001";
+
+#[derive(Debug, Clone)]
+pub struct MeshOptions {
+    pub state: MeshState,
+    /// Sidecars to send requests through, as `ip:port` or `list://ip:port,ip:port`.
+    pub addr: String,
+    /// Deadline of calls through the sidecar, instead of the channel's `timeout`.
+    pub timeout: Option<Duration>,
+    /// Load balancer picking the sidecar among those of `addr`.
+    pub lb_name: String,
+}
+
+impl MeshOptions {
+    pub fn new(state: MeshState, addr: impl Into<String>) -> Self {
+        Self {
+            state,
+            addr: addr.into(),
+            timeout: None,
+            lb_name: String::new(),
+        }
+    }
+}
+
+// the sidecars of `options` and the load balancer picking among them
+struct Mesh {
+    options: MeshOptions,
+    sidecars: Vec<String>,
+    lb: LoadBalancer,
+}
+
+impl Mesh {
+    fn new(options: MeshOptions) -> Result<Self> {
+        Ok(Self {
+            sidecars: servers_from_ns_url(&options.addr)?,
+            lb: LoadBalancer::from_name(&options.lb_name)?,
+            options,
+        })
+    }
+
+    fn select(&self) -> &str {
+        self.lb.select(&self.sidecars)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ChannelOptions {
+    pub connect_timeout: Option<Duration>,
+    /// Deadline of a whole call, retries and backup requests included.
+    pub timeout: Option<Duration>,
+    /// Send a second request if the first one hasn't been answered in time.
+    pub backup_request: Option<Duration>,
+    /// Retries of a call failing before its request is sent, or at any point
+    /// if the `Controller` marks the call idempotent.
+    pub max_retry: u32,
+    pub connection_type: ConnectionType,
+    /// Send requests larger than this in chunks, if the protocol supports it.
+    pub chunk_size: Option<usize>,
+}
+
+/// How a channel gets its protocol: by type for `Channel<Brpc>` and alike, by
+/// the configured `protocol_name` from the registry for `Channel<dyn Protocol>`.
+pub trait ChannelProtocol: Protocol {
+    fn new_channel_protocol(name: &str) -> Result<Box<Self>>;
+}
+
+impl<P> ChannelProtocol for P
+where
+    P: Protocol,
+{
+    fn new_channel_protocol(name: &str) -> Result<Box<Self>> {
+        let protocol = P::default();
+        if !name.is_empty() && name != protocol.name() {
+            return Err(ConfErr::Invalid("protocol_name", name.to_string()).into());
+        }
+        Ok(Box::new(protocol))
+    }
+}
+
+impl ChannelProtocol for dyn Protocol {
+    fn new_channel_protocol(name: &str) -> Result<Box<Self>> {
+        // baidu_std unless told otherwise, same as brpc
+        let name = match name {
+            "" => "baidu_std",
+            name => name,
+        };
+        protocol::new_protocol_by_name(name)
+            .ok_or_else(|| ProtocolErr::NotExist(name.to_string()).into())
+    }
+}
+
+pub struct Channel<P>
+where
+    P: Protocol + ?Sized,
+{
+    protocol: Box<P>,
+    ns_url: String,
+    servers: Vec<String>,
+    lb: LoadBalancer,
+    pool: Pool,
+    // connections only ever written to, so no unread response can be left on `pool`
+    oneway_pool: Pool,
+    options: ChannelOptions,
+    mesh: Option<Mesh>,
+    credential: Option<Arc<dyn CredentialGenerator>>,
+    tls: Option<ClientTls>,
+}
+
+impl<P> Channel<P>
+where
+    P: Protocol,
+{
+    pub fn new(addr: String) -> Self {
+        Self {
+            protocol: Box::new(P::default()),
+            ns_url: addr.clone(),
+            servers: vec![addr],
+            lb: LoadBalancer::RoundRobin(Default::default()),
+            pool: Pool::new(ConnectionType::CONNECTION_TYPE_POOLED, None, None),
+            oneway_pool: Pool::new(ConnectionType::CONNECTION_TYPE_POOLED, None, None),
+            options: Default::default(),
+            mesh: None,
+            credential: None,
+            tls: None,
+        }
+    }
+}
+
+impl<P> Channel<P>
+where
+    P: ChannelProtocol + ?Sized,
+{
+    pub fn from_attribute(attr: &ChannelAttribute) -> Result<Self> {
+        let protocol = P::new_channel_protocol(attr.protocol_name())?;
+        let options = ChannelOptions {
+            connect_timeout: millis(attr.connect_timeout_ms),
+            timeout: millis(attr.timeout_ms),
+            backup_request: millis(attr.backup_request_ms),
+            max_retry: attr.max_retry().max(0) as u32,
+            connection_type: connection_type_from_name(attr.connection_type_name())?,
+            ..Default::default()
+        };
+        let mesh = match attr.mesh_state() {
+            MeshState::MESH_STATE_OFF => None,
+            state => Some(Mesh::new(MeshOptions {
+                timeout: millis(attr.mesh_timeout_ms),
+                lb_name: attr.mesh_lb_name().to_string(),
+                ..MeshOptions::new(state, DEFAULT_MESH_ADDR)
+            })?),
+        };
+
+        Ok(Self {
+            protocol,
+            ns_url: attr.ns_url().to_string(),
+            servers: servers_from_ns_url(attr.ns_url())?,
+            lb: LoadBalancer::from_name(attr.lb_name())?,
+            pool: Pool::new(options.connection_type, options.connect_timeout, None),
+            oneway_pool: Pool::new(options.connection_type, options.connect_timeout, None),
+            options,
+            mesh,
+            credential: None,
+            tls: None,
+        })
+    }
+
+    pub fn from_conf(conf: &ChannelConf) -> Result<Self> {
+        let mut channel = Self::from_attribute(&conf.to_attribute()?)?;
+        channel.options.chunk_size = conf.chunk_size;
+        if let (Some(mesh), Some(addr)) = (&channel.mesh, &conf.mesh_addr) {
+            let options = MeshOptions {
+                addr: addr.clone(),
+                ..mesh.options.clone()
+            };
+            channel.mesh = Some(Mesh::new(options)?);
+        }
+        match &conf.tls {
+            Some(tls) => channel.with_tls(tls),
+            None => Ok(channel),
+        }
+    }
+}
+
+impl<P> Channel<P>
+where
+    P: Protocol + ?Sized,
+{
+    pub fn with_options(mut self, options: ChannelOptions) -> Self {
+        self.options = options;
+        self.reset_pools();
+        self
+    }
+
+    /// Connect to the servers over TLS, verifying them by `conf.server_name`.
+    pub fn with_tls(mut self, conf: &ChannelTlsConf) -> Result<Self> {
+        self.tls = Some(ClientTls::new(conf)?);
+        self.reset_pools();
+        Ok(self)
+    }
+
+    fn reset_pools(&mut self) {
+        let options = &self.options;
+        self.pool = Pool::new(
+            options.connection_type,
+            options.connect_timeout,
+            self.tls.clone(),
+        );
+        self.oneway_pool = Pool::new(
+            options.connection_type,
+            options.connect_timeout,
+            self.tls.clone(),
+        );
+    }
+
+    /// Send requests through the sidecars of `mesh`.
+    pub fn with_mesh(mut self, mesh: MeshOptions) -> Result<Self> {
+        self.mesh = Some(Mesh::new(mesh)?);
+        Ok(self)
+    }
+
+    /// Send the credential made by `credential` with every request.
+    pub fn with_credential<C>(mut self, credential: C) -> Self
+    where
+        C: CredentialGenerator,
+    {
+        self.credential = Some(Arc::new(credential));
+        self
+    }
+
+    pub async fn process(&self, req: CommonMsg) -> Result<Vec<u8>> {
+        self.call(&mut Controller::default(), req).await
+    }
+
+    #[instrument(name = "channel", skip_all, fields(ns_url = %self.ns_url))]
+    pub async fn call(&self, cntl: &mut Controller, req: CommonMsg) -> Result<Vec<u8>> {
+        let mut msgs = self.send_request(cntl, req, 1).await?;
+        let msg = msgs.pop().unwrap_or_default();
+
+        // process response
+        let resp = self.protocol.process_response(cntl, msg).await?;
+        if self.mesh().is_some() {
+            debug!(
+                remote = ?cntl.remote_addr(),
+                real_remote = ?cntl.real_remote_addr(),
+                "finish mesh call"
+            );
+        }
+
+        Ok(resp)
+    }
+
+    /// `call` with `req` packing `count` pipelined requests, which get as many
+    /// responses, in order.
+    #[instrument(name = "channel", skip_all, fields(ns_url = %self.ns_url))]
+    pub(crate) async fn call_pipelined(
+        &self,
+        cntl: &mut Controller,
+        req: CommonMsg,
+        count: usize,
+    ) -> Result<Vec<Vec<u8>>> {
+        let msgs = self.send_request(cntl, req, count).await?;
+        let mut resps = Vec::with_capacity(msgs.len());
+        for msg in msgs {
+            resps.push(self.protocol.process_response(cntl, msg).await?);
+        }
+
+        Ok(resps)
+    }
+
+    // send `req` and read the `replies` responses to it
+    async fn send_request(
+        &self,
+        cntl: &mut Controller,
+        req: CommonMsg,
+        replies: usize,
+    ) -> Result<Vec<CommonMsg>> {
+        self.prepare(cntl)?;
+        let mesh = self.mesh();
+
+        // pack request, in frames kept to be sent again on retries
+        let frames: Vec<_> = self.protocol.pack_request_frames(cntl, req)?.collect();
+        let idempotent = cntl.idempotent();
+
+        // send request and parse response
+        let timeout = mesh
+            .and_then(|mesh| mesh.options.timeout)
+            .or(self.options.timeout);
+        let send = async {
+            match mesh {
+                Some(mesh) => self.send_to_mesh(mesh, &frames, replies, idempotent).await,
+                None => self.send(&frames, replies, idempotent).await,
+            }
+        };
+        let (msgs, remote_addr) = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, send)
+                .await
+                .map_err(|_| RpcErr::Timeout(timeout))??,
+            None => send.await?,
+        };
+        cntl.set_remote_addr(remote_addr);
+
+        Ok(msgs)
+    }
+
+    /// Call with a stream set up alongside, on a connection of its own which
+    /// stays open for as long as the stream.
+    #[instrument(name = "channel", skip_all, fields(ns_url = %self.ns_url))]
+    pub async fn call_with_stream(
+        &self,
+        cntl: &mut Controller,
+        req: CommonMsg,
+        options: &StreamOptions,
+    ) -> Result<(Vec<u8>, Stream)> {
+        self.prepare(cntl)?;
+        let server = self.lb.select(&self.servers);
+        let stream = self.pool.connect(server).await?;
+        let remote_addr = stream.peer_addr()?;
+        let (mut reader, writer) = tokio::io::split(stream);
+        let conn = Connection::new(remote_addr, writer);
+        let (stream, settings) = Stream::create(&conn, options);
+        cntl.set_request_stream(settings);
+        conn.write_frames(self.protocol.pack_request_frames(cntl, req)?)
+            .await?;
+
+        // frames of the stream may come ahead of the response
+        let mut buf = BytesMut::with_capacity(BUF_SIZE);
+        let mut chunks = Chunks::default();
+        let read = async {
+            loop {
+                match Streaming.parse(&mut buf) {
+                    Ok(frame) => {
+                        stream::on_frame(&conn, frame)?;
+                        continue;
+                    }
+                    Err(Error::Parse(ParseErr::TryOther)) => match self.protocol.parse(&mut buf) {
+                        Ok(msg) => match chunks.merge(msg)? {
+                            Some(msg) => return Ok(msg),
+                            None => continue,
+                        },
+                        Err(Error::Parse(ParseErr::NotEnoughData)) => {}
+                        Err(e) => return Err(e),
+                    },
+                    Err(Error::Parse(ParseErr::NotEnoughData)) => {}
+                    Err(e) => return Err(e),
+                }
+                if reader.read_buf(&mut buf).await? == 0 {
+                    return Err(ParseErr::UnexpectedEof.into());
+                }
+            }
+        };
+        let msg = match self.options.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, read)
+                .await
+                .map_err(|_| RpcErr::Timeout(timeout))??,
+            None => read.await?,
+        };
+        cntl.set_remote_addr(conn.remote_addr());
+        let resp = self.protocol.process_response(cntl, msg).await?;
+        let remote = cntl.take_response_stream().ok_or(StreamErr::NotAccepted)?;
+        stream.connect(remote);
+
+        // keep handing frames to the stream until either side closes it
+        let closed = stream.closed();
+        tokio::spawn(async move {
+            let read = async {
+                loop {
+                    let frame = protocol::read_message(&Streaming, &mut reader, &mut buf).await?;
+                    stream::on_frame(&conn, frame)?;
+                }
+            };
+            tokio::select! {
+                res = read => {
+                    let res: Result<()> = res;
+                    if let Err(e) = res {
+                        debug!("stream connection err:{e}");
+                    }
+                }
+                _ = closed => {}
+            }
+            stream::close_connection(conn.id());
+        });
+
+        Ok((resp, stream))
+    }
+
+    /// Send `req` without waiting for a response, returning once it is written.
+    ///
+    /// Meant for one-way methods, the server sends nothing back for them.
+    #[instrument(name = "channel", skip_all, fields(ns_url = %self.ns_url))]
+    pub async fn call_oneway(&self, cntl: &mut Controller, req: CommonMsg) -> Result<()> {
+        self.prepare(cntl)?;
+        let mesh = self.mesh();
+        let frames: Vec<_> = self.protocol.pack_request_frames(cntl, req)?.collect();
+        let idempotent = cntl.idempotent();
+
+        let timeout = mesh
+            .and_then(|mesh| mesh.options.timeout)
+            .or(self.options.timeout);
+        let send = async {
+            let mut retry = 0;
+            loop {
+                let sent = AtomicBool::new(false);
+                let server = self.lb.select(&self.servers);
+                let frames = self.protocol.address_request(&frames, server);
+                let res = match self.oneway_conn(mesh, server).await {
+                    Ok(conn) => self.write_oneway(conn, &frames, &sent).await,
+                    Err(e) => Err(e),
+                };
+                let sent = sent.load(Ordering::Relaxed);
+                match res {
+                    Err(e)
+                        if retry < self.options.max_retry && is_retryable(&e, sent, idempotent) =>
+                    {
+                        retry += 1;
+                        warn!("retry {retry} after err:{e}");
+                    }
+                    res => return res,
+                }
+            }
+        };
+        let remote_addr = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, send)
+                .await
+                .map_err(|_| RpcErr::Timeout(timeout))??,
+            None => send.await?,
+        };
+        cntl.set_remote_addr(remote_addr);
+
+        Ok(())
+    }
+
+    // through the sidecar in mesh mode, unless MESH_LITE falls back to `server`
+    async fn oneway_conn(&self, mesh: Option<&Mesh>, server: &str) -> Result<Conn> {
+        if let Some(mesh) = mesh {
+            if let Some(conn) = self.mesh_conn(mesh, &self.oneway_pool).await? {
+                return Ok(conn);
+            }
+        }
+        self.oneway_pool.get(server).await
+    }
+
+    async fn write_oneway(
+        &self,
+        mut conn: Conn,
+        frames: &[Vec<u8>],
+        sent: &AtomicBool,
+    ) -> Result<SocketAddr> {
+        let remote_addr = conn.stream.peer_addr()?;
+        for frame in frames {
+            conn.stream.write_all(frame).await?;
+        }
+        sent.store(true, Ordering::Relaxed);
+        conn.stream.flush().await?;
+        self.oneway_pool.put(conn);
+
+        Ok(remote_addr)
+    }
+
+    // fill in what the channel adds to every call
+    fn prepare(&self, cntl: &mut Controller) -> Result<()> {
+        if let (None, Some(size)) = (cntl.chunk_size(), self.options.chunk_size) {
+            cntl.set_chunk_size(size);
+        }
+        if let Some(credential) = &self.credential {
+            cntl.set_authentication_data(credential.generate()?);
+        }
+        Ok(())
+    }
+
+    fn mesh(&self) -> Option<&Mesh> {
+        self.mesh
+            .as_ref()
+            .filter(|mesh| mesh.options.state != MeshState::MESH_STATE_OFF)
+    }
+
+    // a connection from `pool` to a sidecar, none if it is unreachable and
+    // MESH_LITE lets calls go to the servers directly
+    async fn mesh_conn(&self, mesh: &Mesh, pool: &Pool) -> Result<Option<Conn>> {
+        let sidecar = mesh.select();
+        match pool.get(sidecar).await {
+            Ok(conn) => Ok(Some(conn)),
+            Err(e) if mesh.options.state == MeshState::MESH_STATE_MESH_LITE => {
+                warn!("sidecar {sidecar} unavailable, fall back to direct: {e}");
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn send_to_mesh(
+        &self,
+        mesh: &Mesh,
+        frames: &[Vec<u8>],
+        replies: usize,
+        idempotent: bool,
+    ) -> Result<(Vec<CommonMsg>, SocketAddr)> {
+        match self.mesh_conn(mesh, &self.pool).await? {
+            Some(conn) => {
+                // for the sidecar to forward to
+                let server = self.lb.select(&self.servers);
+                let frames = self.protocol.address_request(frames, server);
+                let sent = AtomicBool::new(false);
+                self.exchange(conn, &frames, replies, &sent).await
+            }
+            None => self.send(frames, replies, idempotent).await,
+        }
+    }
+
+    async fn send(
+        &self,
+        frames: &[Vec<u8>],
+        replies: usize,
+        idempotent: bool,
+    ) -> Result<(Vec<CommonMsg>, SocketAddr)> {
+        let mut retry = 0;
+        loop {
+            let sent = AtomicBool::new(false);
+            let res = match self.options.backup_request {
+                Some(backup_request) => {
+                    self.send_with_backup(frames, backup_request, replies, &sent)
+                        .await
+                }
+                None => self.send_once(frames, replies, &sent).await,
+            };
+            let sent = sent.load(Ordering::Relaxed);
+            match res {
+                Err(e) if retry < self.options.max_retry && is_retryable(&e, sent, idempotent) => {
+                    retry += 1;
+                    warn!("retry {retry} after err:{e}");
+                }
+                res => return res,
+            }
+        }
+    }
+
+    async fn send_with_backup(
+        &self,
+        frames: &[Vec<u8>],
+        backup_request: Duration,
+        replies: usize,
+        sent: &AtomicBool,
+    ) -> Result<(Vec<CommonMsg>, SocketAddr)> {
+        let first = self.send_once(frames, replies, sent);
+        tokio::pin!(first);
+        tokio::select! {
+            res = &mut first => return res,
+            _ = tokio::time::sleep(backup_request) => {}
+        }
+
+        debug!("send backup request after {backup_request:?}");
+        let backup = self.send_once(frames, replies, sent);
+        tokio::pin!(backup);
+        tokio::select! {
+            res = &mut first => match res {
+                Ok(res) => Ok(res),
+                Err(_) => backup.await,
+            },
+            res = &mut backup => match res {
+                Ok(res) => Ok(res),
+                Err(_) => first.await,
+            },
+        }
+    }
+
+    async fn send_once(
+        &self,
+        frames: &[Vec<u8>],
+        replies: usize,
+        sent: &AtomicBool,
+    ) -> Result<(Vec<CommonMsg>, SocketAddr)> {
+        let server = self.lb.select(&self.servers);
+        let frames = self.protocol.address_request(frames, server);
+        let conn = self.pool.get(server).await?;
+        self.exchange(conn, &frames, replies, sent).await
+    }
+
+    // read `replies` responses for pipelined requests, one message each,
+    // `sent` is set once the request may have reached the server, that is
+    // once all of it is written, a request cut short is never processed
+    async fn exchange(
+        &self,
+        mut conn: Conn,
+        frames: &[Vec<u8>],
+        replies: usize,
+        sent: &AtomicBool,
+    ) -> Result<(Vec<CommonMsg>, SocketAddr)> {
+        let remote_addr = conn.stream.peer_addr()?;
+        for frame in frames {
+            conn.stream.write_all(frame).await?;
+        }
+        sent.store(true, Ordering::Relaxed);
+        conn.stream.flush().await?;
+        let protocol = self.protocol.as_ref();
+        let request = frames.first().map_or(&[][..], |frame| frame.as_slice());
+        let mut buf = BytesMut::with_capacity(BUF_SIZE);
+        let mut msgs = Vec::with_capacity(replies);
+        for _ in 0..replies {
+            msgs.push(
+                protocol::read_response(protocol, &mut conn.stream, &mut buf, request).await?,
+            );
+        }
+        self.pool.put(conn);
+
+        Ok((msgs, remote_addr))
+    }
+}
+
+// whether a call failing with `err` may be sent again, which is not the case
+// once the request may have reached the server, as `sent` tells, unless calling
+// twice does no harm
+fn is_retryable(err: &Error, sent: bool, idempotent: bool) -> bool {
+    if let Error::H2(e) = err {
+        // the server tells it never processed the stream
+        if e.reason() == Some(h2::Reason::REFUSED_STREAM) {
+            return true;
+        }
+    }
+    if sent && !idempotent {
+        return false;
+    }
+    match err {
+        Error::Io(_) | Error::Parse(ParseErr::UnexpectedEof) => true,
+        Error::H2(e) => e.is_io() || e.is_go_away(),
+        _ => false,
+    }
+}
+
+fn millis(ms: Option<i32>) -> Option<Duration> {
+    ms.filter(|ms| *ms > 0)
+        .map(|ms| Duration::from_millis(ms as u64))
+}
+
+fn connection_type_from_name(name: &str) -> Result<ConnectionType> {
+    match name {
+        "" => Ok(ConnectionType::CONNECTION_TYPE_POOLED),
+        "single" => Ok(ConnectionType::CONNECTION_TYPE_SINGLE),
+        "pooled" => Ok(ConnectionType::CONNECTION_TYPE_POOLED),
+        "short" => Ok(ConnectionType::CONNECTION_TYPE_SHORT),
+        _ => Err(ConfErr::Invalid("connection_type_name", name.to_string()).into()),
+    }
+}
+
+pub(crate) fn mesh_state_from_name(name: &str) -> Result<MeshState> {
+    let name = name.to_ascii_lowercase();
+    match name.trim_start_matches("mesh_state_") {
+        "off" => Ok(MeshState::MESH_STATE_OFF),
+        "mesh" => Ok(MeshState::MESH_STATE_MESH),
+        "mesh_lite" => Ok(MeshState::MESH_STATE_MESH_LITE),
+        _ => Err(ConfErr::Invalid("mesh_state", name).into()),
+    }
+}
+
+// `list://ip:port,ip:port` or a single `ip:port`
+fn servers_from_ns_url(ns_url: &str) -> Result<Vec<String>> {
+    let servers: Vec<String> = match ns_url.split_once("://") {
+        Some(("list", list)) => list
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect(),
+        Some(_) => vec![],
+        None => vec![ns_url.trim().to_string()],
+    };
+    if servers.is_empty() || servers.iter().any(|s| s.is_empty()) {
+        return Err(ConfErr::Invalid("ns_url", ns_url.to_string()).into());
+    }
+
+    Ok(servers)
+}
@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use h2::client::SendRequest;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+use tokio_rustls::client::TlsStream;
+use tracing::debug;
+
+use server_kit_protocol::options::ConnectionType;
+
+use crate::tls::ClientTls;
+use crate::Result;
+
+/// Idle connections kept per server, those given back beyond it are closed.
+const MAX_IDLE: usize = 100;
+/// Idle connections older than this are closed rather than reused, as servers
+/// may have dropped them in the meantime.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A connection to a server, over TLS if the channel is set up for it.
+pub(crate) enum ConnStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl ConnStream {
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            ConnStream::Plain(stream) => stream.peer_addr(),
+            ConnStream::Tls(stream) => stream.get_ref().0.peer_addr(),
+        }
+    }
+
+    // whether an idle connection may still be used: not closed by the server
+    // and, in plaintext, with no stray bytes which would be taken for a response
+    fn is_reusable(&self) -> bool {
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut buf = [0; 1];
+        let mut buf = ReadBuf::new(&mut buf);
+        match self {
+            ConnStream::Plain(stream) => stream.poll_peek(&mut cx, &mut buf).is_pending(),
+            // records such as session tickets may come unasked over TLS
+            ConnStream::Tls(stream) => !matches!(
+                stream.get_ref().0.poll_peek(&mut cx, &mut buf),
+                Poll::Ready(Ok(0) | Err(_))
+            ),
+        }
+    }
+}
+
+impl AsyncRead for ConnStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ConnStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            ConnStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ConnStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ConnStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            ConnStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ConnStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            ConnStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ConnStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            ConnStream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+pub(crate) struct Conn {
+    addr: String,
+    pub stream: ConnStream,
+    // the shared slot of a single connection, locked while the call is in flight
+    slot: Option<OwnedMutexGuard<Option<ConnStream>>>,
+}
+
+/// Hands out connections according to the channel's `ConnectionType`.
+///
+/// A connection is only given back by `put` after a complete exchange, so one
+/// dropped halfway (error, timeout, lost backup request) is never reused.
+/// Pooled connections are checked on the way out, at most `MAX_IDLE` of them
+/// wait for reuse per server, for no longer than `IDLE_TIMEOUT`.
+pub(crate) struct Pool {
+    conn_type: ConnectionType,
+    connect_timeout: Option<Duration>,
+    tls: Option<ClientTls>,
+    // idle connections with when they were given back, the latest last
+    idle: Mutex<HashMap<String, Vec<(ConnStream, Instant)>>>,
+    // one multiplexed connection per server
+    h2: Mutex<HashMap<String, (SendRequest<Bytes>, SocketAddr)>>,
+    single: Mutex<HashMap<String, Arc<AsyncMutex<Option<ConnStream>>>>>,
+}
+
+impl Pool {
+    pub fn new(
+        conn_type: ConnectionType,
+        connect_timeout: Option<Duration>,
+        tls: Option<ClientTls>,
+    ) -> Self {
+        Self {
+            conn_type,
+            connect_timeout,
+            tls,
+            idle: Default::default(),
+            h2: Default::default(),
+            single: Default::default(),
+        }
+    }
+
+    pub async fn get(&self, addr: &str) -> Result<Conn> {
+        match self.conn_type {
+            ConnectionType::CONNECTION_TYPE_SINGLE => {
+                let slot = {
+                    let mut single = self.single.lock().unwrap();
+                    Arc::clone(single.entry(addr.to_string()).or_default())
+                };
+                let mut slot = slot.lock_owned().await;
+                let stream = match slot.take() {
+                    Some(stream) => stream,
+                    None => self.connect(addr).await?,
+                };
+                Ok(Conn {
+                    addr: addr.to_string(),
+                    stream,
+                    slot: Some(slot),
+                })
+            }
+            ConnectionType::CONNECTION_TYPE_SHORT => Ok(Conn {
+                addr: addr.to_string(),
+                stream: self.connect(addr).await?,
+                slot: None,
+            }),
+            _ => {
+                let stream = match self.idle(addr) {
+                    Some(stream) => stream,
+                    None => self.connect(addr).await?,
+                };
+                Ok(Conn {
+                    addr: addr.to_string(),
+                    stream,
+                    slot: None,
+                })
+            }
+        }
+    }
+
+    pub fn put(&self, conn: Conn) {
+        match conn.slot {
+            Some(mut slot) => *slot = Some(conn.stream),
+            None if self.conn_type == ConnectionType::CONNECTION_TYPE_SHORT => {}
+            None => {
+                let mut idle = self.idle.lock().unwrap();
+                let conns = idle.entry(conn.addr).or_default();
+                if conns.len() < MAX_IDLE {
+                    conns.push((conn.stream, Instant::now()));
+                }
+            }
+        }
+    }
+
+    // the latest idle connection to `addr` still usable, dropping the others
+    // on the way which are either closed or too old
+    fn idle(&self, addr: &str) -> Option<ConnStream> {
+        let mut idle = self.idle.lock().unwrap();
+        let conns = idle.get_mut(addr)?;
+        conns.retain(|(_, since)| since.elapsed() < IDLE_TIMEOUT);
+        while let Some((stream, _)) = conns.pop() {
+            if stream.is_reusable() {
+                return Some(stream);
+            }
+            debug!("drop idle connection to {addr} closed by the server");
+        }
+        None
+    }
+
+    /// The HTTP/2 connection to `addr` and the address of its peer.
+    pub async fn h2(&self, addr: &str) -> Result<(SendRequest<Bytes>, SocketAddr)> {
+        if let Some(conn) = self.h2.lock().unwrap().get(addr) {
+            return Ok(conn.clone());
+        }
+        let stream = self.connect(addr).await?;
+        let remote_addr = stream.peer_addr()?;
+        let (sender, connection) = h2::client::handshake(stream).await?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                debug!("h2 connection to {remote_addr} err:{e}");
+            }
+        });
+        let conn = (sender, remote_addr);
+        self.h2
+            .lock()
+            .unwrap()
+            .insert(addr.to_string(), conn.clone());
+        Ok(conn)
+    }
+
+    /// Drop the HTTP/2 connection to `addr` which failed, to connect anew.
+    pub fn remove_h2(&self, addr: &str) {
+        self.h2.lock().unwrap().remove(addr);
+    }
+
+    pub async fn connect(&self, addr: &str) -> Result<ConnStream> {
+        let connect = TcpStream::connect(addr);
+        let stream = match self.connect_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, connect).await.map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("connect to {addr} timeout"),
+                )
+            })??,
+            None => connect.await?,
+        };
+        // small frames of streams would otherwise wait on delayed acks
+        stream.set_nodelay(true)?;
+        match &self.tls {
+            Some(tls) => {
+                let server_name = tls.server_name.clone();
+                let stream = tls.connector.connect(server_name, stream).await?;
+                Ok(ConnStream::Tls(Box::new(stream)))
+            }
+            None => Ok(ConnStream::Plain(stream)),
+        }
+    }
+}
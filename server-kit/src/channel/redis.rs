@@ -0,0 +1,38 @@
+use super::Channel;
+use crate::protocol::{Redis, RedisReply, RedisRequest};
+use crate::{Controller, Result};
+
+impl Channel<Redis> {
+    /// Send the commands of `req` pipelined on one connection, with the
+    /// timeouts, retries and load balancing of the channel.
+    ///
+    /// Replies are in the order of the commands.
+    pub async fn pipeline(
+        &self,
+        cntl: &mut Controller,
+        req: RedisRequest,
+    ) -> Result<Vec<RedisReply>> {
+        if req.is_empty() {
+            return Ok(vec![]);
+        }
+        let count = req.len();
+        let replies = self.call_pipelined(cntl, req.into(), count).await?;
+
+        replies
+            .iter()
+            .map(|reply| RedisReply::parse(reply))
+            .collect()
+    }
+
+    /// Send one command given as its name and arguments, as in `["GET", "key"]`.
+    pub async fn command<I, A>(&self, cntl: &mut Controller, args: I) -> Result<RedisReply>
+    where
+        I: IntoIterator<Item = A>,
+        A: AsRef<[u8]>,
+    {
+        let mut replies = self
+            .pipeline(cntl, RedisRequest::new().command(args))
+            .await?;
+        Ok(replies.remove(0))
+    }
+}
@@ -0,0 +1,24 @@
+use super::Channel;
+use crate::message::CommonMsg;
+use crate::protocol::Thrift;
+use crate::{Controller, Result};
+
+impl Channel<Thrift> {
+    /// Call `method_name` with its args struct encoded by TBinaryProtocol,
+    /// with the timeouts, retries and load balancing of the channel, and a
+    /// seqid of its own the reply is checked against.
+    ///
+    /// Returns the result struct, or fails with `RpcErr::Failed` of the type
+    /// and message of a `TApplicationException`.
+    pub async fn call_method(
+        &self,
+        cntl: &mut Controller,
+        method_name: &str,
+        args: Vec<u8>,
+    ) -> Result<Vec<u8>> {
+        cntl.set_thrift_seq_id(self.protocol.next_seq_id());
+        let mut msg = CommonMsg::new(args);
+        msg.with_meta(method_name.as_bytes().to_vec());
+        self.call(cntl, msg).await
+    }
+}
@@ -0,0 +1,157 @@
+use std::io::{Read, Write};
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use protobuf::Enum;
+
+use server_kit_protocol::options::CompressType;
+
+use crate::error::CompressErr;
+use crate::message::MAX_MESSAGE_SIZE;
+use crate::Result;
+
+pub fn compress_type_from_i32(value: i32) -> Result<CompressType> {
+    CompressType::from_i32(value).ok_or_else(|| CompressErr::Unknown(value).into())
+}
+
+/// Snappy in raw format as brpc does, lz4 in block format with the
+/// uncompressed size prepended as 4 bytes little endian.
+///
+/// brpc has no lz4 codec of its own behind `COMPRESS_TYPE_LZ4`, so that
+/// framing is server-kit's and only understood by server-kit peers.
+pub fn compress(ty: CompressType, data: &[u8]) -> Result<Vec<u8>> {
+    let data = match ty {
+        CompressType::COMPRESS_TYPE_NONE => data.to_vec(),
+        CompressType::COMPRESS_TYPE_SNAPPY => snap::raw::Encoder::new()
+            .compress_vec(data)
+            .map_err(CompressErr::from)?,
+        CompressType::COMPRESS_TYPE_GZIP => {
+            let mut encoder = GzEncoder::new(vec![], Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()?
+        }
+        CompressType::COMPRESS_TYPE_ZLIB => {
+            let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()?
+        }
+        CompressType::COMPRESS_TYPE_LZ4 => lz4_flex::compress_prepend_size(data),
+    };
+
+    Ok(data)
+}
+
+/// `data` decompressed, failing rather than inflating it beyond `MAX_MESSAGE_SIZE`.
+pub fn decompress(ty: CompressType, data: &[u8]) -> Result<Vec<u8>> {
+    decompress_within(ty, data, MAX_MESSAGE_SIZE)
+}
+
+fn decompress_within(ty: CompressType, data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    let data = match ty {
+        CompressType::COMPRESS_TYPE_NONE => data.to_vec(),
+        CompressType::COMPRESS_TYPE_SNAPPY => {
+            if snap::raw::decompress_len(data).map_err(CompressErr::from)? > limit {
+                return Err(CompressErr::TooLarge(limit).into());
+            }
+            snap::raw::Decoder::new()
+                .decompress_vec(data)
+                .map_err(CompressErr::from)?
+        }
+        CompressType::COMPRESS_TYPE_GZIP => read_within(GzDecoder::new(data), limit)?,
+        CompressType::COMPRESS_TYPE_ZLIB => read_within(ZlibDecoder::new(data), limit)?,
+        CompressType::COMPRESS_TYPE_LZ4 => {
+            // the size is checked before lz4_flex allocates as much
+            let size = data
+                .get(..4)
+                .map(|size| u32::from_le_bytes(size.try_into().unwrap()) as usize);
+            if size.is_some_and(|size| size > limit) {
+                return Err(CompressErr::TooLarge(limit).into());
+            }
+            let data = lz4_flex::decompress_size_prepended(data).map_err(CompressErr::from)?;
+            // a block cut short may still decompress, to less than told
+            match size {
+                Some(size) if size != data.len() => {
+                    return Err(CompressErr::Lz4Truncated(size, data.len()).into());
+                }
+                _ => data,
+            }
+        }
+    };
+
+    Ok(data)
+}
+
+fn read_within(decoder: impl Read, limit: usize) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    decoder.take(limit as u64 + 1).read_to_end(&mut buf)?;
+    if buf.len() > limit {
+        return Err(CompressErr::TooLarge(limit).into());
+    }
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TYPES: [CompressType; 5] = [
+        CompressType::COMPRESS_TYPE_NONE,
+        CompressType::COMPRESS_TYPE_SNAPPY,
+        CompressType::COMPRESS_TYPE_GZIP,
+        CompressType::COMPRESS_TYPE_ZLIB,
+        CompressType::COMPRESS_TYPE_LZ4,
+    ];
+
+    #[test]
+    fn round_trip() {
+        let data = b"hello hello hello hello".repeat(100);
+        for ty in TYPES {
+            let compressed = compress(ty, &data).unwrap();
+            if ty != CompressType::COMPRESS_TYPE_NONE {
+                assert!(compressed.len() < data.len(), "{ty:?}");
+            }
+            assert_eq!(decompress(ty, &compressed).unwrap(), data, "{ty:?}");
+            assert!(decompress(ty, &compress(ty, b"").unwrap())
+                .unwrap()
+                .is_empty());
+        }
+    }
+
+    #[test]
+    fn reject_corrupt() {
+        for ty in &TYPES[1..] {
+            let mut data = compress(*ty, &b"hello".repeat(100)).unwrap();
+            data.truncate(data.len() / 2);
+            assert!(decompress(*ty, &data).is_err(), "{ty:?}");
+            assert!(
+                decompress(*ty, b"\x05\x00\x00\x00garbage").is_err(),
+                "{ty:?}"
+            );
+        }
+        assert!(matches!(
+            compress_type_from_i32(99),
+            Err(crate::Error::Compress(CompressErr::Unknown(99)))
+        ));
+    }
+
+    #[test]
+    fn reject_oversize() {
+        let data = vec![0; 4096];
+        for ty in &TYPES[1..] {
+            let compressed = compress(*ty, &data).unwrap();
+            assert_eq!(decompress_within(*ty, &compressed, 4096).unwrap(), data);
+            let err = decompress_within(*ty, &compressed, 4095).unwrap_err();
+            assert!(
+                matches!(err, crate::Error::Compress(CompressErr::TooLarge(4095))),
+                "{ty:?}: {err}"
+            );
+        }
+
+        // told by the prepended size, before anything is allocated
+        let mut lz4 = u32::MAX.to_le_bytes().to_vec();
+        lz4.extend_from_slice(b"tiny");
+        let err = decompress(CompressType::COMPRESS_TYPE_LZ4, &lz4).unwrap_err();
+        assert!(err.to_string().contains("larger than"), "{err}");
+    }
+}
@@ -1,15 +1,95 @@
+use std::collections::HashMap;
 use std::path::Path;
 
 use serde::de::DeserializeOwned;
 use serde_derive::Deserialize;
 use tokio::{fs::File, io::AsyncReadExt};
 
+use server_kit_protocol::options::ChannelAttribute;
+
+use crate::channel::mesh_state_from_name;
 use crate::Result;
 
 #[derive(Deserialize)]
 pub struct Conf {
     pub ip: String,
     pub port: u32,
+    /// Names of the protocols to serve, all enabled by default if empty.
+    #[serde(default)]
+    pub protocols: Vec<String>,
+    pub tls: Option<TlsConf>,
+}
+
+/// TLS of the server, certificates and keys are PEM files.
+#[derive(Deserialize, Debug, Clone)]
+pub struct TlsConf {
+    pub cert: String,
+    pub key: String,
+    /// Require clients to present a certificate issued by this CA.
+    pub client_ca: Option<String>,
+    /// Also serve plaintext on the port, told apart from TLS by the ClientHello.
+    #[serde(default)]
+    pub allow_plaintext: bool,
+}
+
+#[derive(Deserialize, Default)]
+pub struct ClientConf {
+    #[serde(default)]
+    pub channels: HashMap<String, ChannelConf>,
+}
+
+/// TOML mirror of `ChannelAttribute`, `mesh_state` is given by name such as "mesh_lite",
+/// plus the options `ChannelAttribute` has no field for.
+#[derive(Deserialize, Default, Debug, Clone)]
+pub struct ChannelConf {
+    pub connect_timeout_ms: Option<i32>,
+    pub timeout_ms: Option<i32>,
+    pub backup_request_ms: Option<i32>,
+    pub max_retry: Option<i32>,
+    pub protocol_name: Option<String>,
+    pub connection_type_name: Option<String>,
+    pub lb_name: Option<String>,
+    pub ns_url: Option<String>,
+    pub mesh_state: Option<String>,
+    pub mesh_timeout_ms: Option<i32>,
+    pub mesh_lb_name: Option<String>,
+    /// Sidecars in mesh mode, `DEFAULT_MESH_ADDR` of the channel module if not set.
+    pub mesh_addr: Option<String>,
+    pub chunk_size: Option<usize>,
+    pub tls: Option<ChannelTlsConf>,
+}
+
+/// TLS of a channel, certificates and keys are PEM files.
+#[derive(Deserialize, Default, Debug, Clone)]
+pub struct ChannelTlsConf {
+    /// CA to verify the server certificate by.
+    pub ca: String,
+    /// Name the server certificate has to be valid for.
+    pub server_name: String,
+    /// Client certificate and key, for servers requiring one.
+    pub cert: Option<String>,
+    pub key: Option<String>,
+}
+
+impl ChannelConf {
+    pub fn to_attribute(&self) -> Result<ChannelAttribute> {
+        let mut attr = ChannelAttribute::new();
+        attr.connect_timeout_ms = self.connect_timeout_ms;
+        attr.timeout_ms = self.timeout_ms;
+        attr.backup_request_ms = self.backup_request_ms;
+        attr.max_retry = self.max_retry;
+        attr.protocol_name = self.protocol_name.clone();
+        attr.connection_type_name = self.connection_type_name.clone();
+        attr.lb_name = self.lb_name.clone();
+        attr.ns_url = self.ns_url.clone();
+        if let Some(mesh_state) = &self.mesh_state {
+            attr.set_mesh_state(mesh_state_from_name(mesh_state)?);
+        }
+        attr.mesh_timeout_ms = self.mesh_timeout_ms;
+        attr.mesh_lb_name = self.mesh_lb_name.clone();
+
+        Ok(attr)
+    }
 }
 
 pub async fn read_conf<T>(path: impl AsRef<Path>) -> Result<T>
@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use server_kit_protocol::options::CompressType;
+use server_kit_protocol::streaming_rpc_meta::StreamSettings;
+
+use crate::error::StreamErr;
+use crate::protocol::NsheadHeader;
+use crate::socket::Connection;
+use crate::stream::{Stream, StreamOptions};
+use crate::tls::PeerCertificate;
+use crate::{Metadata, Result};
+
+/// Per-request state handed to a `Service` on the server side.
+#[derive(Default, Debug)]
+pub struct Context {
+    request_meta: Metadata,
+    response_meta: Metadata,
+    request_compress_type: CompressType,
+    response_compress_type: Option<CompressType>,
+    request_attachment: Vec<u8>,
+    response_attachment: Vec<u8>,
+    // the stream asked for by the request and the settings of the accepted one
+    remote_stream: Option<(Connection, StreamSettings)>,
+    accepted_stream: Option<StreamSettings>,
+    identity: Option<String>,
+    peer_certificate: Option<Arc<PeerCertificate>>,
+    // of http requests
+    unresolved_path: String,
+    query_params: HashMap<String, String>,
+    // of nshead requests
+    nshead_header: Option<NsheadHeader>,
+    nshead_response_header: Option<NsheadHeader>,
+}
+
+impl Context {
+    pub fn new(request_meta: Metadata) -> Self {
+        Self {
+            request_meta,
+            ..Default::default()
+        }
+    }
+
+    pub fn request_metadata(&self) -> &Metadata {
+        &self.request_meta
+    }
+
+    pub fn response_metadata(&self) -> &Metadata {
+        &self.response_meta
+    }
+
+    pub fn response_metadata_mut(&mut self) -> &mut Metadata {
+        &mut self.response_meta
+    }
+
+    pub fn request_compress_type(&self) -> CompressType {
+        self.request_compress_type
+    }
+
+    pub fn set_request_compress_type(&mut self, ty: CompressType) {
+        self.request_compress_type = ty;
+    }
+
+    pub fn response_compress_type(&self) -> Option<CompressType> {
+        self.response_compress_type
+    }
+
+    /// Compress the response by `ty` rather than the method's default or the
+    /// compression of the request.
+    pub fn set_response_compress_type(&mut self, ty: CompressType) {
+        self.response_compress_type = Some(ty);
+    }
+
+    /// Raw bytes sent after the request payload, bypassing serialization and compression.
+    pub fn request_attachment(&self) -> &[u8] {
+        &self.request_attachment
+    }
+
+    pub fn set_request_attachment(&mut self, attachment: Vec<u8>) {
+        self.request_attachment = attachment;
+    }
+
+    pub fn response_attachment(&self) -> &[u8] {
+        &self.response_attachment
+    }
+
+    pub fn set_response_attachment(&mut self, attachment: Vec<u8>) {
+        self.response_attachment = attachment;
+    }
+
+    pub(crate) fn take_response_attachment(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.response_attachment)
+    }
+
+    /// Accept the stream requested along with this request, the peer learns of
+    /// it from the response.
+    pub fn accept_stream(&mut self, options: &StreamOptions) -> Result<Stream> {
+        let (conn, remote) = self.remote_stream.take().ok_or(StreamErr::NotRequested)?;
+        let (stream, settings) = Stream::accept(&conn, remote, options);
+        self.accepted_stream = Some(settings);
+        Ok(stream)
+    }
+
+    pub(crate) fn set_remote_stream(&mut self, conn: Connection, settings: StreamSettings) {
+        self.remote_stream = Some((conn, settings));
+    }
+
+    pub(crate) fn take_accepted_stream(&mut self) -> Option<StreamSettings> {
+        self.accepted_stream.take()
+    }
+
+    /// Who sent the request, as verified by the server's authenticator.
+    pub fn identity(&self) -> Option<&str> {
+        self.identity.as_deref()
+    }
+
+    pub(crate) fn set_identity(&mut self, identity: Option<String>) {
+        self.identity = identity;
+    }
+
+    /// The certificate the client presented over TLS.
+    pub fn peer_certificate(&self) -> Option<&PeerCertificate> {
+        self.peer_certificate.as_deref()
+    }
+
+    pub(crate) fn set_peer_certificate(&mut self, cert: Option<Arc<PeerCertificate>>) {
+        self.peer_certificate = cert;
+    }
+
+    /// The part of the url path matched by the wildcard of a restful mapping.
+    pub fn unresolved_path(&self) -> &str {
+        &self.unresolved_path
+    }
+
+    pub(crate) fn set_unresolved_path(&mut self, path: String) {
+        self.unresolved_path = path;
+    }
+
+    /// A query parameter of the url of an http request, decoded.
+    pub fn query_param(&self, key: &str) -> Option<&str> {
+        self.query_params.get(key).map(String::as_str)
+    }
+
+    pub fn query_params(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.query_params
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub(crate) fn set_query_params(&mut self, params: HashMap<String, String>) {
+        self.query_params = params;
+    }
+
+    /// The nshead header of the request.
+    pub fn nshead_header(&self) -> Option<&NsheadHeader> {
+        self.nshead_header.as_ref()
+    }
+
+    // the response echoes the header of the request unless the handler changes it
+    pub(crate) fn set_nshead_header(&mut self, head: NsheadHeader) {
+        self.nshead_header = Some(head);
+        self.nshead_response_header = Some(head);
+    }
+
+    /// The nshead header of the response, which echoes the one of the request
+    /// unless changed here.
+    pub fn nshead_response_header(&self) -> Option<&NsheadHeader> {
+        self.nshead_response_header.as_ref()
+    }
+
+    pub fn nshead_response_header_mut(&mut self) -> Option<&mut NsheadHeader> {
+        self.nshead_response_header.as_mut()
+    }
+}
@@ -0,0 +1,193 @@
+use std::net::SocketAddr;
+
+use server_kit_protocol::options::CompressType;
+use server_kit_protocol::streaming_rpc_meta::StreamSettings;
+
+use crate::protocol::NsheadHeader;
+use crate::Metadata;
+
+/// Per-call options and results on the channel side.
+#[derive(Default, Debug)]
+pub struct Controller {
+    request_meta: Metadata,
+    response_meta: Metadata,
+    remote_addr: Option<SocketAddr>,
+    real_remote_addr: Option<SocketAddr>,
+    request_compress_type: CompressType,
+    response_compress_type: CompressType,
+    request_attachment: Vec<u8>,
+    response_attachment: Vec<u8>,
+    request_stream: Option<StreamSettings>,
+    response_stream: Option<StreamSettings>,
+    chunk_size: Option<usize>,
+    authentication_data: Vec<u8>,
+    http_status: Option<u16>,
+    idempotent: bool,
+    thrift_seq_id: i32,
+    nshead_log_id: u32,
+    nshead_provider: String,
+    nshead_response_header: Option<NsheadHeader>,
+}
+
+impl Controller {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn request_metadata(&self) -> &Metadata {
+        &self.request_meta
+    }
+
+    pub fn request_metadata_mut(&mut self) -> &mut Metadata {
+        &mut self.request_meta
+    }
+
+    pub fn response_metadata(&self) -> &Metadata {
+        &self.response_meta
+    }
+
+    pub fn set_response_metadata(&mut self, meta: Metadata) {
+        self.response_meta = meta;
+    }
+
+    /// The peer this call was actually sent to, the sidecar in mesh mode.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    pub fn set_remote_addr(&mut self, addr: SocketAddr) {
+        self.remote_addr = Some(addr);
+    }
+
+    /// The backend which served this call as reported by the mesh sidecar.
+    pub fn real_remote_addr(&self) -> Option<SocketAddr> {
+        self.real_remote_addr
+    }
+
+    pub fn set_real_remote_addr(&mut self, addr: SocketAddr) {
+        self.real_remote_addr = Some(addr);
+    }
+
+    pub fn request_compress_type(&self) -> CompressType {
+        self.request_compress_type
+    }
+
+    pub fn set_request_compress_type(&mut self, ty: CompressType) {
+        self.request_compress_type = ty;
+    }
+
+    pub fn response_compress_type(&self) -> CompressType {
+        self.response_compress_type
+    }
+
+    pub fn set_response_compress_type(&mut self, ty: CompressType) {
+        self.response_compress_type = ty;
+    }
+
+    /// Raw bytes sent after the request payload, bypassing serialization and compression.
+    pub fn request_attachment(&self) -> &[u8] {
+        &self.request_attachment
+    }
+
+    pub fn set_request_attachment(&mut self, attachment: Vec<u8>) {
+        self.request_attachment = attachment;
+    }
+
+    pub fn response_attachment(&self) -> &[u8] {
+        &self.response_attachment
+    }
+
+    pub fn set_response_attachment(&mut self, attachment: Vec<u8>) {
+        self.response_attachment = attachment;
+    }
+
+    /// Send the request in chunks of at most `size` bytes if it is larger,
+    /// overrides `ChannelOptions::chunk_size`.
+    pub fn set_chunk_size(&mut self, size: usize) {
+        self.chunk_size = Some(size);
+    }
+
+    pub fn chunk_size(&self) -> Option<usize> {
+        self.chunk_size
+    }
+
+    pub(crate) fn request_stream(&self) -> Option<&StreamSettings> {
+        self.request_stream.as_ref()
+    }
+
+    pub(crate) fn set_request_stream(&mut self, settings: StreamSettings) {
+        self.request_stream = Some(settings);
+    }
+
+    pub(crate) fn set_response_stream(&mut self, settings: StreamSettings) {
+        self.response_stream = Some(settings);
+    }
+
+    pub(crate) fn take_response_stream(&mut self) -> Option<StreamSettings> {
+        self.response_stream.take()
+    }
+
+    pub fn authentication_data(&self) -> &[u8] {
+        &self.authentication_data
+    }
+
+    /// Credential sent with the request, filled in by the channel's generator if it has one.
+    pub fn set_authentication_data(&mut self, data: Vec<u8>) {
+        self.authentication_data = data;
+    }
+
+    /// Status code of the response to an http request.
+    pub fn http_status(&self) -> Option<u16> {
+        self.http_status
+    }
+
+    pub fn set_http_status(&mut self, status: u16) {
+        self.http_status = Some(status);
+    }
+
+    /// Whether the call may be retried even if the request may have reached
+    /// the server, which is only safe if calling twice does no harm.
+    pub fn idempotent(&self) -> bool {
+        self.idempotent
+    }
+
+    pub fn set_idempotent(&mut self, idempotent: bool) {
+        self.idempotent = idempotent;
+    }
+
+    /// Seqid of the thrift call, to match the response against.
+    pub(crate) fn thrift_seq_id(&self) -> i32 {
+        self.thrift_seq_id
+    }
+
+    pub(crate) fn set_thrift_seq_id(&mut self, seq_id: i32) {
+        self.thrift_seq_id = seq_id;
+    }
+
+    /// log_id of the nshead header of the request.
+    pub fn nshead_log_id(&self) -> u32 {
+        self.nshead_log_id
+    }
+
+    pub fn set_nshead_log_id(&mut self, log_id: u32) {
+        self.nshead_log_id = log_id;
+    }
+
+    /// provider of the nshead header of the request, cut to 16 bytes.
+    pub fn nshead_provider(&self) -> &str {
+        &self.nshead_provider
+    }
+
+    pub fn set_nshead_provider(&mut self, provider: impl Into<String>) {
+        self.nshead_provider = provider.into();
+    }
+
+    /// The nshead header of the response.
+    pub fn nshead_response_header(&self) -> Option<&NsheadHeader> {
+        self.nshead_response_header.as_ref()
+    }
+
+    pub(crate) fn set_nshead_response_header(&mut self, head: NsheadHeader) {
+        self.nshead_response_header = Some(head);
+    }
+}
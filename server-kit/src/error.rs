@@ -1,5 +1,18 @@
+use std::time::Duration;
+
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Error code of brpc responses to requests of a service not served.
+pub const ENOSERVICE: i32 = 1001;
+/// Error code of brpc responses to requests of a method not in the service.
+pub const ENOMETHOD: i32 = 1002;
+/// Error code of brpc responses to requests that can't be made sense of.
+pub const EREQUEST: i32 = 1003;
+/// Error code of brpc responses to requests failing authentication.
+pub const ERPCAUTH: i32 = 1004;
+/// Error code of brpc responses to requests the method failed to handle.
+pub const EINTERNAL: i32 = 2001;
+
 /// An error type that combines all possible errors by this library.
 #[derive(thiserror::Error, Debug)]
 #[error("{0}")]
@@ -7,7 +20,20 @@ pub enum Error {
     StrErr(String),
     Parse(#[from] ParseErr),
     Svc(#[from] SvcErr),
+    Rpc(#[from] RpcErr),
+    Conf(#[from] ConfErr),
+    Protocol(#[from] ProtocolErr),
+    Compress(#[from] CompressErr),
+    Stream(#[from] StreamErr),
+    Auth(#[from] AuthErr),
+    Http(#[from] HttpErr),
+    Redis(#[from] RedisErr),
+    Memcache(#[from] MemcacheErr),
+    Thrift(#[from] ThriftErr),
+    Mcpack(#[from] McpackErr),
     PbErr(#[from] protobuf::Error),
+    Tls(#[from] tokio_rustls::rustls::Error),
+    H2(#[from] h2::Error),
     /// Io error from tcp
     Io(#[from] std::io::Error),
     Toml(#[from] toml::de::Error),
@@ -18,8 +44,22 @@ pub enum Error {
 pub enum ParseErr {
     #[error("try other protocol")]
     TryOther,
+    #[error("not enough data")]
+    NotEnoughData,
     #[error("unexpected eof")]
     UnexpectedEof,
+    #[error("invalid attachment_size {0}")]
+    InvalidAttachmentSize(i32),
+    #[error("invalid user_message_size {0}")]
+    InvalidUserMessageSize(i32),
+    #[error("invalid frame size {0}")]
+    InvalidFrameSize(i64),
+    #[error("unexpected chunk {1} of message {0}")]
+    UnexpectedChunk(i64, i64),
+    #[error("messages in chunks of more than {0} bytes in all")]
+    ChunksTooLarge(usize),
+    #[error("more than {0} messages in chunks at once")]
+    TooManyChunkedMessages(usize),
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -29,3 +69,123 @@ pub enum SvcErr {
     #[error("service {0} not exist")]
     NotExist(String),
 }
+
+#[derive(thiserror::Error, Debug)]
+pub enum RpcErr {
+    #[error("rpc timeout after {0:?}")]
+    Timeout(Duration),
+    #[error("rpc failed with error {0}: {1}")]
+    Failed(i32, String),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfErr {
+    #[error("invalid {0}: {1}")]
+    Invalid(&'static str, String),
+    #[error("channel {0} not exist")]
+    ChannelNotExist(String),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ProtocolErr {
+    #[error("protocol {0} exist")]
+    Exist(String),
+    #[error("protocol {0} not exist")]
+    NotExist(String),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum CompressErr {
+    #[error("unknown compress type {0}")]
+    Unknown(i32),
+    #[error("snappy: {0}")]
+    Snappy(#[from] snap::Error),
+    #[error("lz4: {0}")]
+    Lz4(#[from] lz4_flex::block::DecompressError),
+    #[error("lz4: {1} bytes decompressed instead of {0}")]
+    Lz4Truncated(usize, usize),
+    #[error("decompressed data larger than {0} bytes")]
+    TooLarge(usize),
+    #[error("unknown encoding {0}")]
+    UnknownEncoding(String),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum StreamErr {
+    #[error("no stream in request")]
+    NotRequested,
+    #[error("stream not accepted by server")]
+    NotAccepted,
+    #[error("stream {0} closed")]
+    Closed(i64),
+    #[error("stream {0} reset by peer")]
+    Reset(i64),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthErr {
+    #[error("authentication of {0} rejected")]
+    Rejected(String),
+    #[error("{0} can't authenticate requests")]
+    Unsupported(&'static str),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum HttpErr {
+    #[error("invalid http message: {0}")]
+    Invalid(#[from] httparse::Error),
+    #[error("invalid body: {0}")]
+    InvalidBody(String),
+    #[error("invalid request line {0:?}")]
+    InvalidRequestLine(String),
+    #[error("invalid header {0:?}")]
+    InvalidHeader(String),
+    #[error("body of more than {0} bytes")]
+    BodyTooLarge(usize),
+    #[error("method {0} not allowed")]
+    MethodNotAllowed(String),
+    #[error("no message type of {0} to transcode json")]
+    NoMessageType(String),
+    #[error("json: {0}")]
+    JsonParse(#[from] protobuf_json_mapping::ParseError),
+    #[error("json: {0}")]
+    JsonPrint(#[from] protobuf_json_mapping::PrintError),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum RedisErr {
+    #[error("invalid resp: {0}")]
+    Invalid(String),
+}
+
+#[derive(thiserror::Error, Debug, Clone)]
+pub enum MemcacheErr {
+    #[error("invalid memcache response: {0}")]
+    Invalid(String),
+    #[error("expect response {0}, got {1}")]
+    UnexpectedOpaque(usize, u32),
+    #[error("key of {0} bytes, longer than 250")]
+    KeyTooLong(usize),
+    #[error("operation of {0} bytes, too large to send")]
+    BodyTooLarge(usize),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ThriftErr {
+    #[error("invalid thrift message: {0}")]
+    Invalid(String),
+    #[error("thrift frame of {0} too large")]
+    FrameTooLarge(usize),
+    #[error("expect seqid {0}, got {1}")]
+    UnexpectedSeqId(i32, i32),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum McpackErr {
+    #[error("invalid mcpack: {0}")]
+    Invalid(String),
+    #[error("{0}")]
+    Message(String),
+    #[error("no message type of {0} to convert mcpack")]
+    NoMessageType(String),
+}
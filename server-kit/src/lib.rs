@@ -1,16 +1,30 @@
+pub mod auth;
 pub mod channel;
+pub mod compress;
 pub mod conf;
-mod error;
+mod context;
+mod controller;
+pub mod error;
 pub mod global;
+pub mod mcpack;
 pub mod message;
+mod metadata;
 pub mod protocol;
+mod restful;
 mod server;
 mod service;
 pub mod socket;
+pub mod stream;
+pub mod tls;
 pub mod tracer;
 
+pub use context::Context;
+pub use controller::Controller;
 pub use error::Error;
 pub use error::Result;
+pub use metadata::Metadata;
 pub use server::Server;
+pub use service::MethodDescriptor;
 pub use service::Service;
 pub use service::ServiceDescriptor;
+pub use service::Services;
@@ -0,0 +1,177 @@
+//! mcpack2, the self-describing binary format of legacy nshead services,
+//! and compack, the same with arrays of one primitive type packed.
+//!
+//! Values of types implementing serde's `Serialize` and `Deserialize` go
+//! to and from mcpack by `to_vec` and `from_slice`, others by `Value`.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::error::McpackErr;
+use crate::Result;
+
+pub use value::Value;
+
+mod de;
+pub(crate) mod pb;
+mod ser;
+mod value;
+
+/// The encoding of mcpack values, both are decoded alike.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Mcpack2,
+    /// mcpack2 with arrays of one primitive type packed as isomorphic arrays.
+    Compack,
+}
+
+/// Encode `value` as mcpack2.
+pub fn to_vec<T>(value: &T) -> Result<Vec<u8>>
+where
+    T: Serialize + ?Sized,
+{
+    to_vec_with_format(value, Format::Mcpack2)
+}
+
+pub fn to_vec_with_format<T>(value: &T, format: Format) -> Result<Vec<u8>>
+where
+    T: Serialize + ?Sized,
+{
+    Ok(to_value(value)?.encode(format))
+}
+
+pub fn from_slice<T>(buf: &[u8]) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    from_value(Value::decode(buf)?)
+}
+
+pub fn to_value<T>(value: &T) -> Result<Value>
+where
+    T: Serialize + ?Sized,
+{
+    Ok(value.serialize(ser::ValueSerializer)?)
+}
+
+pub fn from_value<T>(value: Value) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    Ok(T::deserialize(value)?)
+}
+
+impl serde::ser::Error for McpackErr {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        McpackErr::Message(msg.to_string())
+    }
+}
+
+impl serde::de::Error for McpackErr {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        McpackErr::Message(msg.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde_derive::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Kind {
+        Plain,
+        Weighted(u32),
+        Ranged { low: i16, high: i16 },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Item {
+        id: u64,
+        name: String,
+        score: f64,
+        tags: Vec<String>,
+        ranks: Vec<i32>,
+        parent: Option<Box<Item>>,
+        kinds: Vec<Kind>,
+        attrs: BTreeMap<String, bool>,
+    }
+
+    fn item() -> Item {
+        let leaf = Item {
+            id: 1,
+            name: "leaf".to_string(),
+            score: 0.5,
+            tags: vec![],
+            ranks: vec![],
+            parent: None,
+            kinds: vec![],
+            attrs: BTreeMap::new(),
+        };
+        Item {
+            id: u64::MAX,
+            name: "root".to_string(),
+            score: -1.25,
+            tags: vec!["a".to_string(), "b".to_string()],
+            ranks: vec![3, -1, 2],
+            parent: Some(Box::new(leaf)),
+            kinds: vec![
+                Kind::Plain,
+                Kind::Weighted(7),
+                Kind::Ranged { low: -1, high: 1 },
+            ],
+            attrs: [("x".to_string(), true), ("y".to_string(), false)].into(),
+        }
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        for format in [Format::Mcpack2, Format::Compack] {
+            let buf = to_vec_with_format(&item(), format).unwrap();
+            assert_eq!(from_slice::<Item>(&buf).unwrap(), item(), "{format:?}");
+        }
+        assert_eq!(
+            to_vec(&item()).unwrap(),
+            to_vec_with_format(&item(), Format::Mcpack2).unwrap()
+        );
+    }
+
+    #[test]
+    fn struct_as_object() {
+        let value = to_value(&item()).unwrap();
+        assert_eq!(value.get("name").and_then(Value::as_str), Some("root"));
+        assert_eq!(value.get("id").and_then(Value::as_u64), Some(u64::MAX));
+        assert_eq!(
+            value
+                .get("parent")
+                .and_then(|p| p.get("id"))
+                .and_then(Value::as_i64),
+            Some(1)
+        );
+        assert!(value.get("nope").is_none());
+
+        // integers of other widths and signs are taken as long as they fit
+        let value = Value::Object(vec![
+            ("low".to_string(), Value::UInt8(1)),
+            ("high".to_string(), Value::Int64(2)),
+        ]);
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Range {
+            low: i16,
+            high: i16,
+        }
+        assert_eq!(
+            from_value::<Range>(value).unwrap(),
+            Range { low: 1, high: 2 }
+        );
+        let value = Value::Object(vec![
+            ("low".to_string(), Value::Int32(-1 << 20)),
+            ("high".to_string(), Value::Int64(2)),
+        ]);
+        assert!(from_value::<Range>(value).is_err());
+    }
+}
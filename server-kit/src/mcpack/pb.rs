@@ -0,0 +1,283 @@
+//! Protobuf messages from and to mcpack objects of the same field names.
+
+use protobuf::reflect::{
+    FieldDescriptor, MessageDescriptor, ReflectValueBox, ReflectValueRef, RuntimeFieldType,
+    RuntimeType,
+};
+use protobuf::MessageDyn;
+
+use crate::error::McpackErr;
+use crate::{MethodDescriptor, Result};
+
+use super::{Format, Value};
+
+/// The request and response types of `method`, named `name` in errors.
+pub(crate) fn message_types<'a>(
+    method: &'a MethodDescriptor,
+    name: &str,
+) -> Result<(&'a MessageDescriptor, &'a MessageDescriptor)> {
+    match (&method.request_type, &method.response_type) {
+        (Some(req_ty), Some(resp_ty)) => Ok((req_ty, resp_ty)),
+        _ => Err(McpackErr::NoMessageType(name.to_string()).into()),
+    }
+}
+
+/// Encode the pb message of type `ty` in `buf` as a mcpack object.
+pub(crate) fn pb_to_mcpack(ty: &MessageDescriptor, buf: &[u8], format: Format) -> Result<Vec<u8>> {
+    let msg = ty.parse_from_bytes(buf)?;
+    Ok(message_to_value(&*msg).encode(format))
+}
+
+/// Encode the mcpack object in `buf` as a pb message of type `ty`, ignoring
+/// fields not in the message.
+pub(crate) fn mcpack_to_pb(ty: &MessageDescriptor, buf: &[u8]) -> Result<Vec<u8>> {
+    let msg = value_to_message(ty, Value::decode(buf)?)?;
+    // fails on missing required fields
+    Ok(msg.write_to_bytes_dyn()?)
+}
+
+pub(crate) fn message_to_value(msg: &dyn MessageDyn) -> Value {
+    let mut fields = Vec::new();
+    for field in msg.descriptor_dyn().fields() {
+        let value = match field.runtime_field_type() {
+            RuntimeFieldType::Singular(_) => match field.get_singular(msg) {
+                Some(value) => to_value(value),
+                None => continue,
+            },
+            RuntimeFieldType::Repeated(_) => {
+                let repeated = field.get_repeated(msg);
+                if repeated.is_empty() {
+                    continue;
+                }
+                Value::Array(repeated.into_iter().map(to_value).collect())
+            }
+            RuntimeFieldType::Map(..) => {
+                let map = field.get_map(msg);
+                if map.is_empty() {
+                    continue;
+                }
+                let entries = map
+                    .into_iter()
+                    .map(|(k, v)| (key_to_string(k), to_value(v)))
+                    .collect();
+                Value::Object(entries)
+            }
+        };
+        fields.push((field.name().to_string(), value));
+    }
+    Value::Object(fields)
+}
+
+pub(crate) fn value_to_message(
+    ty: &MessageDescriptor,
+    value: Value,
+) -> Result<Box<dyn MessageDyn>> {
+    let Value::Object(fields) = value else {
+        return Err(McpackErr::Invalid(format!("{} is not an object", ty.full_name())).into());
+    };
+    let mut msg = ty.new_instance();
+    for (name, value) in fields {
+        let Some(field) = ty.field_by_name(&name) else {
+            continue;
+        };
+        set_field(&field, &mut *msg, value)?;
+    }
+    Ok(msg)
+}
+
+fn set_field(field: &FieldDescriptor, msg: &mut dyn MessageDyn, value: Value) -> Result<()> {
+    if value == Value::Null {
+        return Ok(());
+    }
+    match field.runtime_field_type() {
+        RuntimeFieldType::Singular(ty) => {
+            field.set_singular_field(msg, to_reflect(field, &ty, value)?);
+        }
+        RuntimeFieldType::Repeated(ty) => {
+            let items = match value {
+                Value::Array(items) => items,
+                // a single item for a repeated field
+                value => vec![value],
+            };
+            for item in items {
+                let item = to_reflect(field, &ty, item)?;
+                field.mut_repeated(msg).push(item);
+            }
+        }
+        RuntimeFieldType::Map(key_ty, value_ty) => {
+            let Value::Object(entries) = value else {
+                return Err(mismatch(field));
+            };
+            for (key, value) in entries {
+                let key = to_reflect(field, &key_ty, Value::String(key))?;
+                let value = to_reflect(field, &value_ty, value)?;
+                field.mut_map(msg).insert(key, value);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn to_value(value: ReflectValueRef) -> Value {
+    match value {
+        ReflectValueRef::U32(v) => Value::UInt32(v),
+        ReflectValueRef::U64(v) => Value::UInt64(v),
+        ReflectValueRef::I32(v) => Value::Int32(v),
+        ReflectValueRef::I64(v) => Value::Int64(v),
+        ReflectValueRef::F32(v) => Value::Float(v),
+        ReflectValueRef::F64(v) => Value::Double(v),
+        ReflectValueRef::Bool(v) => Value::Bool(v),
+        ReflectValueRef::String(v) => Value::String(v.to_string()),
+        ReflectValueRef::Bytes(v) => Value::Binary(v.to_vec()),
+        ReflectValueRef::Enum(_, v) => Value::Int32(v),
+        ReflectValueRef::Message(m) => message_to_value(&*m),
+    }
+}
+
+fn key_to_string(key: ReflectValueRef) -> String {
+    match key {
+        ReflectValueRef::String(v) => v.to_string(),
+        key => key.to_string(),
+    }
+}
+
+// `value` as of type `ty`, with numbers converted if in range and map keys parsed
+fn to_reflect(field: &FieldDescriptor, ty: &RuntimeType, value: Value) -> Result<ReflectValueBox> {
+    let key = match &value {
+        Value::String(s) => Some(s.as_str()),
+        _ => None,
+    };
+    let value = match ty {
+        RuntimeType::I32 => value
+            .as_i64()
+            .or_else(|| key?.parse().ok())
+            .and_then(|v| i32::try_from(v).ok())
+            .map(ReflectValueBox::I32),
+        RuntimeType::I64 => value
+            .as_i64()
+            .or_else(|| key?.parse().ok())
+            .map(ReflectValueBox::I64),
+        RuntimeType::U32 => value
+            .as_u64()
+            .or_else(|| key?.parse().ok())
+            .and_then(|v| u32::try_from(v).ok())
+            .map(ReflectValueBox::U32),
+        RuntimeType::U64 => value
+            .as_u64()
+            .or_else(|| key?.parse().ok())
+            .map(ReflectValueBox::U64),
+        RuntimeType::F32 => value.as_f64().map(|v| ReflectValueBox::F32(v as f32)),
+        RuntimeType::F64 => value.as_f64().map(ReflectValueBox::F64),
+        RuntimeType::Bool => match value {
+            Value::Bool(v) => Some(ReflectValueBox::Bool(v)),
+            Value::String(s) => s.parse().ok().map(ReflectValueBox::Bool),
+            value => value.as_i64().map(|v| ReflectValueBox::Bool(v != 0)),
+        },
+        RuntimeType::String => match value {
+            Value::String(s) => Some(ReflectValueBox::String(s)),
+            Value::Binary(b) => String::from_utf8(b).ok().map(ReflectValueBox::String),
+            _ => None,
+        },
+        RuntimeType::VecU8 => match value {
+            Value::Binary(b) => Some(ReflectValueBox::Bytes(b)),
+            Value::String(s) => Some(ReflectValueBox::Bytes(s.into_bytes())),
+            _ => None,
+        },
+        RuntimeType::Enum(ty) => value
+            .as_i64()
+            .and_then(|v| i32::try_from(v).ok())
+            .or_else(|| Some(ty.value_by_name(key?)?.value()))
+            .map(|v| ReflectValueBox::Enum(ty.clone(), v)),
+        RuntimeType::Message(ty) => Some(ReflectValueBox::Message(value_to_message(ty, value)?)),
+    };
+    value.ok_or_else(|| mismatch(field))
+}
+
+fn mismatch(field: &FieldDescriptor) -> crate::Error {
+    McpackErr::Invalid(format!("mismatched type of {}", field.full_name())).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use protobuf::{Message, MessageFull};
+
+    use server_kit_protocol::baidu_rpc_meta::{RpcMeta, RpcRequestMeta, RpcRequestMetaExtField};
+    use server_kit_protocol::options::ProtocolType;
+
+    use super::*;
+
+    fn meta() -> RpcMeta {
+        let mut field = RpcRequestMetaExtField::new();
+        field.set_key("k".to_string());
+        field.set_value("v".to_string());
+        let mut req = RpcRequestMeta::new();
+        req.set_service_name("test.echo".to_string());
+        req.set_method_name("m".to_string());
+        req.set_log_id(-7);
+        req.set_request_code(u64::MAX);
+        req.set_protocol_type(ProtocolType::PROTOCOL_NSHEAD_MCPACK);
+        req.ext_fields.push(field.clone());
+        req.ext_fields.push(field);
+        let mut meta = RpcMeta::new();
+        meta.request = protobuf::MessageField::some(req);
+        meta.set_attachment_size(3);
+        meta.set_authentication_data(b"\0token".to_vec());
+        meta
+    }
+
+    #[test]
+    fn pb_round_trip() {
+        let ty = RpcMeta::descriptor();
+        let pb = meta().write_to_bytes().unwrap();
+        for format in [Format::Mcpack2, Format::Compack] {
+            let buf = pb_to_mcpack(&ty, &pb, format).unwrap();
+            let value = Value::decode(&buf).unwrap();
+            let req = value.get("request").unwrap();
+            assert_eq!(req.get("method_name").and_then(Value::as_str), Some("m"));
+            assert_eq!(req.get("log_id").and_then(Value::as_i64), Some(-7));
+            assert!(value.get("compress_type").is_none());
+
+            let pb = mcpack_to_pb(&ty, &buf).unwrap();
+            assert_eq!(
+                RpcMeta::parse_from_bytes(&pb).unwrap(),
+                meta(),
+                "{format:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn mcpack_to_pb_converts() {
+        let ty = RpcRequestMeta::descriptor();
+        let value = Value::Object(vec![
+            (
+                "service_name".to_string(),
+                Value::Binary(b"test.echo".to_vec()),
+            ),
+            ("method_name".to_string(), Value::String("m".to_string())),
+            ("log_id".to_string(), Value::UInt8(7)),
+            ("timeout_ms".to_string(), Value::String("100".to_string())),
+            (
+                "protocol_type".to_string(),
+                Value::String("PROTOCOL_NSHEAD".to_string()),
+            ),
+            ("unknown".to_string(), Value::Bool(true)),
+            ("channel_id".to_string(), Value::Null),
+        ]);
+        let pb = mcpack_to_pb(&ty, &value.encode(Format::Mcpack2)).unwrap();
+        let req = RpcRequestMeta::parse_from_bytes(&pb).unwrap();
+        assert_eq!((req.service_name(), req.log_id()), ("test.echo", 7));
+        assert_eq!(req.protocol_type(), ProtocolType::PROTOCOL_NSHEAD);
+        assert!(!req.has_channel_id());
+
+        // a field of a mismatched type, or a required one missing
+        let value = Value::Object(vec![("timeout_ms".to_string(), Value::Int64(1 << 40))]);
+        let err = mcpack_to_pb(&ty, &value.encode(Format::Mcpack2)).unwrap_err();
+        assert!(err.to_string().contains("mismatched type"), "{err}");
+        let value = Value::Object(vec![(
+            "method_name".to_string(),
+            Value::String("m".to_string()),
+        )]);
+        assert!(mcpack_to_pb(&ty, &value.encode(Format::Mcpack2)).is_err());
+    }
+}
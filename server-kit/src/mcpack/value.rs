@@ -0,0 +1,477 @@
+use bytes::{Buf, BufMut};
+
+use crate::error::McpackErr;
+use crate::Result;
+
+use super::Format;
+
+const FIELD_OBJECT: u8 = 0x10;
+const FIELD_ARRAY: u8 = 0x20;
+const FIELD_ISOARRAY: u8 = 0x30;
+const FIELD_STRING: u8 = 0x50;
+const FIELD_BINARY: u8 = 0x60;
+const FIELD_INT8: u8 = 0x11;
+const FIELD_INT16: u8 = 0x12;
+const FIELD_INT32: u8 = 0x14;
+const FIELD_INT64: u8 = 0x18;
+const FIELD_UINT8: u8 = 0x21;
+const FIELD_UINT16: u8 = 0x22;
+const FIELD_UINT32: u8 = 0x24;
+const FIELD_UINT64: u8 = 0x28;
+const FIELD_BOOL: u8 = 0x31;
+const FIELD_FLOAT: u8 = 0x44;
+const FIELD_DOUBLE: u8 = 0x48;
+const FIELD_NULL: u8 = 0x61;
+
+// strings and binaries up to 255 bytes have the short head of 1-byte size
+const FIELD_SHORT_MASK: u8 = 0x80;
+// the size of fixed-size values is in the low bits of their type
+const FIELD_FIXED_MASK: u8 = 0x0f;
+// fields without any of these bits are deleted
+const FIELD_NON_DELETED_MASK: u8 = 0x70;
+
+// nesting of objects and arrays deeper than this is taken as malformed
+const MAX_DEPTH: usize = 64;
+
+/// A mcpack value, of which objects keep the order of their fields.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int8(i8),
+    Int16(i16),
+    Int32(i32),
+    Int64(i64),
+    UInt8(u8),
+    UInt16(u16),
+    UInt32(u32),
+    UInt64(u64),
+    Float(f32),
+    Double(f64),
+    String(String),
+    Binary(Vec<u8>),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// The value of field `name`, if this is an object having it.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields.iter().find(|(n, _)| n == name).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Integers of any width and sign that fit in an i64.
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Value::Int8(v) => Some(v as i64),
+            Value::Int16(v) => Some(v as i64),
+            Value::Int32(v) => Some(v as i64),
+            Value::Int64(v) => Some(v),
+            Value::UInt8(v) => Some(v as i64),
+            Value::UInt16(v) => Some(v as i64),
+            Value::UInt32(v) => Some(v as i64),
+            Value::UInt64(v) => i64::try_from(v).ok(),
+            _ => None,
+        }
+    }
+
+    /// Integers of any width and sign that fit in an u64.
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Value::UInt8(v) => Some(v as u64),
+            Value::UInt16(v) => Some(v as u64),
+            Value::UInt32(v) => Some(v as u64),
+            Value::UInt64(v) => Some(v),
+            _ => self.as_i64().and_then(|v| u64::try_from(v).ok()),
+        }
+    }
+
+    /// Numbers of any type, possibly losing precision.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Float(v) => Some(v as f64),
+            Value::Double(v) => Some(v),
+            Value::UInt64(v) => Some(v as f64),
+            _ => self.as_i64().map(|v| v as f64),
+        }
+    }
+
+    /// Encode as a field without name, which is how mcpack bodies are.
+    pub fn encode(&self, format: Format) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.encode_field(&mut buf, "", format);
+        buf
+    }
+
+    /// Decode the field without name in `buf`, trailing bytes are ignored.
+    pub fn decode(buf: &[u8]) -> Result<Self> {
+        let mut buf = buf;
+        match decode_field(&mut buf, 0)? {
+            Some((_, value)) => Ok(value),
+            None => Err(McpackErr::Invalid("deleted".to_string()).into()),
+        }
+    }
+
+    // the type of fixed-size values, whose bytes are put by `put_fixed`
+    fn fixed_type(&self) -> Option<u8> {
+        let ty = match self {
+            Value::Null => FIELD_NULL,
+            Value::Bool(_) => FIELD_BOOL,
+            Value::Int8(_) => FIELD_INT8,
+            Value::Int16(_) => FIELD_INT16,
+            Value::Int32(_) => FIELD_INT32,
+            Value::Int64(_) => FIELD_INT64,
+            Value::UInt8(_) => FIELD_UINT8,
+            Value::UInt16(_) => FIELD_UINT16,
+            Value::UInt32(_) => FIELD_UINT32,
+            Value::UInt64(_) => FIELD_UINT64,
+            Value::Float(_) => FIELD_FLOAT,
+            Value::Double(_) => FIELD_DOUBLE,
+            _ => return None,
+        };
+        Some(ty)
+    }
+
+    fn put_fixed(&self, buf: &mut Vec<u8>) {
+        match *self {
+            Value::Null => buf.put_u8(0),
+            Value::Bool(v) => buf.put_u8(v as u8),
+            Value::Int8(v) => buf.put_i8(v),
+            Value::Int16(v) => buf.put_i16_le(v),
+            Value::Int32(v) => buf.put_i32_le(v),
+            Value::Int64(v) => buf.put_i64_le(v),
+            Value::UInt8(v) => buf.put_u8(v),
+            Value::UInt16(v) => buf.put_u16_le(v),
+            Value::UInt32(v) => buf.put_u32_le(v),
+            Value::UInt64(v) => buf.put_u64_le(v),
+            Value::Float(v) => buf.put_f32_le(v),
+            Value::Double(v) => buf.put_f64_le(v),
+            _ => {}
+        }
+    }
+
+    // names longer than 254 bytes are cut, as the size with the NUL is a byte
+    fn encode_field(&self, buf: &mut Vec<u8>, name: &str, format: Format) {
+        let name = &name.as_bytes()[..name.len().min(254)];
+        let name_size = match name.len() {
+            0 => 0,
+            n => n as u8 + 1,
+        };
+        let put_name = |buf: &mut Vec<u8>| {
+            if name_size > 0 {
+                buf.put_slice(name);
+                buf.put_u8(0);
+            }
+        };
+
+        if let Some(ty) = self.fixed_type() {
+            buf.put_u8(ty);
+            buf.put_u8(name_size);
+            put_name(buf);
+            self.put_fixed(buf);
+            return;
+        }
+        let (ty, value) = match self {
+            Value::String(s) => {
+                let mut value = Vec::with_capacity(s.len() + 1);
+                value.put_slice(s.as_bytes());
+                value.put_u8(0);
+                (FIELD_STRING, value)
+            }
+            Value::Binary(b) => (FIELD_BINARY, b.clone()),
+            Value::Array(items) => match iso_type(items, format) {
+                Some(item_ty) => {
+                    let mut value = Vec::with_capacity(1 + items.len() * 8);
+                    value.put_u8(item_ty);
+                    items.iter().for_each(|item| item.put_fixed(&mut value));
+                    (FIELD_ISOARRAY, value)
+                }
+                None => {
+                    let mut value = Vec::new();
+                    value.put_u32_le(items.len() as u32);
+                    for item in items {
+                        item.encode_field(&mut value, "", format);
+                    }
+                    (FIELD_ARRAY, value)
+                }
+            },
+            Value::Object(fields) => {
+                let mut value = Vec::new();
+                value.put_u32_le(fields.len() as u32);
+                for (name, field) in fields {
+                    field.encode_field(&mut value, name, format);
+                }
+                (FIELD_OBJECT, value)
+            }
+            _ => unreachable!("fixed-size value"),
+        };
+        match ty {
+            FIELD_STRING | FIELD_BINARY if value.len() <= u8::MAX as usize => {
+                buf.put_u8(ty | FIELD_SHORT_MASK);
+                buf.put_u8(name_size);
+                buf.put_u8(value.len() as u8);
+            }
+            _ => {
+                buf.put_u8(ty);
+                buf.put_u8(name_size);
+                buf.put_u32_le(value.len() as u32);
+            }
+        }
+        put_name(buf);
+        buf.put_slice(&value);
+    }
+}
+
+// the type of the items if `items` is packed as an isomorphic array
+fn iso_type(items: &[Value], format: Format) -> Option<u8> {
+    if format != Format::Compack {
+        return None;
+    }
+    let ty = items.first()?.fixed_type()?;
+    match ty != FIELD_NULL && items.iter().all(|item| item.fixed_type() == Some(ty)) {
+        true => Some(ty),
+        false => None,
+    }
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if buf.len() < len {
+        return Err(McpackErr::Invalid("truncated".to_string()).into());
+    }
+    let (head, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(head)
+}
+
+fn decode_fixed(ty: u8, mut value: &[u8]) -> Result<Value> {
+    let value = match ty {
+        FIELD_NULL => Value::Null,
+        FIELD_BOOL => Value::Bool(value.get_u8() != 0),
+        FIELD_INT8 => Value::Int8(value.get_i8()),
+        FIELD_INT16 => Value::Int16(value.get_i16_le()),
+        FIELD_INT32 => Value::Int32(value.get_i32_le()),
+        FIELD_INT64 => Value::Int64(value.get_i64_le()),
+        FIELD_UINT8 => Value::UInt8(value.get_u8()),
+        FIELD_UINT16 => Value::UInt16(value.get_u16_le()),
+        FIELD_UINT32 => Value::UInt32(value.get_u32_le()),
+        FIELD_UINT64 => Value::UInt64(value.get_u64_le()),
+        FIELD_FLOAT => Value::Float(value.get_f32_le()),
+        FIELD_DOUBLE => Value::Double(value.get_f64_le()),
+        ty => return Err(McpackErr::Invalid(format!("type {ty:#x}")).into()),
+    };
+    Ok(value)
+}
+
+fn decode_items(mut value: &[u8], depth: usize) -> Result<Vec<(String, Value)>> {
+    let count = take(&mut value, 4)?.get_u32_le() as usize;
+    let mut items = Vec::with_capacity(count.min(value.len()));
+    for _ in 0..count {
+        if let Some(item) = decode_field(&mut value, depth + 1)? {
+            items.push(item);
+        }
+    }
+    Ok(items)
+}
+
+// the name and value of the field at the front of `buf`, none if deleted
+fn decode_field(buf: &mut &[u8], depth: usize) -> Result<Option<(String, Value)>> {
+    if depth > MAX_DEPTH {
+        return Err(McpackErr::Invalid("too deeply nested".to_string()).into());
+    }
+    let head = take(buf, 2)?;
+    let (ty, name_size) = (head[0], head[1] as usize);
+    let value_size = match (ty & FIELD_FIXED_MASK, ty & FIELD_SHORT_MASK) {
+        (0, 0) => take(buf, 4)?.get_u32_le() as usize,
+        (0, _) => take(buf, 1)?[0] as usize,
+        (size, _) => size as usize,
+    };
+    let name = take(buf, name_size)?;
+    let value = take(buf, value_size)?;
+    if ty & FIELD_NON_DELETED_MASK == 0 {
+        return Ok(None);
+    }
+    let name = String::from_utf8_lossy(name.strip_suffix(&[0]).unwrap_or(name)).into_owned();
+
+    let value = match ty & !FIELD_SHORT_MASK {
+        FIELD_OBJECT => Value::Object(decode_items(value, depth)?),
+        FIELD_ARRAY => Value::Array(
+            decode_items(value, depth)?
+                .into_iter()
+                .map(|(_, v)| v)
+                .collect(),
+        ),
+        FIELD_ISOARRAY => {
+            let (item_ty, items) = value
+                .split_first()
+                .ok_or_else(|| McpackErr::Invalid("isoarray without type".to_string()))?;
+            let size = (item_ty & FIELD_FIXED_MASK) as usize;
+            if size == 0 || items.len() % size != 0 {
+                return Err(McpackErr::Invalid(format!("isoarray of type {item_ty:#x}")).into());
+            }
+            Value::Array(
+                items
+                    .chunks(size)
+                    .map(|item| decode_fixed(*item_ty, item))
+                    .collect::<Result<_>>()?,
+            )
+        }
+        FIELD_STRING => {
+            let s = value.strip_suffix(&[0]).unwrap_or(value);
+            let s = std::str::from_utf8(s).map_err(|e| McpackErr::Invalid(e.to_string()))?;
+            Value::String(s.to_string())
+        }
+        FIELD_BINARY => Value::Binary(value.to_vec()),
+        ty if ty & FIELD_FIXED_MASK != 0 => decode_fixed(ty, value)?,
+        ty => return Err(McpackErr::Invalid(format!("type {ty:#x}")).into()),
+    };
+
+    Ok(Some((name, value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_types() -> Value {
+        Value::Object(vec![
+            ("null".to_string(), Value::Null),
+            ("bool".to_string(), Value::Bool(true)),
+            ("i8".to_string(), Value::Int8(-8)),
+            ("i16".to_string(), Value::Int16(-16)),
+            ("i32".to_string(), Value::Int32(-32)),
+            ("i64".to_string(), Value::Int64(i64::MIN)),
+            ("u8".to_string(), Value::UInt8(8)),
+            ("u16".to_string(), Value::UInt16(16)),
+            ("u32".to_string(), Value::UInt32(32)),
+            ("u64".to_string(), Value::UInt64(u64::MAX)),
+            ("float".to_string(), Value::Float(1.5)),
+            ("double".to_string(), Value::Double(-2.25)),
+            ("short".to_string(), Value::String("hi".to_string())),
+            ("long".to_string(), Value::String("x".repeat(300))),
+            ("binary".to_string(), Value::Binary(vec![0, 1, 2])),
+            ("empty".to_string(), Value::Array(vec![])),
+            (
+                "mixed".to_string(),
+                Value::Array(vec![Value::Int32(1), Value::String("a".to_string())]),
+            ),
+            (
+                "ints".to_string(),
+                Value::Array(vec![Value::Int32(1), Value::Int32(-2), Value::Int32(3)]),
+            ),
+            (
+                "nested".to_string(),
+                Value::Object(vec![("inner".to_string(), Value::Bool(false))]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn round_trip() {
+        for format in [Format::Mcpack2, Format::Compack] {
+            let value = all_types();
+            let buf = value.encode(format);
+            assert_eq!(Value::decode(&buf).unwrap(), value, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn encode_fields() {
+        let value = Value::Object(vec![
+            ("a".to_string(), Value::Int16(0x102)),
+            ("s".to_string(), Value::String("hi".to_string())),
+        ]);
+        let expected = [
+            &[FIELD_OBJECT, 0][..],
+            &18u32.to_le_bytes(),
+            &2u32.to_le_bytes(),
+            &[FIELD_INT16, 2, b'a', 0, 2, 1],
+            &[
+                FIELD_STRING | FIELD_SHORT_MASK,
+                2,
+                3,
+                b's',
+                0,
+                b'h',
+                b'i',
+                0,
+            ],
+        ]
+        .concat();
+        assert_eq!(value.encode(Format::Mcpack2), expected);
+
+        let long = Value::Binary(vec![7; 256]);
+        let buf = long.encode(Format::Mcpack2);
+        assert_eq!(buf[..6], [FIELD_BINARY, 0, 0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn compack_packs_arrays_of_one_type() {
+        let ints = Value::Array(vec![Value::UInt16(1), Value::UInt16(2)]);
+        let expected = [
+            &[FIELD_ISOARRAY, 0][..],
+            &5u32.to_le_bytes(),
+            &[FIELD_UINT16, 1, 0, 2, 0],
+        ]
+        .concat();
+        assert_eq!(ints.encode(Format::Compack), expected);
+        assert_eq!(ints.encode(Format::Mcpack2)[0], FIELD_ARRAY);
+
+        // nor nulls nor arrays of more than one type are
+        for items in [
+            vec![Value::Null, Value::Null],
+            vec![Value::UInt16(1), Value::UInt32(2)],
+            vec![Value::String("a".to_string())],
+        ] {
+            let buf = Value::Array(items).encode(Format::Compack);
+            assert_eq!(buf[0], FIELD_ARRAY);
+        }
+    }
+
+    #[test]
+    fn skip_deleted_fields() {
+        let mut buf = vec![FIELD_OBJECT, 0];
+        buf.put_u32_le(4 + 4 + 3);
+        buf.put_u32_le(2);
+        buf.extend_from_slice(&[FIELD_INT16 & FIELD_FIXED_MASK, 0, 1, 2]);
+        buf.extend_from_slice(&[FIELD_INT8, 0, 3]);
+        assert_eq!(
+            Value::decode(&buf).unwrap(),
+            Value::Object(vec![(String::new(), Value::Int8(3))])
+        );
+    }
+
+    #[test]
+    fn reject_malformed() {
+        let buf = all_types().encode(Format::Compack);
+        for n in [1, 5, buf.len() - 1] {
+            let err = Value::decode(&buf[..n]).unwrap_err();
+            assert!(err.to_string().contains("truncated"), "{n}: {err}");
+        }
+
+        let mut nested = Value::Null;
+        for _ in 0..=MAX_DEPTH {
+            nested = Value::Array(vec![nested]);
+        }
+        let err = Value::decode(&nested.encode(Format::Mcpack2)).unwrap_err();
+        assert!(err.to_string().contains("too deeply nested"), "{err}");
+
+        let mut isoarray = vec![FIELD_ISOARRAY, 0];
+        isoarray.put_u32_le(4);
+        isoarray.extend_from_slice(&[FIELD_UINT16, 1, 0, 2]);
+        let err = Value::decode(&isoarray).unwrap_err();
+        assert!(err.to_string().contains("isoarray"), "{err}");
+
+        let err = Value::decode(&[0x70, 0, 0, 0, 0, 0]).unwrap_err();
+        assert!(err.to_string().contains("type"), "{err}");
+    }
+}
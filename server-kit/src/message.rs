@@ -1,9 +1,18 @@
+use std::collections::HashMap;
+
 use bytes::{BufMut, BytesMut};
 
+use server_kit_protocol::options::ChunkInfo;
+
+use crate::error::ParseErr;
+use crate::Result;
+
 #[derive(Default, Debug)]
 pub struct CommonMsg {
     pub meta: Vec<u8>,
     pub payload: Vec<u8>,
+    /// Set if this is one chunk of a larger message.
+    pub chunk_info: Option<ChunkInfo>,
 }
 
 impl CommonMsg {
@@ -11,6 +20,7 @@ impl CommonMsg {
         Self {
             meta: vec![],
             payload,
+            chunk_info: None,
         }
     }
 
@@ -47,3 +57,117 @@ impl CommonMsg {
         self.meta_size() + self.payload_size()
     }
 }
+
+/// Largest message reassembled from chunks, or decompressed.
+pub(crate) const MAX_MESSAGE_SIZE: usize = 512 << 20;
+
+// limits of the messages being reassembled on one connection at once
+const MAX_CHUNKS_SIZE: usize = MAX_MESSAGE_SIZE;
+const MAX_CHUNKED_MESSAGES: usize = 64;
+
+/// Reassembles the messages sent in chunks on one connection, whose
+/// `chunk_id` counts down to 0 at the last chunk.
+#[derive(Default)]
+pub(crate) struct Chunks {
+    messages: HashMap<i64, CommonMsg>,
+    // bytes of all the messages being reassembled
+    size: usize,
+}
+
+impl Chunks {
+    /// The complete message, once `msg` is not or no longer part of one.
+    pub fn merge(&mut self, mut msg: CommonMsg) -> Result<Option<CommonMsg>> {
+        let info = match msg.chunk_info.take() {
+            Some(info) => info,
+            None => return Ok(Some(msg)),
+        };
+        let (stream_id, chunk_id) = (info.stream_id(), info.chunk_id());
+        self.size += msg.payload.len();
+        if self.size > MAX_CHUNKS_SIZE {
+            return Err(ParseErr::ChunksTooLarge(MAX_CHUNKS_SIZE).into());
+        }
+        // the first chunk keeps the meta, the info of the latest one
+        let mut first = match self.messages.remove(&stream_id) {
+            Some(mut first) => {
+                let prev = first.chunk_info.as_ref().map_or(0, |info| info.chunk_id());
+                if chunk_id != prev - 1 {
+                    return Err(ParseErr::UnexpectedChunk(stream_id, chunk_id).into());
+                }
+                first.payload.extend_from_slice(&msg.payload);
+                first
+            }
+            None => msg,
+        };
+        if chunk_id <= 0 {
+            self.size -= first.payload.len();
+            return Ok(Some(first));
+        }
+        if self.messages.len() >= MAX_CHUNKED_MESSAGES {
+            return Err(ParseErr::TooManyChunkedMessages(MAX_CHUNKED_MESSAGES).into());
+        }
+        first.chunk_info = Some(info);
+        self.messages.insert(stream_id, first);
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(stream_id: i64, chunk_id: i64, payload: &[u8]) -> CommonMsg {
+        let mut info = ChunkInfo::new();
+        info.set_stream_id(stream_id);
+        info.set_chunk_id(chunk_id);
+        let mut msg = CommonMsg::new(payload.to_vec());
+        msg.chunk_info = Some(info);
+        msg
+    }
+
+    #[test]
+    fn merge_interleaved() {
+        let mut chunks = Chunks::default();
+        assert!(chunks.merge(chunk(1, 1, b"ab")).unwrap().is_none());
+        assert!(chunks.merge(chunk(2, 1, b"xy")).unwrap().is_none());
+        let msg = chunks.merge(CommonMsg::new(b"whole".to_vec())).unwrap();
+        assert_eq!(msg.unwrap().payload, b"whole");
+        assert_eq!(
+            chunks.merge(chunk(2, 0, b"z")).unwrap().unwrap().payload,
+            b"xyz"
+        );
+        assert_eq!(
+            chunks.merge(chunk(1, 0, b"c")).unwrap().unwrap().payload,
+            b"abc"
+        );
+        assert!(chunks.messages.is_empty());
+        assert_eq!(chunks.size, 0);
+    }
+
+    #[test]
+    fn merge_out_of_order() {
+        let mut chunks = Chunks::default();
+        chunks.merge(chunk(1, 2, b"a")).unwrap();
+        assert!(chunks.merge(chunk(1, 0, b"c")).is_err());
+    }
+
+    #[test]
+    fn merge_too_many() {
+        let mut chunks = Chunks::default();
+        for id in 0..MAX_CHUNKED_MESSAGES as i64 {
+            chunks.merge(chunk(id, 1, b"a")).unwrap();
+        }
+        let err = chunks.merge(chunk(-1, 1, b"a")).unwrap_err();
+        assert!(err.to_string().contains("messages in chunks"), "{err}");
+    }
+
+    #[test]
+    fn merge_too_large() {
+        let mut chunks = Chunks::default();
+        chunks
+            .merge(chunk(1, 2, &vec![0; MAX_CHUNKS_SIZE]))
+            .unwrap();
+        let err = chunks.merge(chunk(1, 1, b"a")).unwrap_err();
+        assert!(err.to_string().contains("bytes in all"), "{err}");
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+use protobuf::{Message, UnknownValueRef};
+use server_kit_protocol::baidu_rpc_meta::{RpcMeta, RpcRequestMetaExtField};
+
+// `user_fields` of the upstream brpc `RpcMeta`, a map the vendored meta predates
+const USER_FIELDS: u32 = 9;
+
+/// Key/value side-channel data carried with a request or a response.
+///
+/// On the brpc wire it is encoded as `ext_fields` of the request meta, and as
+/// `user_fields` of the meta of responses as upstream brpc does.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Metadata(HashMap<String, String>);
+
+impl Metadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) -> Option<String> {
+        self.0.insert(key.into(), value.into())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.0.remove(key)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.0.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn to_ext_fields(&self) -> Vec<RpcRequestMetaExtField> {
+        self.iter()
+            .map(|(k, v)| {
+                let mut field = RpcRequestMetaExtField::new();
+                field.set_key(k.to_string());
+                field.set_value(v.to_string());
+                field
+            })
+            .collect()
+    }
+
+    /// Add to the `user_fields` of `meta`, whose entries have the wire format
+    /// of `RpcRequestMetaExtField`.
+    pub(crate) fn write_user_fields(&self, meta: &mut RpcMeta) -> protobuf::Result<()> {
+        for field in self.to_ext_fields() {
+            meta.mut_unknown_fields()
+                .add_length_delimited(USER_FIELDS, field.write_to_bytes()?);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn from_user_fields(meta: &RpcMeta) -> Self {
+        meta.unknown_fields()
+            .iter()
+            .filter_map(|(number, value)| match (number, value) {
+                (USER_FIELDS, UnknownValueRef::LengthDelimited(entry)) => {
+                    let mut field = RpcRequestMetaExtField::new();
+                    field.merge_from_bytes(entry).ok()?;
+                    Some((field.key().to_string(), field.value().to_string()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl From<&[RpcRequestMetaExtField]> for Metadata {
+    fn from(fields: &[RpcRequestMetaExtField]) -> Self {
+        fields
+            .iter()
+            .map(|f| (f.key().to_string(), f.value().to_string()))
+            .collect()
+    }
+}
+
+impl<K, V> FromIterator<(K, V)> for Metadata
+where
+    K: Into<String>,
+    V: Into<String>,
+{
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        Self(
+            iter.into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_fields_round_trip() {
+        let md: Metadata = [("k", "v"), ("trace", "42")].into_iter().collect();
+        let mut meta = RpcMeta::new();
+        md.write_user_fields(&mut meta).unwrap();
+        let meta = RpcMeta::parse_from_bytes(&meta.write_to_bytes().unwrap()).unwrap();
+        assert_eq!(Metadata::from_user_fields(&meta), md);
+    }
+
+    #[test]
+    fn user_fields_wire_format() {
+        // map<string, string> user_fields = 9 of upstream brpc, with one entry k => v
+        let bytes = [0x4a, 0x06, 0x0a, 0x01, b'k', 0x12, 0x01, b'v'];
+        let meta = RpcMeta::parse_from_bytes(&bytes).unwrap();
+        assert_eq!(Metadata::from_user_fields(&meta).get("k"), Some("v"));
+
+        let md: Metadata = [("k", "v")].into_iter().collect();
+        let mut meta = RpcMeta::new();
+        md.write_user_fields(&mut meta).unwrap();
+        assert_eq!(meta.write_to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn ext_fields_round_trip() {
+        let md: Metadata = [("a", "1"), ("b", "")].into_iter().collect();
+        assert_eq!(Metadata::from(md.to_ext_fields().as_slice()), md);
+    }
+}
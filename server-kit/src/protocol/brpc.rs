@@ -1,23 +1,22 @@
-use std::any::TypeId;
-use std::cmp::Ordering;
-use std::collections::HashMap;
 use std::fmt;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicI64, Ordering};
 
 use async_trait::async_trait;
-use bytes::{BufMut, BytesMut};
-use protobuf::Message;
-use tokio::io::AsyncReadExt;
-use tokio::net::TcpStream;
+use bytes::{BufMut, Bytes, BytesMut};
+use protobuf::{Enum, Message, MessageField};
 use tracing::instrument;
 use tracing::{debug, warn};
 
-use server_kit_protocol::baidu_rpc_meta::RpcMeta;
+use server_kit_protocol::baidu_rpc_meta::{RpcMeta, RpcResponseMeta};
+use server_kit_protocol::options::{ChunkInfo, CompressType, ProtocolType, TalkType};
 
-use super::Protocol;
-use crate::error::{ParseErr, SvcErr};
-use crate::global::BUF_SIZE;
+use super::{Frames, Protocol};
+use crate::compress;
+use crate::error::{ParseErr, RpcErr, EREQUEST, ERPCAUTH};
 use crate::message::CommonMsg;
-use crate::{Result, Service};
+use crate::socket::Connection;
+use crate::{Context, Controller, Metadata, Result, Services};
 
 const HEADER_SIZE: usize = ::std::mem::size_of::<Header>();
 const TAG: [u8; 4] = *b"PRPC";
@@ -26,56 +25,42 @@ const BODY_START: usize = TAG_SIZE;
 const BODY_SIZE: usize = 4;
 const META_START: usize = BODY_START + BODY_SIZE;
 
-pub struct Brpc(HashMap<String, Box<dyn Service>>);
+pub struct Brpc {
+    // responses larger than it are sent in chunks
+    chunk_size: Option<usize>,
+}
+
+impl Brpc {
+    pub fn with_chunk_size(size: usize) -> Self {
+        Self {
+            chunk_size: Some(size),
+        }
+    }
+}
 
 #[async_trait]
 impl Protocol for Brpc {
     fn default() -> Self {
-        Brpc(HashMap::default())
+        Brpc { chunk_size: None }
     }
-    fn protocol_id() -> TypeId {
-        TypeId::of::<Self>()
+    fn protocol_type(&self) -> ProtocolType {
+        ProtocolType::PROTOCOL_BAIDU_STD
     }
-
-    fn add_service(&mut self, svc_name: String, svc: Box<dyn Service>) -> Result<()> {
-        let services = &mut self.0;
-        if services.contains_key(&svc_name) {
-            return Err(SvcErr::Exist(svc_name).into());
-        }
-        services.insert(svc_name, svc);
-        Ok(())
+    fn name(&self) -> &'static str {
+        "baidu_std"
+    }
+    fn supports_authentication(&self) -> bool {
+        true
     }
 
     #[instrument(skip_all)]
-    async fn parse(&self, stream: &mut TcpStream) -> Result<CommonMsg> {
-        let mut buf = BytesMut::with_capacity(BUF_SIZE);
-        loop {
-            if stream.read_buf(&mut buf).await? == 0 {
-                return Err(ParseErr::UnexpectedEof.into());
-            }
-            debug!("read from stream: {buf:?}");
-
-            match buf.len() {
-                n if n <= TAG_SIZE => {
-                    if buf[..n] != TAG[..n] {
-                        return Err(ParseErr::TryOther.into());
-                    }
-                    continue;
-                }
-                n if n > TAG_SIZE && n < HEADER_SIZE => {
-                    if buf[..TAG_SIZE] != TAG[..] {
-                        return Err(ParseErr::TryOther.into());
-                    }
-                    continue;
-                }
-                n if n >= HEADER_SIZE => {
-                    if buf[..TAG_SIZE] != TAG[..] {
-                        return Err(ParseErr::TryOther.into());
-                    }
-                    break;
-                }
-                _ => unimplemented!(),
-            };
+    fn parse(&self, buf: &mut BytesMut) -> Result<CommonMsg> {
+        let n = buf.len().min(TAG_SIZE);
+        if buf[..n] != TAG[..n] {
+            return Err(ParseErr::TryOther.into());
+        }
+        if buf.len() < HEADER_SIZE {
+            return Err(ParseErr::NotEnoughData.into());
         }
         let head = buf[..HEADER_SIZE].try_into().unwrap();
         let head = Header::from_u8_slice(&head);
@@ -83,83 +68,258 @@ impl Protocol for Brpc {
             warn!(%head, "body_size less than meta_size");
             return Err(ParseErr::TryOther.into());
         }
+        if buf.len() < HEADER_SIZE + head.body_size as usize {
+            return Err(ParseErr::NotEnoughData.into());
+        }
         debug!(%head, "finish to parse brpc header");
 
         let _ = buf.split_to(HEADER_SIZE);
-        loop {
-            match head.body_size.cmp(&(buf.len() as u32)) {
-                Ordering::Equal => break,
-                Ordering::Greater => {
-                    if stream.read_buf(&mut buf).await? == 0 {
-                        return Err(ParseErr::UnexpectedEof.into());
-                    }
-                    debug!("read from stream: {buf:?}");
-                }
-                Ordering::Less => unimplemented!(),
-            };
-        }
-
+        let mut body = buf.split_to(head.body_size as usize);
         let mut msg = CommonMsg::default();
-        msg.with_meta(buf.split_to(head.meta_size as usize).to_vec());
-        msg.with_payload(buf.to_vec());
+        msg.with_meta(body.split_to(head.meta_size as usize).to_vec());
+        msg.with_payload(body.to_vec());
+        let meta = RpcMeta::parse_from_bytes(&msg.meta)?;
+        msg.chunk_info = meta.chunk_info.into_option();
 
         Ok(msg)
     }
 
     #[instrument(skip_all)]
-    async fn process_request(&self, msg: CommonMsg) -> crate::Result<CommonMsg> {
+    async fn process_request(
+        &self,
+        conn: &Connection,
+        services: &Services,
+        msg: CommonMsg,
+    ) -> crate::Result<Option<CommonMsg>> {
         // request
         let mut meta = RpcMeta::new();
         meta.merge_from_bytes(&msg.meta)?;
+        let identity = match conn.authenticate(meta.authentication_data()).await {
+            Ok(identity) => identity,
+            Err(e) => {
+                warn!("reject request from {}: {e}", conn.remote_addr());
+                return Ok(Some(error_response(ERPCAUTH, e.to_string())?));
+            }
+        };
+        let (compress_type, payload, attachment) = match request_body(&meta, msg.payload) {
+            Ok(body) => body,
+            Err(e) => return Ok(Some(error_response(EREQUEST, e.to_string())?)),
+        };
+        let stream_settings = meta.stream_settings.take();
         let request_meta = meta.request;
         let svc_name = request_meta.service_name();
 
-        let services = &self.0;
-        if !services.contains_key(svc_name) {
-            return Err(SvcErr::NotExist(svc_name.to_string()).into());
-        }
-
         // process
-        let svc = &services[svc_name];
+        let svc = match services.get(svc_name) {
+            Ok(svc) => svc,
+            Err(e) => return Ok(Some(error_response(super::error_code(&e), e.to_string())?)),
+        };
         let method_name = request_meta.method_name();
-        let msg = svc.call_method(method_name, &msg.payload).await?;
+        let mut ctx = Context::new(Metadata::from(request_meta.ext_fields.as_slice()));
+        ctx.set_identity(identity);
+        ctx.set_peer_certificate(conn.peer_certificate());
+        ctx.set_request_compress_type(compress_type);
+        ctx.set_request_attachment(attachment);
+        if let Some(settings) = stream_settings {
+            ctx.set_remote_stream(conn.clone(), settings);
+        }
+        let method = services.method(svc_name, method_name);
+        let res = svc.call_method(&mut ctx, method_name, &payload).await;
+        if method.is_some_and(|m| m.request_talk_type == TalkType::TALK_TYPE_ONEWAY) {
+            if let Err(e) = res {
+                warn!("one-way method {svc_name}.{method_name} err:{e}");
+            }
+            return Ok(None);
+        }
+        let msg = match res {
+            Ok(msg) => msg,
+            Err(e) => return Ok(Some(error_response(super::error_code(&e), e.to_string())?)),
+        };
 
-        // response
+        // response, compressed as the request unless the handler or method says otherwise
+        let compress_type = match ctx.response_compress_type() {
+            Some(compress_type) => compress_type,
+            None => method
+                .map(|m| m.response_compression)
+                .filter(|c| *c != CompressType::COMPRESS_TYPE_NONE)
+                .unwrap_or(compress_type),
+        };
+        let mut resp_meta = RpcResponseMeta::new();
+        resp_meta.set_error_code(0);
         let mut meta = RpcMeta::new();
-        if let Some(resp) = meta.response.as_mut() {
-            resp.set_error_code(0);
+        meta.response = MessageField::some(resp_meta);
+        ctx.response_metadata().write_user_fields(&mut meta)?;
+        meta.set_compress_type(compress_type.value());
+        meta.stream_settings = MessageField::from_option(ctx.take_accepted_stream());
+        let mut payload = compress::compress(compress_type, &msg)?;
+        let attachment = ctx.take_response_attachment();
+        if !attachment.is_empty() {
+            meta.set_attachment_size(attachment.len() as i32);
+            payload.extend_from_slice(&attachment);
         }
-        let meta = meta.write_to_bytes()?;
-        let mut msg = CommonMsg::new(msg);
-        msg.with_meta(meta);
+        let mut msg = CommonMsg::new(payload);
+        msg.with_meta(meta.write_to_bytes()?);
 
-        Ok(msg)
+        Ok(Some(msg))
     }
 
     #[instrument(skip_all)]
     fn pack_response(&self, msg: CommonMsg) -> Vec<u8> {
-        let mut buffer = BytesMut::with_capacity(HEADER_SIZE + msg.body_size() as usize);
-        let head = Header::new(msg.payload_size(), msg.meta_size());
-        buffer.put(head.as_u8_slice().as_slice());
-        buffer.put(msg.to_vec().as_slice());
+        pack_frame(msg)
+    }
 
-        buffer.to_vec()
+    #[instrument(skip_all)]
+    fn pack_response_frames(&self, msg: CommonMsg) -> Frames {
+        let size = match self.chunk_size {
+            Some(size) if msg.payload.len() > size => size,
+            _ => return Box::new(std::iter::once(pack_frame(msg))),
+        };
+        let meta = match RpcMeta::parse_from_bytes(&msg.meta) {
+            Ok(meta) => meta,
+            Err(e) => {
+                warn!("send response in one piece, fail to chunk it:{e}");
+                return Box::new(std::iter::once(pack_frame(msg)));
+            }
+        };
+        pack_chunks(meta, msg.payload, size)
     }
 
     #[instrument(skip_all)]
-    async fn process_response(&self, msg: CommonMsg) -> crate::Result<Vec<u8>> {
-        Ok(msg.payload)
+    async fn process_response(
+        &self,
+        cntl: &mut Controller,
+        msg: CommonMsg,
+    ) -> crate::Result<Vec<u8>> {
+        let meta = RpcMeta::parse_from_bytes(&msg.meta)?;
+        let resp_meta = &meta.response;
+        cntl.set_response_metadata(Metadata::from_user_fields(&meta));
+        if resp_meta.has_real_remote_ip() {
+            let ip = Ipv4Addr::from(resp_meta.real_remote_ip());
+            let port = resp_meta.real_remote_port() as u16;
+            cntl.set_real_remote_addr(SocketAddr::from((ip, port)));
+        }
+        if resp_meta.error_code() != 0 {
+            let text = resp_meta.error_text().to_string();
+            return Err(RpcErr::Failed(resp_meta.error_code(), text).into());
+        }
+        let compress_type = compress::compress_type_from_i32(meta.compress_type())?;
+        cntl.set_response_compress_type(compress_type);
+        if let Some(settings) = meta.stream_settings.as_ref() {
+            cntl.set_response_stream(settings.clone());
+        }
+        let mut payload = msg.payload;
+        cntl.set_response_attachment(split_attachment(&mut payload, meta.attachment_size())?);
+
+        compress::decompress(compress_type, &payload)
     }
 
     #[instrument(skip_all)]
-    fn pack_request(&self, msg: CommonMsg) -> Vec<u8> {
-        let mut buffer = BytesMut::with_capacity(HEADER_SIZE + msg.body_size() as usize);
-        let head = Header::new(msg.payload_size(), msg.meta_size());
-        buffer.put(head.as_u8_slice().as_slice());
-        buffer.put(msg.to_vec().as_slice());
+    fn pack_request(&self, cntl: &Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        let (meta, payload) = request(cntl, msg)?;
+        let mut msg = CommonMsg::new(payload);
+        msg.with_meta(meta.write_to_bytes()?);
 
-        buffer.to_vec()
+        Ok(pack_frame(msg))
     }
+
+    // in chunks if larger than the chunk size of `cntl`
+    #[instrument(skip_all)]
+    fn pack_request_frames(&self, cntl: &Controller, msg: CommonMsg) -> Result<Frames> {
+        let (meta, payload) = request(cntl, msg)?;
+        if let Some(size) = cntl.chunk_size().filter(|size| payload.len() > *size) {
+            meta.check_initialized()?;
+            return Ok(pack_chunks(meta, payload, size));
+        }
+        let mut msg = CommonMsg::new(payload);
+        msg.with_meta(meta.write_to_bytes()?);
+
+        Ok(Box::new(std::iter::once(pack_frame(msg))))
+    }
+}
+
+// the meta and payload of the request `msg` with what `cntl` adds to them
+fn request(cntl: &Controller, mut msg: CommonMsg) -> Result<(RpcMeta, Vec<u8>)> {
+    let mut meta = RpcMeta::parse_from_bytes(&msg.meta)?;
+    meta.request
+        .mut_or_insert_default()
+        .ext_fields
+        .extend(cntl.request_metadata().to_ext_fields());
+    let compress_type = cntl.request_compress_type();
+    if compress_type != CompressType::COMPRESS_TYPE_NONE {
+        meta.set_compress_type(compress_type.value());
+        msg.with_payload(compress::compress(compress_type, &msg.payload)?);
+    }
+    if !cntl.authentication_data().is_empty() {
+        meta.set_authentication_data(cntl.authentication_data().to_vec());
+    }
+    if let Some(settings) = cntl.request_stream() {
+        meta.stream_settings = MessageField::some(settings.clone());
+    }
+    let attachment = cntl.request_attachment();
+    if !attachment.is_empty() {
+        meta.set_attachment_size(attachment.len() as i32);
+        msg.payload.extend_from_slice(attachment);
+    }
+
+    Ok((meta, msg.payload))
+}
+
+// the compress type, payload and attachment of a request, the payload decompressed
+fn request_body(meta: &RpcMeta, mut payload: Vec<u8>) -> Result<(CompressType, Vec<u8>, Vec<u8>)> {
+    let compress_type = compress::compress_type_from_i32(meta.compress_type())?;
+    let attachment = split_attachment(&mut payload, meta.attachment_size())?;
+    let payload = compress::decompress(compress_type, &payload)?;
+    Ok((compress_type, payload, attachment))
+}
+
+fn error_response(code: i32, text: String) -> Result<CommonMsg> {
+    let mut resp_meta = RpcResponseMeta::new();
+    resp_meta.set_error_code(code);
+    resp_meta.set_error_text(text);
+    let mut meta = RpcMeta::new();
+    meta.response = MessageField::some(resp_meta);
+    let mut msg = CommonMsg::default();
+    msg.with_meta(meta.write_to_bytes()?);
+    Ok(msg)
+}
+
+fn pack_frame(msg: CommonMsg) -> Vec<u8> {
+    let mut buffer = BytesMut::with_capacity(HEADER_SIZE + msg.body_size() as usize);
+    let head = Header::new(msg.payload_size(), msg.meta_size());
+    buffer.put(head.as_u8_slice().as_slice());
+    buffer.put(msg.to_vec().as_slice());
+
+    buffer.to_vec()
+}
+
+// every chunk carries `meta`, which has all its required fields, with a
+// `chunk_id` counting down to 0 at the last one, and is framed once it is to be written
+fn pack_chunks(mut meta: RpcMeta, payload: Vec<u8>, size: usize) -> Frames {
+    static NEXT_ID: AtomicI64 = AtomicI64::new(1);
+
+    let stream_id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let size = size.max(1);
+    let last = (payload.len().max(1) - 1) / size;
+    let payload = Bytes::from(payload);
+    Box::new((0..=last).map(move |i| {
+        let mut chunk_info = ChunkInfo::new();
+        chunk_info.set_stream_id(stream_id);
+        chunk_info.set_chunk_id((last - i) as i64);
+        meta.chunk_info = MessageField::some(chunk_info);
+        let end = payload.len().min((i + 1) * size);
+        let mut msg = CommonMsg::new(payload[i * size..end].to_vec());
+        msg.with_meta(meta.write_to_bytes().expect("required fields are set"));
+        pack_frame(msg)
+    }))
+}
+
+// the attachment trails the payload and is never compressed
+pub(crate) fn split_attachment(payload: &mut Vec<u8>, size: i32) -> Result<Vec<u8>> {
+    let at = usize::try_from(size)
+        .ok()
+        .and_then(|size| payload.len().checked_sub(size))
+        .ok_or(ParseErr::InvalidAttachmentSize(size))?;
+    Ok(payload.split_off(at))
 }
 
 #[repr(C)]
@@ -211,3 +371,160 @@ impl Header {
         buf
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use server_kit_protocol::baidu_rpc_meta::RpcRequestMeta;
+
+    use crate::message::Chunks;
+
+    use super::*;
+
+    fn request(svc_name: &str, method_name: &str, payload: &[u8]) -> CommonMsg {
+        let mut req_meta = RpcRequestMeta::new();
+        req_meta.set_service_name(svc_name.to_string());
+        req_meta.set_method_name(method_name.to_string());
+        let mut meta = RpcMeta::new();
+        meta.request = MessageField::some(req_meta);
+        let mut msg = CommonMsg::new(payload.to_vec());
+        msg.with_meta(meta.write_to_bytes().unwrap());
+        msg
+    }
+
+    #[test]
+    fn request_round_trip() {
+        let brpc = Brpc::default();
+        let mut cntl = Controller::new();
+        cntl.request_metadata_mut().insert("user_id", "7");
+        cntl.set_request_attachment(b"att".to_vec());
+        let buf = brpc
+            .pack_request(&cntl, request("test.echo", "m", b"hello"))
+            .unwrap();
+        assert_eq!(buf[..TAG_SIZE], TAG);
+
+        let mut buf = BytesMut::from(&buf[..]);
+        let msg = brpc.parse(&mut buf).unwrap();
+        assert!(buf.is_empty());
+        let meta = RpcMeta::parse_from_bytes(&msg.meta).unwrap();
+        assert_eq!(meta.request.service_name(), "test.echo");
+        assert_eq!(meta.request.method_name(), "m");
+        let md = Metadata::from(meta.request.ext_fields.as_slice());
+        assert_eq!(md.get("user_id"), Some("7"));
+        assert_eq!(meta.attachment_size(), 3);
+        assert_eq!(msg.payload, b"helloatt");
+    }
+
+    #[test]
+    fn parse_partial_and_foreign() {
+        let brpc = Brpc::default();
+        let buf = brpc
+            .pack_request(&Controller::new(), request("s", "m", b"x"))
+            .unwrap();
+        for n in [2, HEADER_SIZE - 1, buf.len() - 1] {
+            let err = brpc.parse(&mut BytesMut::from(&buf[..n])).unwrap_err();
+            assert!(err.to_string().contains("not enough"), "{n}: {err}");
+        }
+        let err = brpc.parse(&mut BytesMut::from(&b"GET / HTTP/1.1"[..]));
+        assert!(err.is_err());
+    }
+
+    fn frames_to_msg(brpc: &Brpc, frames: Frames) -> (usize, CommonMsg) {
+        let mut chunks = Chunks::default();
+        let mut count = 0;
+        for frame in frames {
+            count += 1;
+            let mut buf = BytesMut::from(&frame[..]);
+            let msg = brpc.parse(&mut buf).unwrap();
+            assert!(buf.is_empty());
+            if let Some(msg) = chunks.merge(msg).unwrap() {
+                return (count, msg);
+            }
+        }
+        panic!("incomplete after {count} frames");
+    }
+
+    #[test]
+    fn request_in_chunks() {
+        let brpc = Brpc::default();
+        let mut cntl = Controller::new();
+        cntl.set_chunk_size(4);
+        cntl.set_request_attachment(b"att".to_vec());
+        let frames = brpc
+            .pack_request_frames(&cntl, request("test.echo", "m", b"hello world"))
+            .unwrap();
+        let (count, msg) = frames_to_msg(&brpc, frames);
+        assert_eq!(count, 4);
+        let meta = RpcMeta::parse_from_bytes(&msg.meta).unwrap();
+        assert_eq!(meta.request.method_name(), "m");
+        assert_eq!(meta.attachment_size(), 3);
+        assert_eq!(msg.payload, b"hello worldatt");
+
+        // the plain frame is unchanged by a chunk size it fits in
+        cntl.set_chunk_size(64);
+        let frames = brpc
+            .pack_request_frames(&cntl, request("test.echo", "m", b"hello"))
+            .unwrap();
+        let (count, _) = frames_to_msg(&brpc, frames);
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn request_in_chunks_needs_service() {
+        let mut cntl = Controller::new();
+        cntl.set_chunk_size(1);
+        let msg = CommonMsg::new(b"hello".to_vec());
+        assert!(Brpc::default().pack_request_frames(&cntl, msg).is_err());
+    }
+
+    #[tokio::test]
+    async fn response_in_chunks() {
+        let brpc = Brpc::with_chunk_size(2);
+        let mut meta = RpcMeta::new();
+        meta.response = MessageField::some(RpcResponseMeta::new());
+        let mut msg = CommonMsg::new(b"world".to_vec());
+        msg.with_meta(meta.write_to_bytes().unwrap());
+
+        let (count, msg) = frames_to_msg(&brpc, brpc.pack_response_frames(msg));
+        assert_eq!(count, 3);
+        let payload = brpc
+            .process_response(&mut Controller::new(), msg)
+            .await
+            .unwrap();
+        assert_eq!(payload, b"world");
+    }
+
+    #[tokio::test]
+    async fn response_round_trip() {
+        let brpc = Brpc::default();
+        let mut resp_meta = RpcResponseMeta::new();
+        resp_meta.set_error_code(0);
+        let mut meta = RpcMeta::new();
+        meta.response = MessageField::some(resp_meta);
+        meta.set_attachment_size(1);
+        let md: Metadata = [("seen", "7")].into_iter().collect();
+        md.write_user_fields(&mut meta).unwrap();
+        let mut msg = CommonMsg::new(b"world!".to_vec());
+        msg.with_meta(meta.write_to_bytes().unwrap());
+
+        let mut buf = BytesMut::from(&brpc.pack_response(msg)[..]);
+        let msg = brpc.parse(&mut buf).unwrap();
+        let mut cntl = Controller::new();
+        let payload = brpc.process_response(&mut cntl, msg).await.unwrap();
+        assert_eq!(payload, b"world");
+        assert_eq!(cntl.response_attachment(), b"!");
+        assert_eq!(cntl.response_metadata(), &md);
+    }
+
+    #[tokio::test]
+    async fn error_response_round_trip() {
+        let brpc = Brpc::default();
+        let msg = error_response(ERPCAUTH, "denied".to_string()).unwrap();
+        let mut buf = BytesMut::from(&brpc.pack_response(msg)[..]);
+        let msg = brpc.parse(&mut buf).unwrap();
+        let err = brpc
+            .process_response(&mut Controller::new(), msg)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("denied"), "{err}");
+    }
+}
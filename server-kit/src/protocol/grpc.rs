@@ -0,0 +1,451 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::{BufMut, Bytes, BytesMut};
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use h2::server::SendResponse;
+use h2::RecvStream;
+use http::{HeaderMap, HeaderName, HeaderValue, Request, Response};
+use tracing::{debug, instrument, warn};
+
+use server_kit_protocol::options::{CompressType, ProtocolType};
+
+use super::http::MAX_BODY_SIZE;
+use super::Protocol;
+use crate::error::{AuthErr, CompressErr, HttpErr, ParseErr, RpcErr, SvcErr};
+use crate::message::CommonMsg;
+use crate::socket::{Connection, ConnectionIo};
+use crate::{compress, Context, Controller, Error, Metadata, Result, Services};
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+// compressed flag and length in front of each message
+const PREFIX_SIZE: usize = 5;
+pub(crate) const CONTENT_TYPE: &str = "application/grpc";
+
+/// Status codes of grpc, carried by `grpc-status`.
+pub mod status {
+    pub const OK: i32 = 0;
+    pub const UNKNOWN: i32 = 2;
+    pub const INVALID_ARGUMENT: i32 = 3;
+    pub const DEADLINE_EXCEEDED: i32 = 4;
+    pub const RESOURCE_EXHAUSTED: i32 = 8;
+    pub const UNIMPLEMENTED: i32 = 12;
+    pub const INTERNAL: i32 = 13;
+    pub const UNAUTHENTICATED: i32 = 16;
+}
+
+/// Unary grpc over HTTP/2 without TLS (h2c), whose clients start right away
+/// with the connection preface. Requests of `/package.Service/Method` go to
+/// the service of the full name.
+///
+/// A message is a grpc message, prefixed by its compressed flag and length.
+pub struct Grpc;
+
+#[async_trait]
+impl Protocol for Grpc {
+    fn default() -> Self {
+        Grpc
+    }
+    fn protocol_type(&self) -> ProtocolType {
+        ProtocolType::PROTOCOL_H2
+    }
+    fn name(&self) -> &'static str {
+        "h2"
+    }
+
+    /// Recognizes the connection preface, leaving it in `buf` for `serve_connection`.
+    fn parse(&self, buf: &mut BytesMut) -> Result<CommonMsg> {
+        let n = buf.len().min(PREFACE.len());
+        if buf[..n] != PREFACE[..n] {
+            return Err(ParseErr::TryOther.into());
+        }
+        if n < PREFACE.len() {
+            return Err(ParseErr::NotEnoughData.into());
+        }
+        Ok(CommonMsg::default())
+    }
+
+    // requests are served by `serve_connection`
+    async fn process_request(
+        &self,
+        _conn: &Connection,
+        _services: &Services,
+        _msg: CommonMsg,
+    ) -> Result<Option<CommonMsg>> {
+        Ok(None)
+    }
+
+    fn pack_response(&self, msg: CommonMsg) -> Vec<u8> {
+        msg.to_vec()
+    }
+
+    fn is_multiplexed(&self) -> bool {
+        true
+    }
+    fn supports_authentication(&self) -> bool {
+        true
+    }
+
+    #[instrument(skip_all)]
+    async fn serve_connection(
+        &self,
+        conn: &Connection,
+        services: &Services,
+        io: ConnectionIo<'_>,
+    ) -> Result<()> {
+        let mut h2 = h2::server::handshake(io).await?;
+        let mut calls = FuturesUnordered::new();
+        loop {
+            tokio::select! {
+                req = h2.accept() => match req {
+                    Some(req) => {
+                        let (req, respond) = req?;
+                        calls.push(handle(conn, services, req, respond));
+                    }
+                    None => break,
+                },
+                Some(()) = calls.next(), if !calls.is_empty() => {}
+            }
+        }
+        debug!("h2 connection closed");
+
+        Ok(())
+    }
+
+    /// Frame the request message, compressed by the request compress type.
+    fn pack_request(&self, cntl: &Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        let compress_type = cntl.request_compress_type();
+        encoding_name(compress_type)?;
+        let payload = compress::compress(compress_type, &msg.payload)?;
+        Ok(frame(
+            &payload,
+            compress_type != CompressType::COMPRESS_TYPE_NONE,
+        ))
+    }
+
+    /// Unframe the response message, compressed by the response compress type.
+    async fn process_response(&self, cntl: &mut Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        unframe(&msg.payload, cntl.response_compress_type())
+    }
+}
+
+async fn handle(
+    conn: &Connection,
+    services: &Services,
+    req: Request<RecvStream>,
+    respond: SendResponse<Bytes>,
+) {
+    let path = req.uri().path().to_string();
+    let res = call(conn, services, req).await;
+    if let Err(e) = &res {
+        debug!("{path} err:{e}");
+    }
+    if let Err(e) = send_response(respond, res) {
+        warn!("send response of {path} err:{e}");
+    }
+}
+
+async fn call(
+    conn: &Connection,
+    services: &Services,
+    req: Request<RecvStream>,
+) -> Result<(Metadata, Vec<u8>)> {
+    let (parts, mut body) = req.into_parts();
+    let path = parts.uri.path();
+    let (svc_name, method_name) = path
+        .trim_start_matches('/')
+        .split_once('/')
+        .ok_or_else(|| SvcErr::NotExist(path.to_string()))?;
+    let svc = services.get(svc_name)?;
+    let headers = to_metadata(&parts.headers);
+    let compress_type = compress_type(headers.get("grpc-encoding"))?;
+    let timeout = headers.get("grpc-timeout").and_then(parse_timeout);
+    let accept_encoding = headers.get("grpc-accept-encoding").map(String::from);
+    let auth_data = headers.get("authorization").unwrap_or_default().as_bytes();
+    let identity = conn.authenticate(auth_data).await?;
+
+    let req = unframe(&read_body(&mut body).await?, compress_type)?;
+    let mut ctx = Context::new(headers);
+    ctx.set_identity(identity);
+    ctx.set_peer_certificate(conn.peer_certificate());
+    ctx.set_request_compress_type(compress_type);
+    let call = svc.call_method(&mut ctx, method_name, &req);
+    let resp = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, call)
+            .await
+            .map_err(|_| RpcErr::Timeout(timeout))??,
+        None => call.await?,
+    };
+
+    // compressed as the request unless the handler or method says otherwise,
+    // if the client accepts the encoding
+    let compress_type = match ctx.response_compress_type() {
+        Some(compress_type) => compress_type,
+        None => services
+            .method(svc_name, method_name)
+            .map(|m| m.response_compression)
+            .filter(|c| *c != CompressType::COMPRESS_TYPE_NONE)
+            .unwrap_or(compress_type),
+    };
+    let mut metadata = ctx.response_metadata().clone();
+    let encoding = encoding_name(compress_type).ok().flatten().filter(|name| {
+        accept_encoding
+            .iter()
+            .flat_map(|accept| accept.split(','))
+            .any(|accept| accept.trim() == *name)
+    });
+    let resp = match encoding {
+        Some(encoding) => {
+            metadata.insert("grpc-encoding", encoding);
+            frame(&compress::compress(compress_type, &resp)?, true)
+        }
+        None => frame(&resp, false),
+    };
+
+    Ok((metadata, resp))
+}
+
+fn send_response(mut respond: SendResponse<Bytes>, res: Result<(Metadata, Vec<u8>)>) -> Result<()> {
+    let mut head = Response::new(());
+    let headers = head.headers_mut();
+    headers.insert("content-type", HeaderValue::from_static(CONTENT_TYPE));
+    match res {
+        Ok((metadata, body)) => {
+            insert_headers(headers, &metadata);
+            let mut stream = respond.send_response(head, false)?;
+            stream.send_data(body.into(), false)?;
+            stream.send_trailers(status_headers(status::OK, ""))?;
+        }
+        // trailers-only
+        Err(e) => {
+            headers.extend(status_headers(status_of(&e), &message_of(&e)));
+            respond.send_response(head, true)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn status_of(err: &Error) -> i32 {
+    match err {
+        Error::Svc(SvcErr::NotExist(_)) => status::UNIMPLEMENTED,
+        Error::Compress(CompressErr::UnknownEncoding(_)) => status::UNIMPLEMENTED,
+        Error::Rpc(RpcErr::Timeout(_)) => status::DEADLINE_EXCEEDED,
+        Error::Rpc(RpcErr::Failed(code, _)) if (1..=16).contains(code) => *code,
+        Error::Auth(AuthErr::Rejected(_)) => status::UNAUTHENTICATED,
+        Error::Http(HttpErr::BodyTooLarge(_)) => status::RESOURCE_EXHAUSTED,
+        Error::Http(_) => status::INTERNAL,
+        Error::PbErr(_) => status::INVALID_ARGUMENT,
+        _ => status::UNKNOWN,
+    }
+}
+
+// the text of a failure of the handler, whose code goes in grpc-status
+fn message_of(err: &Error) -> String {
+    match err {
+        Error::Rpc(RpcErr::Failed(_, text)) => text.clone(),
+        _ => err.to_string(),
+    }
+}
+
+fn status_headers(code: i32, message: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("grpc-status", HeaderValue::from(code));
+    if !message.is_empty() {
+        // always valid once percent-encoded
+        let message = HeaderValue::from_str(&percent_encode(message)).unwrap();
+        headers.insert("grpc-message", message);
+    }
+    headers
+}
+
+pub(crate) fn insert_headers(headers: &mut HeaderMap, metadata: &Metadata) {
+    for (name, value) in metadata.iter() {
+        match (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            (Ok(name), Ok(value)) => {
+                headers.insert(name, value);
+            }
+            _ => warn!(name, "skip invalid header"),
+        }
+    }
+}
+
+pub(crate) fn to_metadata(headers: &HeaderMap) -> Metadata {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+            (name.as_str(), value)
+        })
+        .collect()
+}
+
+/// The whole body, failing once it outgrows the bodies of HTTP/1.
+pub(crate) async fn read_body(body: &mut RecvStream) -> Result<Vec<u8>> {
+    let mut data = vec![];
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        let _ = body.flow_control().release_capacity(chunk.len());
+        if chunk.len() > MAX_BODY_SIZE - data.len() {
+            return Err(HttpErr::BodyTooLarge(MAX_BODY_SIZE).into());
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+fn frame(msg: &[u8], compressed: bool) -> Vec<u8> {
+    let mut buf = BytesMut::with_capacity(PREFIX_SIZE + msg.len());
+    buf.put_u8(compressed as u8);
+    buf.put_u32(msg.len() as u32);
+    buf.put(msg);
+    buf.to_vec()
+}
+
+// exactly one message, as calls are unary
+fn unframe(data: &[u8], compress_type: CompressType) -> Result<Vec<u8>> {
+    if data.len() < PREFIX_SIZE {
+        return Err(HttpErr::InvalidBody("no grpc message".to_string()).into());
+    }
+    let size = u32::from_be_bytes(data[1..PREFIX_SIZE].try_into().unwrap()) as usize;
+    if data.len() != PREFIX_SIZE + size {
+        let text = format!("grpc message of {size} bytes in {} bytes", data.len());
+        return Err(HttpErr::InvalidBody(text).into());
+    }
+    let msg = &data[PREFIX_SIZE..];
+    match data[0] {
+        0 => Ok(msg.to_vec()),
+        _ => compress::decompress(compress_type, msg),
+    }
+}
+
+pub(crate) fn compress_type(encoding: Option<&str>) -> Result<CompressType> {
+    match encoding {
+        None | Some("identity") => Ok(CompressType::COMPRESS_TYPE_NONE),
+        Some("gzip") => Ok(CompressType::COMPRESS_TYPE_GZIP),
+        Some("deflate") => Ok(CompressType::COMPRESS_TYPE_ZLIB),
+        Some(encoding) => Err(CompressErr::UnknownEncoding(encoding.to_string()).into()),
+    }
+}
+
+/// Encodings of responses the channel takes, for `grpc-accept-encoding`.
+pub(crate) const ACCEPT_ENCODING: &str = "gzip,deflate";
+
+pub(crate) fn encoding_name(compress_type: CompressType) -> Result<Option<&'static str>> {
+    match compress_type {
+        CompressType::COMPRESS_TYPE_NONE => Ok(None),
+        CompressType::COMPRESS_TYPE_GZIP => Ok(Some("gzip")),
+        CompressType::COMPRESS_TYPE_ZLIB => Ok(Some("deflate")),
+        ty => Err(CompressErr::UnknownEncoding(format!("{ty:?}")).into()),
+    }
+}
+
+/// `grpc-timeout` of `timeout`, in the finest unit within the 8 digits allowed.
+pub(crate) fn format_timeout(timeout: Duration) -> String {
+    let units = [
+        (timeout.as_nanos(), 'n'),
+        (timeout.as_micros(), 'u'),
+        (timeout.as_millis(), 'm'),
+        (timeout.as_secs() as u128, 'S'),
+    ];
+    let (value, unit) = units
+        .into_iter()
+        .find(|(value, _)| *value < 100_000_000)
+        .unwrap_or((timeout.as_secs() as u128 / 3600, 'H'));
+    format!("{value}{unit}")
+}
+
+// none if invalid, or too long to be told from no deadline
+fn parse_timeout(value: &str) -> Option<Duration> {
+    if !value.is_ascii() {
+        return None;
+    }
+    let (value, unit) = value.split_at(value.len().checked_sub(1)?);
+    let value: u64 = value.parse().ok()?;
+    match unit {
+        "H" => value.checked_mul(3600).map(Duration::from_secs),
+        "M" => value.checked_mul(60).map(Duration::from_secs),
+        "S" => Some(Duration::from_secs(value)),
+        "m" => Some(Duration::from_millis(value)),
+        "u" => Some(Duration::from_micros(value)),
+        "n" => Some(Duration::from_nanos(value)),
+        _ => None,
+    }
+}
+
+fn percent_encode(message: &str) -> String {
+    let mut encoded = String::with_capacity(message.len());
+    for byte in message.bytes() {
+        match byte {
+            b' '..=b'~' if byte != b'%' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+pub(crate) fn percent_decode(message: &str) -> String {
+    let bytes = message.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeout_round_trip() {
+        for timeout in [
+            Duration::from_nanos(42),
+            Duration::from_millis(1500),
+            Duration::from_secs(3600 * 24),
+        ] {
+            assert_eq!(parse_timeout(&format_timeout(timeout)), Some(timeout));
+        }
+        assert_eq!(parse_timeout("2H"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_timeout("3M"), Some(Duration::from_secs(180)));
+    }
+
+    #[test]
+    fn invalid_timeout() {
+        for value in ["", "H", "5", "5x", "-5S", "5\u{fffd}", "\u{fffd}", "5é"] {
+            assert_eq!(parse_timeout(value), None, "{value:?}");
+        }
+        assert_eq!(parse_timeout(&format!("{}H", u64::MAX)), None);
+        assert_eq!(parse_timeout(&format!("{}M", u64::MAX / 2)), None);
+    }
+
+    #[test]
+    fn frame_round_trip() {
+        let msg = b"hello".repeat(10);
+        assert_eq!(
+            unframe(&frame(&msg, false), CompressType::COMPRESS_TYPE_NONE).unwrap(),
+            msg
+        );
+        let gzip = CompressType::COMPRESS_TYPE_GZIP;
+        let compressed = frame(&compress::compress(gzip, &msg).unwrap(), true);
+        assert_eq!(unframe(&compressed, gzip).unwrap(), msg);
+        assert!(unframe(&compressed[..compressed.len() - 1], gzip).is_err());
+    }
+}
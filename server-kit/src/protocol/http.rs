@@ -0,0 +1,754 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use httparse::Status;
+use protobuf::reflect::MessageDescriptor;
+use tracing::{debug, instrument, warn};
+
+use server_kit_protocol::options::ProtocolType;
+
+use super::Protocol;
+use crate::error::{HttpErr, ParseErr, RpcErr, SvcErr};
+use crate::message::CommonMsg;
+use crate::socket::Connection;
+use crate::{Context, Controller, Error, Metadata, Result, Services};
+
+// more headers than this and the message is taken as malformed
+const MAX_HEADERS: usize = 64;
+pub(crate) const MAX_BODY_SIZE: usize = 64 << 20;
+const METHODS: [&[u8]; 7] = [
+    b"GET ",
+    b"POST ",
+    b"PUT ",
+    b"DELETE ",
+    b"HEAD ",
+    b"PATCH ",
+    b"OPTIONS ",
+];
+const VERSION: &[u8] = b"HTTP/";
+
+/// HTTP/1.1, which serves `POST /ServiceName/MethodName` with a json or
+/// protobuf body as a call of the method, as well as the paths of restful mappings.
+///
+/// The meta of a message is its start line and headers, the payload its body.
+pub struct Http;
+
+/// How the body of a call of a method over http is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyFormat {
+    Json,
+    Proto,
+}
+
+impl BodyFormat {
+    fn from_mime(value: &str) -> Option<Self> {
+        let mime = value.split(';').next().unwrap_or_default().trim();
+        match mime {
+            "application/json" => Some(BodyFormat::Json),
+            "application/proto" | "application/protobuf" | "application/x-protobuf" => {
+                Some(BodyFormat::Proto)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            BodyFormat::Json => "application/json",
+            BodyFormat::Proto => "application/proto",
+        }
+    }
+}
+
+/// A request sent by `Channel<Http>`.
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    method: String,
+    uri: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpRequest {
+    /// A request of `uri`, either a path or an absolute url giving the `Host` header.
+    pub fn new(method: impl Into<String>, uri: &str) -> Self {
+        let (host, uri) = match uri.split_once("://") {
+            Some((_, rest)) => match rest.find('/') {
+                Some(idx) => (Some(&rest[..idx]), &rest[idx..]),
+                None => (Some(rest), "/"),
+            },
+            None => (None, uri),
+        };
+        let req = Self {
+            method: method.into(),
+            uri: uri.to_string(),
+            headers: vec![],
+            body: vec![],
+        };
+        match host {
+            Some(host) => req.header("Host", host),
+            None => req,
+        }
+    }
+
+    pub fn get(uri: &str) -> Self {
+        Self::new("GET", uri)
+    }
+
+    pub fn post(uri: &str, body: impl Into<Vec<u8>>) -> Self {
+        Self::new("POST", uri).body(body)
+    }
+
+    /// A call of a method of a server-kit service served over http.
+    pub fn call_method(
+        svc_name: &str,
+        method_name: &str,
+        format: BodyFormat,
+        body: impl Into<Vec<u8>>,
+    ) -> Self {
+        Self::post(&format!("/{svc_name}/{method_name}"), body)
+            .header("Content-Type", format.content_type())
+            .header("Accept", format.content_type())
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn has_header(&self, name: &str) -> bool {
+        self.headers
+            .iter()
+            .any(|(n, _)| n.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Fails if the method, uri or a header would break the head into other lines.
+impl TryFrom<HttpRequest> for CommonMsg {
+    type Error = Error;
+
+    fn try_from(req: HttpRequest) -> Result<Self> {
+        if [&req.method, &req.uri]
+            .iter()
+            .any(|s| s.is_empty() || s.contains([' ', '\r', '\n']))
+        {
+            let line = format!("{} {}", req.method, req.uri);
+            return Err(HttpErr::InvalidRequestLine(line).into());
+        }
+        let mut head = format!("{} {} HTTP/1.1\r\n", req.method, req.uri);
+        for (name, value) in &req.headers {
+            head.push_str(&header_line(name, value)?);
+        }
+        let mut msg = CommonMsg::new(req.body);
+        msg.with_meta(head.into_bytes());
+        Ok(msg)
+    }
+}
+
+/// A response to an `HttpRequest`, with header names in lowercase.
+#[derive(Debug, Default, Clone)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Metadata,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// The body of a 2xx response, the status and the body as the error text otherwise.
+    pub fn into_success(self) -> Result<Vec<u8>> {
+        if !self.is_success() {
+            let text = String::from_utf8_lossy(&self.body).into_owned();
+            return Err(RpcErr::Failed(self.status as i32, text).into());
+        }
+        Ok(self.body)
+    }
+}
+
+/// `msg` as a response, with its status and headers kept in `cntl` too.
+pub(crate) fn read_response(cntl: &mut Controller, msg: CommonMsg) -> Result<HttpResponse> {
+    let (status, headers) = response_head(&msg.meta)?;
+    cntl.set_http_status(status);
+    cntl.set_response_metadata(headers.clone());
+    Ok(HttpResponse {
+        status,
+        headers,
+        body: msg.payload,
+    })
+}
+
+#[async_trait]
+impl Protocol for Http {
+    fn default() -> Self {
+        Http
+    }
+    fn protocol_type(&self) -> ProtocolType {
+        ProtocolType::PROTOCOL_HTTP
+    }
+    fn name(&self) -> &'static str {
+        "http"
+    }
+    fn supports_authentication(&self) -> bool {
+        true
+    }
+
+    #[instrument(skip_all)]
+    fn parse(&self, buf: &mut BytesMut) -> Result<CommonMsg> {
+        parse_message(buf, false)
+    }
+
+    // the response to a HEAD request has the headers a GET would get, but no body
+    #[instrument(skip_all)]
+    fn parse_response(&self, buf: &mut BytesMut, request: &[u8]) -> Result<CommonMsg> {
+        parse_message(buf, request.starts_with(b"HEAD "))
+    }
+
+    #[instrument(skip_all)]
+    async fn process_request(
+        &self,
+        conn: &Connection,
+        services: &Services,
+        msg: CommonMsg,
+    ) -> Result<Option<CommonMsg>> {
+        let (method, uri, headers) = request_head(&msg.meta)?;
+        let request_format = headers
+            .get("content-type")
+            .and_then(BodyFormat::from_mime)
+            .unwrap_or(BodyFormat::Json);
+        // the format asked for by Accept, or the one of the request
+        let response_format = headers
+            .get("accept")
+            .and_then(|accept| accept.split(',').find_map(BodyFormat::from_mime))
+            .unwrap_or(request_format);
+
+        let identity = match conn
+            .authenticate(headers.get("authorization").unwrap_or_default().as_bytes())
+            .await
+        {
+            Ok(identity) => identity,
+            Err(e) => {
+                warn!("reject request from {}: {e}", conn.remote_addr());
+                return Ok(Some(error_response(401, e)));
+            }
+        };
+        let mut ctx = Context::new(headers);
+        ctx.set_identity(identity);
+        ctx.set_peer_certificate(conn.peer_certificate());
+
+        let res = call(
+            services,
+            &mut ctx,
+            &method,
+            &uri,
+            (request_format, response_format),
+            &msg.payload,
+        )
+        .await;
+        let msg = match res {
+            Ok(body) => response(
+                200,
+                response_format.content_type(),
+                ctx.response_metadata(),
+                body,
+            ),
+            Err(e) => {
+                debug!("{method} {uri} err:{e}");
+                error_response(status_of(&e), e)
+            }
+        };
+
+        Ok(Some(msg))
+    }
+
+    fn pack_response(&self, msg: CommonMsg) -> Vec<u8> {
+        pack_message(msg)
+    }
+
+    /// Fails on statuses other than 2xx, with the body as the error text.
+    #[instrument(skip_all)]
+    async fn process_response(&self, cntl: &mut Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        read_response(cntl, msg)?.into_success()
+    }
+
+    /// Requests without a `Host` get the one of `server`.
+    fn address_request<'a>(&self, frames: &'a [Vec<u8>], server: &str) -> Cow<'a, [Vec<u8>]> {
+        let head = match frames.first() {
+            Some(head) => head,
+            None => return Cow::Borrowed(frames),
+        };
+        let head_end = head
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .unwrap_or(head.len());
+        let mut lines = head[..head_end].split(|b| *b == b'\n');
+        let line_end = lines.next().map_or(0, |line| line.len() + 1);
+        let has_host = lines.any(|line| {
+            line.get(..5)
+                .is_some_and(|name| name.eq_ignore_ascii_case(b"host:"))
+        });
+        if has_host || line_end > head.len() {
+            return Cow::Borrowed(frames);
+        }
+
+        let mut addressed = frames.to_vec();
+        let host = format!("Host: {server}\r\n");
+        addressed[0].splice(line_end..line_end, host.bytes());
+        Cow::Owned(addressed)
+    }
+
+    /// The meta of `msg` is taken as the request line and headers, to which
+    /// the request metadata, the credential and `Content-Length` are added.
+    fn pack_request(&self, cntl: &Controller, mut msg: CommonMsg) -> Result<Vec<u8>> {
+        let mut head = String::new();
+        for (name, value) in cntl.request_metadata().iter() {
+            head.push_str(&header_line(name, value)?);
+        }
+        if !cntl.authentication_data().is_empty() {
+            let credential = String::from_utf8_lossy(cntl.authentication_data());
+            head.push_str(&header_line("Authorization", &credential)?);
+        }
+        msg.meta.extend_from_slice(head.as_bytes());
+        Ok(pack_message(msg))
+    }
+}
+
+async fn call(
+    services: &Services,
+    ctx: &mut Context,
+    method: &str,
+    uri: &str,
+    (request_format, response_format): (BodyFormat, BodyFormat),
+    body: &[u8],
+) -> Result<Vec<u8>> {
+    let (path, query) = uri.split_once('?').unwrap_or((uri, ""));
+    ctx.set_query_params(parse_query(query));
+    let (svc_name, method_name) = match services.resolve_restful(path) {
+        Some((svc_name, method_name, unresolved)) => {
+            ctx.set_unresolved_path(unresolved.to_string());
+            (svc_name, method_name)
+        }
+        None => {
+            // a GET is a call without a request body
+            if method != "POST" && method != "GET" {
+                return Err(HttpErr::MethodNotAllowed(method.to_string()).into());
+            }
+            let (svc_name, method_name) = path
+                .trim_start_matches('/')
+                .split_once('/')
+                .ok_or_else(|| SvcErr::NotExist(path.to_string()))?;
+            let svc_name = services.full_name(svc_name)?;
+            // those mapped are only reached by their mappings
+            if services.has_restful_mappings(svc_name) {
+                return Err(SvcErr::NotExist(path.to_string()).into());
+            }
+            (svc_name, method_name)
+        }
+    };
+    let svc = services.get(svc_name)?;
+    let method = services.method(svc_name, method_name);
+    let full_method_name = format!("{svc_name}.{method_name}");
+
+    // an empty body is an empty message in either format
+    let req = match request_format {
+        _ if body.is_empty() => vec![],
+        BodyFormat::Json => {
+            let ty = method.and_then(|m| m.request_type.as_ref());
+            json_to_pb(ty, &full_method_name, body)?
+        }
+        BodyFormat::Proto => body.to_vec(),
+    };
+    let resp = svc.call_method(ctx, method_name, &req).await?;
+    match response_format {
+        BodyFormat::Json => {
+            let ty = method.and_then(|m| m.response_type.as_ref());
+            pb_to_json(ty, &full_method_name, &resp)
+        }
+        BodyFormat::Proto => Ok(resp),
+    }
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+// '+' is a space in query strings
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn json_to_pb(ty: Option<&MessageDescriptor>, name: &str, body: &[u8]) -> Result<Vec<u8>> {
+    let ty = ty.ok_or_else(|| HttpErr::NoMessageType(name.to_string()))?;
+    let json = std::str::from_utf8(body).map_err(|e| HttpErr::InvalidBody(e.to_string()))?;
+    let msg = protobuf_json_mapping::parse_dyn_from_str(ty, json).map_err(HttpErr::from)?;
+    // fails on missing required fields
+    let req = msg
+        .write_to_bytes_dyn()
+        .map_err(|e| HttpErr::InvalidBody(e.to_string()))?;
+    Ok(req)
+}
+
+fn pb_to_json(ty: Option<&MessageDescriptor>, name: &str, body: &[u8]) -> Result<Vec<u8>> {
+    let ty = ty.ok_or_else(|| HttpErr::NoMessageType(name.to_string()))?;
+    let msg = ty.parse_from_bytes(body)?;
+    let json = protobuf_json_mapping::print_to_string(&*msg).map_err(HttpErr::from)?;
+    Ok(json.into_bytes())
+}
+
+/// Cut a message off the front of `buf`, whose body is left out for
+/// responses to HEAD requests, as `head_request` tells.
+fn parse_message(buf: &mut BytesMut, head_request: bool) -> Result<CommonMsg> {
+    let sniffed = METHODS.iter().chain([&VERSION]).any(|token| {
+        let n = buf.len().min(token.len());
+        buf[..n] == token[..n]
+    });
+    if !sniffed {
+        return Err(ParseErr::TryOther.into());
+    }
+
+    let (head_size, body) = match parse_head(buf, head_request)? {
+        Some(res) => res,
+        None => return Err(ParseErr::NotEnoughData.into()),
+    };
+    let (body_size, payload) = match body {
+        Body::Sized(size) if buf.len() < head_size + size => {
+            return Err(ParseErr::NotEnoughData.into())
+        }
+        Body::Sized(size) => (size, buf[head_size..head_size + size].to_vec()),
+        Body::Chunked => match decode_chunked(&buf[head_size..])? {
+            Some(res) => res,
+            None => return Err(ParseErr::NotEnoughData.into()),
+        },
+    };
+    debug!(head_size, body_size, "finish to parse http message");
+
+    let mut msg = CommonMsg::new(payload);
+    msg.with_meta(buf.split_to(head_size).to_vec());
+    let _ = buf.split_to(body_size);
+
+    Ok(msg)
+}
+
+enum Body {
+    Sized(usize),
+    Chunked,
+}
+
+/// Size of the start line and headers at the front of `buf` and how the body
+/// after them is delimited, none if they are incomplete.
+fn parse_head(buf: &[u8], head_request: bool) -> Result<Option<(usize, Body)>> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let (status, code) = match buf.starts_with(VERSION) {
+        true => {
+            let mut resp = httparse::Response::new(&mut headers);
+            (resp.parse(buf).map_err(HttpErr::from)?, resp.code)
+        }
+        false => {
+            let status = httparse::Request::new(&mut headers)
+                .parse(buf)
+                .map_err(HttpErr::from)?;
+            (status, None)
+        }
+    };
+    let head_size = match status {
+        Status::Complete(size) => size,
+        Status::Partial => return Ok(None),
+    };
+
+    // responses without a body of their own
+    if head_request || code.is_some_and(|code| code < 200 || code == 204 || code == 304) {
+        return Ok(Some((head_size, Body::Sized(0))));
+    }
+    let mut body = Body::Sized(0);
+    for header in headers.iter().take_while(|h| !h.name.is_empty()) {
+        if header.name.eq_ignore_ascii_case("transfer-encoding") {
+            if header.value.eq_ignore_ascii_case(b"chunked") {
+                return Ok(Some((head_size, Body::Chunked)));
+            }
+        } else if header.name.eq_ignore_ascii_case("content-length") {
+            let value = String::from_utf8_lossy(header.value);
+            let size = value
+                .trim()
+                .parse()
+                .map_err(|_| HttpErr::InvalidBody(format!("content-length {value}")))?;
+            if size > MAX_BODY_SIZE {
+                return Err(HttpErr::BodyTooLarge(MAX_BODY_SIZE).into());
+            }
+            body = Body::Sized(size);
+        }
+    }
+
+    Ok(Some((head_size, body)))
+}
+
+/// Size of the chunked body at the front of `buf` and its data, none if it is incomplete.
+fn decode_chunked(buf: &[u8]) -> Result<Option<(usize, Vec<u8>)>> {
+    let mut pos = 0;
+    let mut data = vec![];
+    loop {
+        let (n, size) = match httparse::parse_chunk_size(&buf[pos..]) {
+            Ok(Status::Complete(res)) => res,
+            Ok(Status::Partial) => return Ok(None),
+            Err(_) => return Err(HttpErr::InvalidBody("chunk size".to_string()).into()),
+        };
+        pos += n;
+        if size == 0 {
+            // the last chunk, followed by optional trailers and an empty line
+            let rest = &buf[pos..];
+            if rest.starts_with(b"\r\n") {
+                return Ok(Some((pos + 2, data)));
+            }
+            return Ok(rest
+                .windows(4)
+                .position(|w| w == b"\r\n\r\n")
+                .map(|end| (pos + end + 4, data)));
+        }
+        if size > (MAX_BODY_SIZE - data.len()) as u64 {
+            return Err(HttpErr::BodyTooLarge(MAX_BODY_SIZE).into());
+        }
+        let size = size as usize;
+        if buf.len() < pos + size + 2 {
+            return Ok(None);
+        }
+        data.extend_from_slice(&buf[pos..pos + size]);
+        pos += size + 2;
+    }
+}
+
+/// Method, uri and headers of a request head, with header names in lowercase.
+fn request_head(meta: &[u8]) -> Result<(String, String, Metadata)> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut req = httparse::Request::new(&mut headers);
+    req.parse(meta).map_err(HttpErr::from)?;
+    let method = req.method.unwrap_or_default().to_string();
+    let uri = req.path.unwrap_or_default().to_string();
+    Ok((method, uri, to_metadata(req.headers)))
+}
+
+/// Status code and headers of a response head, with header names in lowercase.
+fn response_head(meta: &[u8]) -> Result<(u16, Metadata)> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut resp = httparse::Response::new(&mut headers);
+    resp.parse(meta).map_err(HttpErr::from)?;
+    Ok((resp.code.unwrap_or_default(), to_metadata(resp.headers)))
+}
+
+// repeated headers are joined by commas
+fn to_metadata(headers: &[httparse::Header]) -> Metadata {
+    let mut metadata = Metadata::new();
+    for header in headers {
+        let name = header.name.to_ascii_lowercase();
+        let value = String::from_utf8_lossy(header.value);
+        let value = match metadata.remove(&name) {
+            Some(prev) => format!("{prev}, {value}"),
+            None => value.into_owned(),
+        };
+        metadata.insert(name, value);
+    }
+    metadata
+}
+
+fn status_of(err: &Error) -> u16 {
+    match err {
+        Error::Svc(SvcErr::NotExist(_)) => 404,
+        Error::Http(HttpErr::MethodNotAllowed(_)) => 405,
+        Error::Http(HttpErr::NoMessageType(_)) => 415,
+        Error::Http(_) => 400,
+        _ => 500,
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        415 => "Unsupported Media Type",
+        _ => "Internal Server Error",
+    }
+}
+
+fn response(status: u16, content_type: &str, headers: &Metadata, body: Vec<u8>) -> CommonMsg {
+    let mut head = format!(
+        "HTTP/1.1 {status} {}\r\nContent-Type: {content_type}\r\n",
+        reason(status)
+    );
+    for (name, value) in headers.iter() {
+        match header_line(name, value) {
+            Ok(line) => head.push_str(&line),
+            Err(e) => warn!("leave out response header, {e}"),
+        }
+    }
+    let mut msg = CommonMsg::new(body);
+    msg.with_meta(head.into_bytes());
+    msg
+}
+
+// fails on names or values which would end the line early or make another
+// header, and on names which aren't a token
+fn header_line(name: &str, value: &str) -> Result<String> {
+    let is_token = !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_graphic() && !b"\"(),/:;<=>?@[\\]{}".contains(&b));
+    if !is_token || value.contains(['\r', '\n']) {
+        return Err(HttpErr::InvalidHeader(name.to_string()).into());
+    }
+    Ok(format!("{name}: {value}\r\n"))
+}
+
+fn error_response(status: u16, err: Error) -> CommonMsg {
+    let body = format!("{err}\n").into_bytes();
+    response(status, "text/plain", &Metadata::new(), body)
+}
+
+// the meta lacks the `Content-Length` and the empty line ending the head
+fn pack_message(msg: CommonMsg) -> Vec<u8> {
+    let mut buf = msg.meta;
+    buf.extend_from_slice(format!("Content-Length: {}\r\n\r\n", msg.payload.len()).as_bytes());
+    buf.extend_from_slice(&msg.payload);
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_err(buf: &[u8]) -> Error {
+        Http.parse(&mut BytesMut::from(buf)).unwrap_err()
+    }
+
+    #[test]
+    fn request_round_trip() {
+        let req = HttpRequest::post("http://example.com/a?b=1", "body").header("X-Id", "7");
+        let mut cntl = Controller::new();
+        cntl.request_metadata_mut().insert("user_id", "42");
+        let buf = Http
+            .pack_request(&cntl, CommonMsg::try_from(req).unwrap())
+            .unwrap();
+
+        let mut buf = BytesMut::from(&buf[..]);
+        let msg = Http.parse(&mut buf).unwrap();
+        assert!(buf.is_empty());
+        assert_eq!(msg.payload, b"body");
+        let (method, uri, headers) = request_head(&msg.meta).unwrap();
+        assert_eq!((method.as_str(), uri.as_str()), ("POST", "/a?b=1"));
+        assert_eq!(headers.get("host"), Some("example.com"));
+        assert_eq!(headers.get("x-id"), Some("7"));
+        assert_eq!(headers.get("user_id"), Some("42"));
+    }
+
+    #[test]
+    fn reject_line_breaks() {
+        let req = HttpRequest::get("/").header("X-Id", "7\r\nX-Admin: 1");
+        assert!(CommonMsg::try_from(req).is_err());
+        let req = HttpRequest::get("/").header("X-Id\r\nX-Admin", "1");
+        assert!(CommonMsg::try_from(req).is_err());
+        let req = HttpRequest::get("/ HTTP/1.1\r\nX-Admin: 1\r\n");
+        assert!(CommonMsg::try_from(req).is_err());
+
+        let msg = CommonMsg::try_from(HttpRequest::get("/")).unwrap();
+        let mut cntl = Controller::new();
+        cntl.request_metadata_mut()
+            .insert("user_id", "42\nX-Admin: 1");
+        assert!(Http.pack_request(&cntl, msg).is_err());
+
+        // left out of responses
+        let mut headers = Metadata::new();
+        headers.insert("x-id", "7\r\nX-Admin: 1");
+        headers.insert("x-ok", "1");
+        let msg = response(200, "text/plain", &headers, vec![]);
+        let (_, headers) = response_head(&[msg.meta, b"\r\n".to_vec()].concat()).unwrap();
+        assert_eq!(headers.get("x-ok"), Some("1"));
+        assert!(headers.get("x-id").is_none() && headers.get("x-admin").is_none());
+    }
+
+    #[test]
+    fn reject_large_body() {
+        let head = format!(
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_SIZE + 1
+        );
+        let err = parse_err(head.as_bytes());
+        assert!(
+            matches!(err, Error::Http(HttpErr::BodyTooLarge(_))),
+            "{err}"
+        );
+
+        let head = format!(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n",
+            MAX_BODY_SIZE + 1
+        );
+        let err = parse_err(head.as_bytes());
+        assert!(
+            matches!(err, Error::Http(HttpErr::BodyTooLarge(_))),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn chunked_body() {
+        let mut buf = BytesMut::from(
+            &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n"[..],
+        );
+        let msg = Http.parse(&mut buf).unwrap();
+        assert_eq!(msg.payload, b"abcde");
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn response_to_head() {
+        let resp = &b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n"[..];
+        let mut buf = BytesMut::from(resp);
+        let msg = Http
+            .parse_response(&mut buf, b"HEAD / HTTP/1.1\r\n")
+            .unwrap();
+        assert!(msg.payload.is_empty() && buf.is_empty());
+
+        let mut buf = BytesMut::from(resp);
+        let err = Http
+            .parse_response(&mut buf, b"GET / HTTP/1.1\r\n")
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::Parse(ParseErr::NotEnoughData)),
+            "{err}"
+        );
+    }
+}
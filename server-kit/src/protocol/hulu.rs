@@ -0,0 +1,291 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use async_trait::async_trait;
+use bytes::{BufMut, BytesMut};
+use protobuf::{Enum, Message};
+use tracing::{debug, instrument, warn};
+
+use server_kit_protocol::hulu_pbrpc_meta::{HuluRpcRequestMeta, HuluRpcResponseMeta};
+use server_kit_protocol::options::{CompressType, ProtocolType, TalkType};
+
+use super::Protocol;
+use crate::compress;
+use crate::error::{ParseErr, RpcErr, ENOMETHOD, EREQUEST, ERPCAUTH};
+use crate::message::CommonMsg;
+use crate::socket::Connection;
+use crate::{Context, Controller, Result, Services};
+
+const HEADER_SIZE: usize = 12;
+const TAG: [u8; 4] = *b"HULU";
+
+/// hulu_pbrpc of the legacy C++ pb-RPC framework, which names methods by
+/// their index in the service, and its header in host order.
+///
+/// The meta of a message is a `HuluRpcRequestMeta` or `HuluRpcResponseMeta`,
+/// whose `user_message_size` tells the payload apart from the attachment after it.
+pub struct HuluPbrpc;
+
+#[async_trait]
+impl Protocol for HuluPbrpc {
+    fn default() -> Self {
+        HuluPbrpc
+    }
+    fn protocol_type(&self) -> ProtocolType {
+        ProtocolType::PROTOCOL_HULU_PBRPC
+    }
+    fn name(&self) -> &'static str {
+        "hulu_pbrpc"
+    }
+    fn supports_authentication(&self) -> bool {
+        true
+    }
+
+    #[instrument(skip_all)]
+    fn parse(&self, buf: &mut BytesMut) -> Result<CommonMsg> {
+        let n = buf.len().min(TAG.len());
+        if buf[..n] != TAG[..n] {
+            return Err(ParseErr::TryOther.into());
+        }
+        if buf.len() < HEADER_SIZE {
+            return Err(ParseErr::NotEnoughData.into());
+        }
+        let body_size = u32::from_le_bytes(buf[4..8].try_into().unwrap()) as usize;
+        let meta_size = u32::from_le_bytes(buf[8..12].try_into().unwrap()) as usize;
+        if body_size < meta_size {
+            warn!(body_size, meta_size, "body_size less than meta_size");
+            return Err(ParseErr::TryOther.into());
+        }
+        if buf.len() < HEADER_SIZE + body_size {
+            return Err(ParseErr::NotEnoughData.into());
+        }
+        debug!(body_size, meta_size, "finish to parse hulu header");
+
+        let _ = buf.split_to(HEADER_SIZE);
+        let mut body = buf.split_to(body_size);
+        let mut msg = CommonMsg::default();
+        msg.with_meta(body.split_to(meta_size).to_vec());
+        msg.with_payload(body.to_vec());
+
+        Ok(msg)
+    }
+
+    #[instrument(skip_all)]
+    async fn process_request(
+        &self,
+        conn: &Connection,
+        services: &Services,
+        msg: CommonMsg,
+    ) -> Result<Option<CommonMsg>> {
+        // request
+        let meta = HuluRpcRequestMeta::parse_from_bytes(&msg.meta)?;
+        let correlation_id = meta.correlation_id();
+        let identity = match conn.authenticate(meta.credential_data()).await {
+            Ok(identity) => identity,
+            Err(e) => {
+                warn!("reject request from {}: {e}", conn.remote_addr());
+                return Ok(Some(error_response(
+                    correlation_id,
+                    ERPCAUTH,
+                    e.to_string(),
+                )?));
+            }
+        };
+        let compress_type = compress::compress_type_from_i32(meta.compress_type())?;
+        let mut payload = msg.payload;
+        let attachment = match meta.has_user_message_size() {
+            true => match split_user_message(&mut payload, meta.user_message_size()) {
+                Ok(attachment) => attachment,
+                Err(e) => {
+                    return Ok(Some(error_response(
+                        correlation_id,
+                        EREQUEST,
+                        e.to_string(),
+                    )?));
+                }
+            },
+            false => vec![],
+        };
+        let payload = compress::decompress(compress_type, &payload)?;
+
+        // process
+        let svc_name = match services.full_name(meta.service_name()) {
+            Ok(svc_name) => svc_name,
+            Err(e) => {
+                let code = super::error_code(&e);
+                return Ok(Some(error_response(correlation_id, code, e.to_string())?));
+            }
+        };
+        let svc_desc = services.descriptor(svc_name);
+        let method = match meta.has_method_name() {
+            true => svc_desc.and_then(|svc_desc| svc_desc.method(meta.method_name())),
+            false => usize::try_from(meta.method_index())
+                .ok()
+                .and_then(|idx| svc_desc?.methods.get(idx)),
+        };
+        let method = match method {
+            Some(method) => method,
+            None => {
+                let text = match meta.has_method_name() {
+                    true => format!("no method {} of {svc_name}", meta.method_name()),
+                    false => format!("no method {} of {svc_name}", meta.method_index()),
+                };
+                return Ok(Some(error_response(correlation_id, ENOMETHOD, text)?));
+            }
+        };
+        let mut ctx = Context::default();
+        ctx.set_identity(identity);
+        ctx.set_peer_certificate(conn.peer_certificate());
+        ctx.set_request_compress_type(compress_type);
+        ctx.set_request_attachment(attachment);
+        let svc = services.get(svc_name)?;
+        let res = svc.call_method(&mut ctx, method.name, &payload).await;
+        if method.request_talk_type == TalkType::TALK_TYPE_ONEWAY {
+            if let Err(e) = res {
+                warn!("one-way method {svc_name}.{} err:{e}", method.name);
+            }
+            return Ok(None);
+        }
+        let msg = match res {
+            Ok(msg) => msg,
+            Err(e) => {
+                let code = super::error_code(&e);
+                return Ok(Some(error_response(correlation_id, code, e.to_string())?));
+            }
+        };
+
+        // response, compressed as the request unless the handler or method says otherwise
+        let compress_type = match ctx.response_compress_type() {
+            Some(compress_type) => compress_type,
+            None => Some(method.response_compression)
+                .filter(|c| *c != CompressType::COMPRESS_TYPE_NONE)
+                .unwrap_or(compress_type),
+        };
+        let mut meta = HuluRpcResponseMeta::new();
+        meta.set_error_code(0);
+        meta.set_correlation_id(correlation_id);
+        meta.set_compress_type(compress_type.value());
+        let mut payload = compress::compress(compress_type, &msg)?;
+        let attachment = ctx.take_response_attachment();
+        if !attachment.is_empty() {
+            meta.set_user_message_size(payload.len() as i32);
+            payload.extend_from_slice(&attachment);
+        }
+        let mut msg = CommonMsg::new(payload);
+        msg.with_meta(meta.write_to_bytes()?);
+
+        Ok(Some(msg))
+    }
+
+    #[instrument(skip_all)]
+    fn pack_response(&self, msg: CommonMsg) -> Vec<u8> {
+        pack_frame(msg)
+    }
+
+    // the meta of `msg` is a `HuluRpcRequestMeta` with the service name and method index
+    #[instrument(skip_all)]
+    fn pack_request(&self, cntl: &Controller, mut msg: CommonMsg) -> Result<Vec<u8>> {
+        static NEXT_ID: AtomicI64 = AtomicI64::new(1);
+
+        let mut meta = HuluRpcRequestMeta::parse_from_bytes(&msg.meta)?;
+        meta.set_correlation_id(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        let compress_type = cntl.request_compress_type();
+        if compress_type != CompressType::COMPRESS_TYPE_NONE {
+            meta.set_compress_type(compress_type.value());
+            msg.with_payload(compress::compress(compress_type, &msg.payload)?);
+        }
+        if !cntl.authentication_data().is_empty() {
+            meta.set_credential_data(cntl.authentication_data().to_vec());
+        }
+        let attachment = cntl.request_attachment();
+        if !attachment.is_empty() {
+            meta.set_user_message_size(msg.payload.len() as i32);
+            msg.payload.extend_from_slice(attachment);
+        }
+        msg.with_meta(meta.write_to_bytes()?);
+
+        Ok(pack_frame(msg))
+    }
+
+    #[instrument(skip_all)]
+    async fn process_response(&self, cntl: &mut Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        let meta = HuluRpcResponseMeta::parse_from_bytes(&msg.meta)?;
+        if meta.error_code() != 0 {
+            let text = meta.error_text().to_string();
+            return Err(RpcErr::Failed(meta.error_code(), text).into());
+        }
+        let compress_type = compress::compress_type_from_i32(meta.compress_type())?;
+        cntl.set_response_compress_type(compress_type);
+        let mut payload = msg.payload;
+        if meta.has_user_message_size() {
+            let attachment = split_user_message(&mut payload, meta.user_message_size())?;
+            cntl.set_response_attachment(attachment);
+        }
+
+        compress::decompress(compress_type, &payload)
+    }
+}
+
+// the attachment after the first `user_message_size` bytes of `payload`
+fn split_user_message(payload: &mut Vec<u8>, user_message_size: i32) -> Result<Vec<u8>> {
+    let at = usize::try_from(user_message_size)
+        .ok()
+        .filter(|size| *size <= payload.len())
+        .ok_or(ParseErr::InvalidUserMessageSize(user_message_size))?;
+    Ok(payload.split_off(at))
+}
+
+fn error_response(correlation_id: i64, code: i32, text: String) -> Result<CommonMsg> {
+    let mut meta = HuluRpcResponseMeta::new();
+    meta.set_error_code(code);
+    meta.set_error_text(text);
+    meta.set_correlation_id(correlation_id);
+    let mut msg = CommonMsg::default();
+    msg.with_meta(meta.write_to_bytes()?);
+    Ok(msg)
+}
+
+// "HULU", body size and meta size, both in little endian as hulu puts them in host order
+fn pack_frame(msg: CommonMsg) -> Vec<u8> {
+    let mut buffer = BytesMut::with_capacity(HEADER_SIZE + msg.body_size() as usize);
+    buffer.put_slice(&TAG);
+    buffer.put_u32_le(msg.body_size());
+    buffer.put_u32_le(msg.meta_size());
+    buffer.put(msg.to_vec().as_slice());
+
+    buffer.to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_at_user_message_size() {
+        for (size, attachment) in [(0, &b"hello"[..]), (2, b"llo"), (5, b"")] {
+            let mut payload = b"hello".to_vec();
+            assert_eq!(split_user_message(&mut payload, size).unwrap(), attachment);
+            assert_eq!(payload, b"hello"[..size as usize]);
+        }
+        for size in [-1, 6, i32::MAX, i32::MIN] {
+            let err = split_user_message(&mut b"hello".to_vec(), size).unwrap_err();
+            assert!(err.to_string().contains("user_message_size"), "{err}");
+        }
+    }
+
+    #[tokio::test]
+    async fn response_round_trip() {
+        let mut meta = HuluRpcResponseMeta::new();
+        meta.set_error_code(0);
+        meta.set_user_message_size(5);
+        let mut msg = CommonMsg::new(b"world!".to_vec());
+        msg.with_meta(meta.write_to_bytes().unwrap());
+
+        let mut buf = BytesMut::from(&HuluPbrpc.pack_response(msg)[..]);
+        let msg = HuluPbrpc.parse(&mut buf).unwrap();
+        assert!(buf.is_empty());
+        let mut cntl = Controller::new();
+        let payload = HuluPbrpc.process_response(&mut cntl, msg).await.unwrap();
+        assert_eq!(payload, b"world");
+        assert_eq!(cntl.response_attachment(), b"!");
+    }
+}
@@ -0,0 +1,410 @@
+use async_trait::async_trait;
+use bytes::{Buf, BufMut, BytesMut};
+use tracing::instrument;
+
+use server_kit_protocol::options::ProtocolType;
+
+use super::Protocol;
+use crate::error::{MemcacheErr, ParseErr};
+use crate::message::CommonMsg;
+use crate::socket::Connection;
+use crate::{Controller, Error, Result, Services};
+
+const HEADER_SIZE: usize = 24;
+// longest key memcached takes
+const MAX_KEY_LEN: usize = 250;
+const MAGIC_REQUEST: u8 = 0x80;
+const MAGIC_RESPONSE: u8 = 0x81;
+
+const OP_GET: u8 = 0x00;
+const OP_SET: u8 = 0x01;
+const OP_ADD: u8 = 0x02;
+const OP_REPLACE: u8 = 0x03;
+const OP_DELETE: u8 = 0x04;
+const OP_INCREMENT: u8 = 0x05;
+const OP_DECREMENT: u8 = 0x06;
+const OP_VERSION: u8 = 0x0b;
+const OP_TOUCH: u8 = 0x1c;
+
+/// Response statuses of the memcache binary protocol.
+pub mod status {
+    pub const OK: u16 = 0x00;
+    pub const KEY_NOT_FOUND: u16 = 0x01;
+    pub const KEY_EXISTS: u16 = 0x02;
+    pub const VALUE_TOO_LARGE: u16 = 0x03;
+    pub const INVALID_ARGUMENTS: u16 = 0x04;
+    pub const ITEM_NOT_STORED: u16 = 0x05;
+    pub const NON_NUMERIC_VALUE: u16 = 0x06;
+    pub const UNKNOWN_COMMAND: u16 = 0x81;
+    pub const OUT_OF_MEMORY: u16 = 0x82;
+}
+
+/// The memcache binary protocol, client side only.
+///
+/// A message is a raw response.
+pub struct Memcache;
+
+/// Operations sent in one go by `Channel<Memcache>`, responded to in the same order.
+///
+/// Keys longer than 250 bytes fail the request when it is sent.
+#[derive(Debug, Clone, Default)]
+pub struct MemcacheRequest {
+    buf: Vec<u8>,
+    count: usize,
+    // of the first operation which can't be sent
+    error: Option<MemcacheErr>,
+}
+
+impl MemcacheRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(self, key: impl AsRef<[u8]>) -> Self {
+        self.push(OP_GET, &[], key.as_ref(), &[], 0)
+    }
+
+    /// Store `value` whatever the key has, or only if its cas is still `cas` unless that is 0.
+    pub fn set(
+        self,
+        key: impl AsRef<[u8]>,
+        value: impl AsRef<[u8]>,
+        flags: u32,
+        exptime: u32,
+        cas: u64,
+    ) -> Self {
+        self.store(OP_SET, key.as_ref(), value.as_ref(), flags, exptime, cas)
+    }
+
+    /// Store `value` if the key has none.
+    pub fn add(
+        self,
+        key: impl AsRef<[u8]>,
+        value: impl AsRef<[u8]>,
+        flags: u32,
+        exptime: u32,
+    ) -> Self {
+        self.store(OP_ADD, key.as_ref(), value.as_ref(), flags, exptime, 0)
+    }
+
+    /// Store `value` if the key has one, and its cas is still `cas` unless that is 0.
+    pub fn replace(
+        self,
+        key: impl AsRef<[u8]>,
+        value: impl AsRef<[u8]>,
+        flags: u32,
+        exptime: u32,
+        cas: u64,
+    ) -> Self {
+        self.store(
+            OP_REPLACE,
+            key.as_ref(),
+            value.as_ref(),
+            flags,
+            exptime,
+            cas,
+        )
+    }
+
+    pub fn delete(self, key: impl AsRef<[u8]>, cas: u64) -> Self {
+        self.push(OP_DELETE, &[], key.as_ref(), &[], cas)
+    }
+
+    /// Add `delta` to the counter, which starts at `initial` if the key has
+    /// none, or is not created if `exptime` is 0xffffffff.
+    pub fn incr(self, key: impl AsRef<[u8]>, delta: u64, initial: u64, exptime: u32) -> Self {
+        self.counter(OP_INCREMENT, key.as_ref(), delta, initial, exptime)
+    }
+
+    /// Same as `incr` but subtracting `delta`, down to 0 at most.
+    pub fn decr(self, key: impl AsRef<[u8]>, delta: u64, initial: u64, exptime: u32) -> Self {
+        self.counter(OP_DECREMENT, key.as_ref(), delta, initial, exptime)
+    }
+
+    pub fn touch(self, key: impl AsRef<[u8]>, exptime: u32) -> Self {
+        self.push(OP_TOUCH, &exptime.to_be_bytes(), key.as_ref(), &[], 0)
+    }
+
+    pub fn version(self) -> Self {
+        self.push(OP_VERSION, &[], &[], &[], 0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    fn store(
+        self,
+        opcode: u8,
+        key: &[u8],
+        value: &[u8],
+        flags: u32,
+        exptime: u32,
+        cas: u64,
+    ) -> Self {
+        let mut extras = [0; 8];
+        extras[..4].copy_from_slice(&flags.to_be_bytes());
+        extras[4..].copy_from_slice(&exptime.to_be_bytes());
+        self.push(opcode, &extras, key, value, cas)
+    }
+
+    fn counter(self, opcode: u8, key: &[u8], delta: u64, initial: u64, exptime: u32) -> Self {
+        let mut extras = [0; 20];
+        extras[..8].copy_from_slice(&delta.to_be_bytes());
+        extras[8..16].copy_from_slice(&initial.to_be_bytes());
+        extras[16..].copy_from_slice(&exptime.to_be_bytes());
+        self.push(opcode, &extras, key, &[], 0)
+    }
+
+    // the index of the operation goes in opaque, to check responses against
+    fn push(mut self, opcode: u8, extras: &[u8], key: &[u8], value: &[u8], cas: u64) -> Self {
+        let body_len = extras.len() + key.len() + value.len();
+        let error = if key.len() > MAX_KEY_LEN {
+            Some(MemcacheErr::KeyTooLong(key.len()))
+        } else if u8::try_from(extras.len()).is_err() || u32::try_from(body_len).is_err() {
+            Some(MemcacheErr::BodyTooLarge(body_len))
+        } else {
+            None
+        };
+        if let Some(error) = error {
+            self.error.get_or_insert(error);
+            return self;
+        }
+        self.buf.reserve(HEADER_SIZE + body_len);
+        self.buf.put_u8(MAGIC_REQUEST);
+        self.buf.put_u8(opcode);
+        self.buf.put_u16(key.len() as u16);
+        self.buf.put_u8(extras.len() as u8);
+        self.buf.put_u8(0); // data type
+        self.buf.put_u16(0); // vbucket
+        self.buf.put_u32(body_len as u32);
+        self.buf.put_u32(self.count as u32);
+        self.buf.put_u64(cas);
+        self.buf.put_slice(extras);
+        self.buf.put_slice(key);
+        self.buf.put_slice(value);
+        self.count += 1;
+        self
+    }
+}
+
+/// Fails with the first operation too large to be sent.
+impl TryFrom<MemcacheRequest> for CommonMsg {
+    type Error = Error;
+
+    fn try_from(req: MemcacheRequest) -> Result<Self> {
+        match req.error {
+            Some(error) => Err(error.into()),
+            None => Ok(CommonMsg::new(req.buf)),
+        }
+    }
+}
+
+/// The response to one memcache operation. Failures like a missing key
+/// are in `status`, with the value holding the message of the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemcacheReply {
+    pub opcode: u8,
+    pub status: u16,
+    /// Flags of the value got.
+    pub flags: u32,
+    pub cas: u64,
+    pub value: Vec<u8>,
+}
+
+impl MemcacheReply {
+    pub fn is_ok(&self) -> bool {
+        self.status == status::OK
+    }
+
+    /// The counter after `incr` or `decr`.
+    pub fn counter(&self) -> Option<u64> {
+        match (self.is_ok(), self.opcode) {
+            (true, OP_INCREMENT | OP_DECREMENT) => {
+                Some(u64::from_be_bytes(self.value.as_slice().try_into().ok()?))
+            }
+            _ => None,
+        }
+    }
+
+    /// The server version replied to `version`.
+    pub fn version(&self) -> Option<&str> {
+        match (self.is_ok(), self.opcode) {
+            (true, OP_VERSION) => std::str::from_utf8(&self.value).ok(),
+            _ => None,
+        }
+    }
+
+    /// Parse `buf`, which must be exactly the response to the operation
+    /// `index` of the request, as framed by `Memcache::parse`.
+    pub(crate) fn parse(buf: &[u8], index: usize) -> Result<Self> {
+        if buf.len() < HEADER_SIZE {
+            return Err(MemcacheErr::Invalid(format!("response of {}", buf.len())).into());
+        }
+        let mut head = &buf[..HEADER_SIZE];
+        head.advance(1);
+        let opcode = head.get_u8();
+        let key_len = head.get_u16() as usize;
+        let extras_len = head.get_u8() as usize;
+        head.advance(1);
+        let status = head.get_u16();
+        let body_len = head.get_u32() as usize;
+        let opaque = head.get_u32();
+        let cas = head.get_u64();
+        if buf.len() != HEADER_SIZE + body_len || extras_len + key_len > body_len {
+            return Err(MemcacheErr::Invalid(format!("body of {body_len}")).into());
+        }
+        if opaque as usize != index {
+            return Err(MemcacheErr::UnexpectedOpaque(index, opaque).into());
+        }
+
+        let mut body = &buf[HEADER_SIZE..];
+        let flags = match (status, extras_len) {
+            (status::OK, 4..) => body.get_u32(),
+            _ => 0,
+        };
+        Ok(Self {
+            opcode,
+            status,
+            flags,
+            cas,
+            value: buf[HEADER_SIZE + extras_len + key_len..].to_vec(),
+        })
+    }
+}
+
+#[async_trait]
+impl Protocol for Memcache {
+    fn default() -> Self {
+        Memcache
+    }
+    fn protocol_type(&self) -> ProtocolType {
+        ProtocolType::PROTOCOL_MEMCACHE
+    }
+    fn name(&self) -> &'static str {
+        "memcache"
+    }
+
+    #[instrument(skip_all)]
+    fn parse(&self, buf: &mut BytesMut) -> Result<CommonMsg> {
+        match buf.first() {
+            None => return Err(ParseErr::NotEnoughData.into()),
+            Some(&MAGIC_RESPONSE) => {}
+            Some(_) => return Err(ParseErr::TryOther.into()),
+        }
+        if buf.len() < HEADER_SIZE {
+            return Err(ParseErr::NotEnoughData.into());
+        }
+        let body_len = (&buf[8..12]).get_u32() as usize;
+        if buf.len() < HEADER_SIZE + body_len {
+            return Err(ParseErr::NotEnoughData.into());
+        }
+
+        Ok(CommonMsg::new(
+            buf.split_to(HEADER_SIZE + body_len).to_vec(),
+        ))
+    }
+
+    async fn process_request(
+        &self,
+        _conn: &Connection,
+        _services: &Services,
+        _msg: CommonMsg,
+    ) -> Result<Option<CommonMsg>> {
+        Err(Error::StrErr("memcache is client side only".to_string()))
+    }
+
+    fn pack_response(&self, msg: CommonMsg) -> Vec<u8> {
+        msg.to_vec()
+    }
+
+    #[instrument(skip_all)]
+    fn pack_request(&self, _cntl: &Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        Ok(msg.to_vec())
+    }
+
+    #[instrument(skip_all)]
+    async fn process_response(&self, _cntl: &mut Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        Ok(msg.payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(opcode: u8, status: u16, opaque: u32, extras: &[u8], value: &[u8]) -> Vec<u8> {
+        let mut buf = vec![MAGIC_RESPONSE, opcode];
+        buf.put_u16(0);
+        buf.put_u8(extras.len() as u8);
+        buf.put_u8(0);
+        buf.put_u16(status);
+        buf.put_u32((extras.len() + value.len()) as u32);
+        buf.put_u32(opaque);
+        buf.put_u64(7);
+        buf.extend_from_slice(extras);
+        buf.extend_from_slice(value);
+        buf
+    }
+
+    #[test]
+    fn reject_long_key() {
+        let key = vec![b'k'; MAX_KEY_LEN];
+        let req = MemcacheRequest::new().get(&key);
+        assert!(CommonMsg::try_from(req).is_ok());
+
+        let key = vec![b'k'; MAX_KEY_LEN + 1];
+        for req in [
+            MemcacheRequest::new().get(&key),
+            MemcacheRequest::new().get("a").set(&key, "v", 0, 0, 0),
+            MemcacheRequest::new().incr(&key, 1, 0, 0).get("a"),
+        ] {
+            let err = CommonMsg::try_from(req).unwrap_err();
+            assert!(err.to_string().contains("longer than 250"), "{err}");
+        }
+    }
+
+    #[test]
+    fn request_layout() {
+        let req = MemcacheRequest::new().get("a").set("key", "value", 1, 2, 3);
+        assert_eq!(req.len(), 2);
+        let buf = CommonMsg::try_from(req).unwrap().payload;
+        let set = &buf[HEADER_SIZE + 1..];
+        assert_eq!(set[..2], [MAGIC_REQUEST, OP_SET]);
+        assert_eq!(u16::from_be_bytes([set[2], set[3]]), 3);
+        assert_eq!(set[4], 8);
+        assert_eq!((&set[8..12]).get_u32(), 8 + 3 + 5);
+        assert_eq!((&set[12..16]).get_u32(), 1);
+        assert_eq!((&set[16..24]).get_u64(), 3);
+        assert_eq!(&set[HEADER_SIZE + 8..], b"keyvalue");
+    }
+
+    #[test]
+    fn frame_each_response() {
+        let first = response(OP_GET, status::OK, 0, &5u32.to_be_bytes(), b"v");
+        let second = response(OP_GET, status::KEY_NOT_FOUND, 1, &[], b"Not found");
+        let mut buf = BytesMut::from(&[first.clone(), second[..30].to_vec()].concat()[..]);
+        let msg = Memcache.parse(&mut buf).unwrap();
+        assert_eq!(msg.payload, first);
+        let err = Memcache.parse(&mut buf).unwrap_err();
+        assert!(
+            matches!(err, Error::Parse(ParseErr::NotEnoughData)),
+            "{err}"
+        );
+
+        let reply = MemcacheReply::parse(&first, 0).unwrap();
+        assert_eq!(
+            (reply.flags, reply.cas, reply.value.as_slice()),
+            (5, 7, &b"v"[..])
+        );
+        let reply = MemcacheReply::parse(&second, 1).unwrap();
+        assert!(!reply.is_ok() && reply.value == b"Not found");
+
+        assert!(MemcacheReply::parse(&second, 0).is_err());
+        assert!(MemcacheReply::parse(&second[..30], 1).is_err());
+    }
+}
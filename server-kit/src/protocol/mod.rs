@@ -1,36 +1,188 @@
-use std::any::TypeId;
+use std::borrow::Cow;
+use std::iter;
 
 use async_trait::async_trait;
-use tokio::net::TcpStream;
+use bytes::BytesMut;
+use tokio::io::{AsyncRead, AsyncReadExt};
 
-use crate::message::CommonMsg;
-use crate::{Result, Service};
+use server_kit_protocol::options::ProtocolType;
+
+use crate::error::{ParseErr, RpcErr, SvcErr, EINTERNAL, ENOSERVICE};
+use crate::message::{Chunks, CommonMsg};
+use crate::socket::{Connection, ConnectionIo};
+use crate::{Controller, Error, Result, Services};
 
 pub use brpc::Brpc;
-pub use nshead::Nshead;
+pub use grpc::{status as grpc_status, Grpc};
+pub use http::{BodyFormat, Http, HttpRequest, HttpResponse};
+pub use hulu::HuluPbrpc;
+pub use memcache::{status as memcache_status, Memcache, MemcacheReply, MemcacheRequest};
+pub use nova::NovaPbrpc;
+pub use nshead::{Header as NsheadHeader, Nshead, NsheadPbServiceAdaptor};
+pub use nshead_mcpack::NsheadMcpack;
+pub use public::PublicPbrpc;
+pub use redis::{Redis, RedisCommandHandler, RedisReply, RedisRequest, RedisService};
+pub use sofa::SofaPbrpc;
+pub use streaming::Streaming;
+pub use thrift::{exception as thrift_exception, Thrift};
+pub use ubrpc::{UbrpcCompack, UbrpcMcpack2};
+
+pub(crate) use http::read_response as read_http_response;
+pub use registry::{
+    new_protocol, new_protocol_by_name, protocol_names, protocol_type_by_name, protocol_types,
+    register_protocol, register_protocol_by_name,
+};
+pub(crate) use streaming::pack_frame;
+
+/// Frames of a message, made as they are written.
+pub type Frames = Box<dyn Iterator<Item = Vec<u8>> + Send>;
 
 mod brpc;
+pub(crate) mod grpc;
+mod http;
+mod hulu;
+mod memcache;
+mod nova;
 mod nshead;
+mod nshead_mcpack;
+mod public;
+mod redis;
+mod registry;
+mod sofa;
+mod streaming;
+mod thrift;
+mod ubrpc;
 
 #[async_trait]
 pub trait Protocol: Sync + Send + 'static {
     fn default() -> Self
     where
         Self: Sized;
-    fn protocol_id() -> TypeId
-    where
-        Self: Sized;
-
-    fn add_service(&mut self, svc_name: String, svc: Box<dyn Service>) -> Result<()>;
+    fn protocol_type(&self) -> ProtocolType;
+    fn name(&self) -> &'static str;
 
     // for server and channel
-    async fn parse(&self, stream: &mut TcpStream) -> Result<CommonMsg>;
+    /// Cut one message off the front of `buf`, returns `ParseErr::NotEnoughData`
+    /// to wait for more bytes or `ParseErr::TryOther` if they aren't of this protocol.
+    fn parse(&self, buf: &mut BytesMut) -> Result<CommonMsg>;
+    /// `parse` for the channel, which reads the response to `request`, the
+    /// first frame it was sent in, for protocols whose responses are only
+    /// delimited knowing the request.
+    fn parse_response(&self, buf: &mut BytesMut, _request: &[u8]) -> Result<CommonMsg> {
+        self.parse(buf)
+    }
 
     // for server
-    async fn process_request(&self, msg: CommonMsg) -> Result<CommonMsg>;
+    /// Returns the response to write back, if any.
+    async fn process_request(
+        &self,
+        conn: &Connection,
+        services: &Services,
+        msg: CommonMsg,
+    ) -> Result<Option<CommonMsg>>;
     fn pack_response(&self, msg: CommonMsg) -> Vec<u8>;
+    /// `pack_response` in frames written one at a time, so that other traffic
+    /// on the connection can go in between, one unless the protocol sends
+    /// large messages in chunks.
+    fn pack_response_frames(&self, msg: CommonMsg) -> Frames {
+        Box::new(iter::once(self.pack_response(msg)))
+    }
+
+    /// Whether servers enable the protocol when not told which ones to serve.
+    /// Protocols that sniffing can't tell apart from others, like nova_pbrpc
+    /// from nshead, are only served if enabled by name or `add_protocol`.
+    fn is_enabled_by_default(&self) -> bool {
+        true
+    }
+
+    /// Whether requests carry credentials the protocol verifies by
+    /// `Connection::authenticate`. Servers with an authenticator refuse the
+    /// requests of the protocols which don't rather than serve them unchecked.
+    fn supports_authentication(&self) -> bool {
+        false
+    }
+
+    /// Whether the protocol multiplexes the connection with framing of its
+    /// own, like HTTP/2, and serves all of it by `serve_connection` once
+    /// `parse` has recognized it.
+    fn is_multiplexed(&self) -> bool {
+        false
+    }
+    async fn serve_connection(
+        &self,
+        _conn: &Connection,
+        _services: &Services,
+        _io: ConnectionIo<'_>,
+    ) -> Result<()> {
+        Ok(())
+    }
 
     // for channel
-    fn pack_request(&self, msg: CommonMsg) -> Vec<u8>;
-    async fn process_response(&self, msg: CommonMsg) -> Result<Vec<u8>>;
+    fn pack_request(&self, cntl: &Controller, msg: CommonMsg) -> Result<Vec<u8>>;
+    /// `pack_request` in frames, see `pack_response_frames`.
+    fn pack_request_frames(&self, cntl: &Controller, msg: CommonMsg) -> Result<Frames> {
+        Ok(Box::new(iter::once(self.pack_request(cntl, msg)?)))
+    }
+    /// The frames of a request packed by `pack_request_frames` as sent to
+    /// `server`, for protocols naming in the request the server it goes to.
+    fn address_request<'a>(&self, frames: &'a [Vec<u8>], _server: &str) -> Cow<'a, [Vec<u8>]> {
+        Cow::Borrowed(frames)
+    }
+    async fn process_response(&self, cntl: &mut Controller, msg: CommonMsg) -> Result<Vec<u8>>;
+}
+
+pub(crate) async fn read_message<P, S>(
+    protocol: &P,
+    stream: &mut S,
+    buf: &mut BytesMut,
+) -> Result<CommonMsg>
+where
+    P: Protocol + ?Sized,
+    S: AsyncRead + Unpin,
+{
+    read_with(|buf| protocol.parse(buf), stream, buf).await
+}
+
+/// `read_message` of the response to `request`, see `Protocol::parse_response`.
+pub(crate) async fn read_response<P, S>(
+    protocol: &P,
+    stream: &mut S,
+    buf: &mut BytesMut,
+    request: &[u8],
+) -> Result<CommonMsg>
+where
+    P: Protocol + ?Sized,
+    S: AsyncRead + Unpin,
+{
+    read_with(|buf| protocol.parse_response(buf, request), stream, buf).await
+}
+
+async fn read_with<F, S>(mut parse: F, stream: &mut S, buf: &mut BytesMut) -> Result<CommonMsg>
+where
+    F: FnMut(&mut BytesMut) -> Result<CommonMsg>,
+    S: AsyncRead + Unpin,
+{
+    let mut chunks = Chunks::default();
+    loop {
+        match parse(buf) {
+            Ok(msg) => match chunks.merge(msg)? {
+                Some(msg) => return Ok(msg),
+                None => continue,
+            },
+            Err(Error::Parse(ParseErr::NotEnoughData)) => {}
+            Err(e) => return Err(e),
+        }
+        if stream.read_buf(buf).await? == 0 {
+            return Err(ParseErr::UnexpectedEof.into());
+        }
+    }
+}
+
+/// brpc error code of a request failing with `err`.
+pub(crate) fn error_code(err: &Error) -> i32 {
+    match err {
+        Error::Svc(SvcErr::NotExist(_)) => ENOSERVICE,
+        Error::Rpc(RpcErr::Failed(code, _)) => *code,
+        _ => EINTERNAL,
+    }
 }
@@ -0,0 +1,164 @@
+use async_trait::async_trait;
+use bytes::BytesMut;
+use protobuf::Enum;
+use tracing::{debug, instrument, warn};
+
+use server_kit_protocol::options::{CompressType, ProtocolType, TalkType};
+
+use super::nshead::{self, Header, NSHEAD_SIZE};
+use super::Protocol;
+use crate::compress;
+use crate::error::{CompressErr, RpcErr, SvcErr, ENOMETHOD};
+use crate::message::CommonMsg;
+use crate::socket::Connection;
+use crate::{Context, Controller, Error, Result, Services};
+
+// set in the version of the nshead header if the body is compressed by snappy
+const NOVA_SNAPPY_COMPRESS_FLAG: u16 = 0x1;
+
+/// nova_pbrpc, which is nshead with the index of the method in the service
+/// in `reserved` of the header, and the body compressed by snappy or not at all.
+///
+/// The meta of a message is its nshead header. Calls go to the bound service,
+/// or to the only service of the server. Nova has no way to reply errors, so
+/// a failed call closes the connection.
+///
+/// Sniffing can't tell nova from nshead, so servers serve it only if enabled
+/// by name or `add_protocol`, in front of nshead.
+pub struct NovaPbrpc {
+    svc_name: Option<String>,
+}
+
+impl NovaPbrpc {
+    pub fn with_service(svc_name: impl Into<String>) -> Self {
+        Self {
+            svc_name: Some(svc_name.into()),
+        }
+    }
+}
+
+fn compress_type_of(head: &Header) -> CompressType {
+    match head.version & NOVA_SNAPPY_COMPRESS_FLAG {
+        0 => CompressType::COMPRESS_TYPE_NONE,
+        _ => CompressType::COMPRESS_TYPE_SNAPPY,
+    }
+}
+
+// `head` with the flag of `compress_type`, which is either none or snappy
+fn with_compress_type(mut head: Header, compress_type: CompressType) -> Result<Header> {
+    match compress_type {
+        CompressType::COMPRESS_TYPE_NONE => head.version &= !NOVA_SNAPPY_COMPRESS_FLAG,
+        CompressType::COMPRESS_TYPE_SNAPPY => head.version |= NOVA_SNAPPY_COMPRESS_FLAG,
+        ty => return Err(CompressErr::Unknown(ty.value()).into()),
+    }
+    Ok(head)
+}
+
+#[async_trait]
+impl Protocol for NovaPbrpc {
+    fn default() -> Self {
+        NovaPbrpc { svc_name: None }
+    }
+    fn protocol_type(&self) -> ProtocolType {
+        ProtocolType::PROTOCOL_NOVA_PBRPC
+    }
+    fn name(&self) -> &'static str {
+        "nova_pbrpc"
+    }
+
+    #[instrument(skip_all)]
+    fn parse(&self, buf: &mut BytesMut) -> Result<CommonMsg> {
+        let head = nshead::peek_frame(buf)?;
+        debug!(%head, "finish to parse nova");
+
+        let meta = buf.split_to(NSHEAD_SIZE);
+        let body = buf.split_to(head.body_size as usize);
+        let mut msg = CommonMsg::new(body.to_vec());
+        msg.with_meta(meta.to_vec());
+
+        Ok(msg)
+    }
+
+    fn is_enabled_by_default(&self) -> bool {
+        false
+    }
+
+    #[instrument(skip_all)]
+    async fn process_request(
+        &self,
+        conn: &Connection,
+        services: &Services,
+        msg: CommonMsg,
+    ) -> Result<Option<CommonMsg>> {
+        let head = nshead::header_of(&msg);
+        let compress_type = compress_type_of(&head);
+        let payload = compress::decompress(compress_type, &msg.payload)?;
+
+        let svc_name = match &self.svc_name {
+            Some(svc_name) => svc_name.as_str(),
+            None if services.len() == 1 => services.iter().next().unwrap().0,
+            None => return Err(SvcErr::NotExist("nova_pbrpc".to_string()).into()),
+        };
+        let method = services
+            .descriptor(svc_name)
+            .and_then(|svc_desc| svc_desc.methods.get(head.reserved as usize))
+            .ok_or_else(|| {
+                let text = format!("no method {} of {svc_name}", head.reserved);
+                RpcErr::Failed(ENOMETHOD, text)
+            })?;
+        let mut ctx = Context::default();
+        ctx.set_peer_certificate(conn.peer_certificate());
+        ctx.set_request_compress_type(compress_type);
+        ctx.set_nshead_header(head);
+        let svc = services.get(svc_name)?;
+        let res = svc.call_method(&mut ctx, method.name, &payload).await;
+        if method.request_talk_type == TalkType::TALK_TYPE_ONEWAY {
+            if let Err(e) = res {
+                warn!("one-way method {svc_name}.{} err:{e}", method.name);
+            }
+            return Ok(None);
+        }
+        let msg = res?;
+
+        // response, compressed as the request unless the handler says otherwise
+        let compress_type = ctx.response_compress_type().unwrap_or(compress_type);
+        let head = ctx.nshead_response_header().copied().unwrap_or_default();
+        let head = with_compress_type(head, compress_type)?;
+        let mut msg = CommonMsg::new(compress::compress(compress_type, &msg)?);
+        msg.with_meta(head.as_u8_slice().to_vec());
+
+        Ok(Some(msg))
+    }
+
+    #[instrument(skip_all)]
+    fn pack_response(&self, msg: CommonMsg) -> Vec<u8> {
+        nshead::pack_frame(nshead::header_of(&msg), &msg.payload)
+    }
+
+    // the meta of `msg` is the method index, as 4 bytes of little endian
+    #[instrument(skip_all)]
+    fn pack_request(&self, cntl: &Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        let mut head = nshead::request_header(cntl);
+        if !msg.meta.is_empty() {
+            let index = msg.meta[..].try_into().map_err(|_| {
+                Error::StrErr(format!("nova method index of {} bytes", msg.meta.len()))
+            })?;
+            head.reserved = u32::from_le_bytes(index);
+        }
+        let compress_type = cntl.request_compress_type();
+        let head = with_compress_type(head, compress_type)?;
+        let payload = compress::compress(compress_type, &msg.payload)?;
+
+        Ok(nshead::pack_frame(head, &payload))
+    }
+
+    #[instrument(skip_all)]
+    async fn process_response(&self, cntl: &mut Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        let head = nshead::header_of(&msg);
+        let compress_type = compress_type_of(&head);
+        cntl.set_response_compress_type(compress_type);
+        cntl.set_nshead_response_header(head);
+
+        compress::decompress(compress_type, &msg.payload)
+    }
+}
@@ -1,143 +1,279 @@
-use std::any::TypeId;
-use std::cmp::Ordering;
 use std::fmt;
+use std::sync::Arc;
 
 use async_trait::async_trait;
 use bytes::BufMut;
 use bytes::BytesMut;
-use tokio::io::AsyncReadExt;
-use tokio::net::TcpStream;
+use tracing::debug;
 use tracing::instrument;
-use tracing::{debug, warn};
+use tracing::warn;
+
+use server_kit_protocol::options::ProtocolType;
+use server_kit_protocol::options::TalkType;
 
 use super::Protocol;
 use crate::error::ParseErr;
+use crate::error::RpcErr;
 use crate::error::SvcErr;
-use crate::global::BUF_SIZE;
+use crate::error::ENOMETHOD;
 use crate::message::CommonMsg;
+use crate::socket::Connection;
+use crate::Context;
+use crate::Controller;
+use crate::MethodDescriptor;
 use crate::Result;
-use crate::Service;
+use crate::Services;
 
 pub const NSHEAD_MAGICNUM: u32 = 0xfb709394;
 pub const NSHEAD_SIZE: usize = ::std::mem::size_of::<Header>();
 
-pub struct Nshead(Option<(String, Box<dyn Service>)>);
+/// Nshead carries no service or method name, so requests go to the bound
+/// method with the nshead body as its request and response, or to the
+/// method the adaptor picks.
+///
+/// Nor does it carry errors, failed requests are answered with an empty body
+/// unless the adaptor serializes them otherwise.
+pub struct Nshead {
+    // service name and method name
+    service: Option<(String, String)>,
+    adaptor: Option<Arc<dyn NsheadPbServiceAdaptor>>,
+}
 
-#[async_trait]
-impl Protocol for Nshead {
-    fn default() -> Self {
-        Nshead(None)
-    }
-    fn protocol_id() -> TypeId {
-        TypeId::of::<Self>()
+impl Nshead {
+    pub fn with_service(svc_name: impl Into<String>, method_name: impl Into<String>) -> Self {
+        Self {
+            service: Some((svc_name.into(), method_name.into())),
+            adaptor: None,
+        }
     }
 
-    fn add_service(&mut self, svc_name: String, svc: Box<dyn Service>) -> Result<()> {
-        match &self.0 {
-            Some((svc_name, _)) => return Err(SvcErr::Exist(svc_name.clone()).into()),
-            None => self.0 = Some((svc_name, svc)),
+    /// Serve the services of the server by the methods `adaptor` picks for requests.
+    pub fn with_adaptor<A>(adaptor: A) -> Self
+    where
+        A: NsheadPbServiceAdaptor,
+    {
+        Self {
+            service: None,
+            adaptor: Some(Arc::new(adaptor)),
         }
+    }
+}
 
-        Ok(())
+/// Routes nshead requests to the methods of pb services, which is how
+/// several services are served behind one nshead port.
+pub trait NsheadPbServiceAdaptor: Sync + Send + 'static {
+    /// The service, by full or short name, and the name of the method to
+    /// call for the request of `head` and `body`.
+    fn parse_method_info(&self, head: &Header, body: &[u8]) -> Result<(String, String)>;
+
+    /// The request of `method` in `body`, which is the body as it is by default.
+    fn parse_request_from_body(&self, _method: &MethodDescriptor, body: &[u8]) -> Result<Vec<u8>> {
+        Ok(body.to_vec())
     }
 
-    #[instrument(skip_all)]
-    async fn parse(&self, stream: &mut TcpStream) -> Result<CommonMsg> {
-        let mut buf = BytesMut::with_capacity(BUF_SIZE);
-        loop {
-            if stream.read_buf(&mut buf).await? == 0 {
-                return Err(ParseErr::UnexpectedEof.into());
-            }
-            debug!("read from stream: {buf:?}");
+    /// The body of the response `res` of `method`, which is none if there
+    /// was no method to call. A failed `res` is an empty body by default, as
+    /// is the body of a failure here.
+    fn serialize_response_to_body(
+        &self,
+        _method: Option<&MethodDescriptor>,
+        res: Result<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        Ok(res.unwrap_or_default())
+    }
+}
 
-            if buf.len() >= NSHEAD_SIZE {
-                break;
-            }
-        }
-        let head = &buf[..NSHEAD_SIZE].try_into().unwrap();
-        let head = Header::from_u8_slice(head);
-        if head.magic_num != NSHEAD_MAGICNUM {
-            warn!(%head, "unexpected header");
-            return Err(ParseErr::TryOther.into());
+#[async_trait]
+impl Protocol for Nshead {
+    // bound to the only service of the server, if there is exactly one
+    fn default() -> Self {
+        Nshead {
+            service: None,
+            adaptor: None,
         }
+    }
+    fn protocol_type(&self) -> ProtocolType {
+        ProtocolType::PROTOCOL_NSHEAD
+    }
+    fn name(&self) -> &'static str {
+        "nshead"
+    }
+
+    #[instrument(skip_all)]
+    fn parse(&self, buf: &mut BytesMut) -> Result<CommonMsg> {
+        let head = peek_frame(buf)?;
         debug!(%head, "finish to parse nshead");
 
-        let _ = buf.split_to(NSHEAD_SIZE);
-        loop {
-            match head.body_size.cmp(&(buf.len() as u32)) {
-                Ordering::Equal => break,
-                Ordering::Greater => {
-                    if stream.read_buf(&mut buf).await? == 0 {
-                        return Err(ParseErr::UnexpectedEof.into());
-                    }
-                    debug!("read from stream: {buf:?}");
-                }
-                Ordering::Less => unimplemented!(),
-            };
-        }
+        let meta = buf.split_to(NSHEAD_SIZE);
+        let body = buf.split_to(head.body_size as usize);
+        let mut msg = CommonMsg::new(body.to_vec());
+        msg.with_meta(meta.to_vec());
 
-        Ok(CommonMsg::new(buf.to_vec()))
+        Ok(msg)
     }
 
     #[instrument(skip_all)]
     async fn process_request(
         &self,
+        conn: &Connection,
+        services: &Services,
         msg: CommonMsg,
-        // services: &HashMap<&'static str, Box<dyn Service>>,
-    ) -> Result<CommonMsg> {
-        // let (_, svc) = services
-        //     .iter()
-        //     .next()
-        //     .ok_or_else(|| SvcErr::NotExist("nshead".to_string()))?;
-        let (_, svc) = self.0.as_ref().unwrap();
-        let msg = svc.call_method("", &msg.payload).await?;
-        let msg = CommonMsg::new(msg);
-        Ok(msg)
+    ) -> Result<Option<CommonMsg>> {
+        if let Some(adaptor) = &self.adaptor {
+            return Ok(process_by_adaptor(adaptor.as_ref(), conn, services, msg).await);
+        }
+        let mut ctx = Context::default();
+        ctx.set_peer_certificate(conn.peer_certificate());
+        ctx.set_nshead_header(header_of(&msg));
+        let svc = match &self.service {
+            Some((svc_name, method_name)) => services
+                .get(svc_name)
+                .map(|svc| (svc, method_name.as_str())),
+            None if services.len() == 1 => Ok((services.iter().next().unwrap().1, "")),
+            None => Err(SvcErr::NotExist("nshead".to_string()).into()),
+        };
+        let res = match svc {
+            Ok((svc, method_name)) => svc.call_method(&mut ctx, method_name, &msg.payload).await,
+            Err(e) => Err(e),
+        };
+        let body = res.unwrap_or_else(|e| {
+            debug!("nshead request err:{e}");
+            vec![]
+        });
+
+        Ok(Some(response(&ctx, body)))
     }
 
     #[instrument(skip_all)]
     fn pack_response(&self, msg: CommonMsg) -> Vec<u8> {
-        let msg = msg.to_vec();
-
-        let mut head = Header::default_with_len(msg.len() as u32);
-        head.body_size = msg.len() as u32;
-
-        let mut buffer = BytesMut::with_capacity(NSHEAD_SIZE + msg.len());
-        buffer.put(head.as_u8_slice());
-        buffer.put(msg.as_slice());
-
-        buffer.to_vec()
+        pack_frame(header_of(&msg), &msg.payload)
     }
 
     #[instrument(skip_all)]
-    async fn process_response(&self, msg: CommonMsg) -> Result<Vec<u8>> {
+    async fn process_response(&self, cntl: &mut Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        cntl.set_nshead_response_header(header_of(&msg));
         Ok(msg.payload)
     }
 
     #[instrument(skip_all)]
-    fn pack_request(&self, msg: CommonMsg) -> Vec<u8> {
-        let msg = msg.to_vec();
+    fn pack_request(&self, cntl: &Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        Ok(pack_frame(request_header(cntl), &msg.to_vec()))
+    }
+}
 
-        let mut nshead = Header::default_with_len(msg.len() as u32);
-        nshead.body_size = msg.len() as u32;
+async fn process_by_adaptor(
+    adaptor: &dyn NsheadPbServiceAdaptor,
+    conn: &Connection,
+    services: &Services,
+    msg: CommonMsg,
+) -> Option<CommonMsg> {
+    let mut ctx = Context::default();
+    ctx.set_peer_certificate(conn.peer_certificate());
+    ctx.set_nshead_header(header_of(&msg));
+    let method = adaptor
+        .parse_method_info(&header_of(&msg), &msg.payload)
+        .and_then(|(svc_name, method_name)| {
+            let svc_name = services.full_name(&svc_name)?;
+            match services.method(svc_name, &method_name) {
+                Some(method) => Ok((svc_name, method)),
+                None => {
+                    let text = format!("no method {method_name} of {svc_name}");
+                    Err(RpcErr::Failed(ENOMETHOD, text).into())
+                }
+            }
+        });
+    let (method, res) = match method {
+        Ok((svc_name, method)) => {
+            let res = match adaptor.parse_request_from_body(method, &msg.payload) {
+                Ok(req) => match services.get(svc_name) {
+                    Ok(svc) => svc.call_method(&mut ctx, method.name, &req).await,
+                    Err(e) => Err(e),
+                },
+                Err(e) => Err(e),
+            };
+            if method.request_talk_type == TalkType::TALK_TYPE_ONEWAY {
+                if let Err(e) = res {
+                    warn!("one-way method {svc_name}.{} err:{e}", method.name);
+                }
+                return None;
+            }
+            (Some(method), res)
+        }
+        Err(e) => (None, Err(e)),
+    };
+    if let Err(e) = &res {
+        debug!("nshead request err:{e}");
+    }
+    let body = adaptor
+        .serialize_response_to_body(method, res)
+        .unwrap_or_else(|e| {
+            warn!("serialize nshead response err:{e}");
+            vec![]
+        });
+
+    Some(response(&ctx, body))
+}
+
+/// The response of `body` with the nshead header of the response in `ctx` as its meta.
+pub(crate) fn response(ctx: &Context, body: Vec<u8>) -> CommonMsg {
+    let head = ctx.nshead_response_header().copied().unwrap_or_default();
+    let mut msg = CommonMsg::new(body);
+    msg.with_meta(head.as_u8_slice().to_vec());
+    msg
+}
+
+/// The header of the nshead frame at the front of `buf`, once all of the frame is there.
+pub(crate) fn peek_frame(buf: &[u8]) -> Result<Header> {
+    if buf.len() < NSHEAD_SIZE {
+        return Err(ParseErr::NotEnoughData.into());
+    }
+    let head = Header::from_u8_slice(buf[..NSHEAD_SIZE].try_into().unwrap());
+    if head.magic_num != NSHEAD_MAGICNUM {
+        debug!(%head, "unexpected header");
+        return Err(ParseErr::TryOther.into());
+    }
+    if buf.len() < NSHEAD_SIZE + head.body_size as usize {
+        return Err(ParseErr::NotEnoughData.into());
+    }
 
-        let mut buffer = BytesMut::with_capacity(NSHEAD_SIZE + msg.len());
-        buffer.put(nshead.as_u8_slice());
-        buffer.put(msg.as_slice());
+    Ok(head)
+}
 
-        buffer.to_vec()
+/// The header kept in the meta of `msg` by `parse`, or the default one.
+pub(crate) fn header_of(msg: &CommonMsg) -> Header {
+    match msg.meta[..].try_into() {
+        Ok(bytes) => Header::from_u8_slice(bytes),
+        Err(_) => Header::default(),
     }
 }
 
+/// The header of a request with the log_id and provider set by `cntl`.
+pub(crate) fn request_header(cntl: &Controller) -> Header {
+    let mut head = Header::default();
+    head.set_log_id(cntl.nshead_log_id());
+    head.set_provider(cntl.nshead_provider().as_bytes());
+    head
+}
+
+pub(crate) fn pack_frame(mut head: Header, body: &[u8]) -> Vec<u8> {
+    head.body_size = body.len() as u32;
+
+    let mut buffer = BytesMut::with_capacity(NSHEAD_SIZE + body.len());
+    buffer.put(head.as_u8_slice());
+    buffer.put(body);
+
+    buffer.to_vec()
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct Header {
     id: u16,
-    version: u16,
-    log_id: u32,
+    pub(crate) version: u16,
+    pub(crate) log_id: u32,
     provider: [u8; 16],
     pub magic_num: u32,
-    reserved: u32,
+    pub(crate) reserved: u32,
     pub body_size: u32,
 }
 
@@ -166,11 +302,49 @@ impl fmt::Display for Header {
 }
 
 impl Header {
-    pub fn default_with_len(body_len: u32) -> Self {
-        Self {
-            body_size: body_len,
-            ..Default::default()
-        }
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    pub fn log_id(&self) -> u32 {
+        self.log_id
+    }
+
+    /// The provider without the trailing NULs.
+    pub fn provider(&self) -> &[u8] {
+        let len = self.provider.iter().position(|b| *b == 0);
+        &self.provider[..len.unwrap_or(self.provider.len())]
+    }
+
+    pub fn reserved(&self) -> u32 {
+        self.reserved
+    }
+
+    pub fn set_id(&mut self, id: u16) {
+        self.id = id;
+    }
+
+    pub fn set_version(&mut self, version: u16) {
+        self.version = version;
+    }
+
+    pub fn set_log_id(&mut self, log_id: u32) {
+        self.log_id = log_id;
+    }
+
+    /// Set the provider to `provider` cut to 16 bytes.
+    pub fn set_provider(&mut self, provider: &[u8]) {
+        let len = provider.len().min(self.provider.len());
+        self.provider = Default::default();
+        self.provider[..len].copy_from_slice(&provider[..len]);
+    }
+
+    pub fn set_reserved(&mut self, reserved: u32) {
+        self.reserved = reserved;
     }
 
     pub fn from_u8_slice(bytes: &[u8; ::std::mem::size_of::<Self>()]) -> Self {
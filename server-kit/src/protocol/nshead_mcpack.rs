@@ -0,0 +1,123 @@
+use async_trait::async_trait;
+use bytes::BytesMut;
+use tracing::{debug, instrument};
+
+use server_kit_protocol::options::ProtocolType;
+
+use super::nshead::{self, NSHEAD_SIZE};
+use super::Protocol;
+use crate::error::{ParseErr, SvcErr};
+use crate::mcpack::{pb, Format};
+use crate::message::CommonMsg;
+use crate::socket::Connection;
+use crate::{Context, Controller, Result, Services};
+
+/// nshead with mcpack2 bodies, which servers convert from and to the pb
+/// messages of the bound method, and channels send and receive as they are.
+///
+/// Sniffing can't tell it from nshead, so servers serve it only if enabled by
+/// name or `add_protocol`, and without nshead which is tried first.
+pub struct NsheadMcpack {
+    // service name and method name
+    service: Option<(String, String)>,
+}
+
+impl NsheadMcpack {
+    pub fn with_service(svc_name: impl Into<String>, method_name: impl Into<String>) -> Self {
+        Self {
+            service: Some((svc_name.into(), method_name.into())),
+        }
+    }
+}
+
+// whether `body` starts with an object without name, as mcpack bodies do
+pub(crate) fn is_mcpack(body: &[u8]) -> bool {
+    matches!(body, [0x10, 0, ..])
+}
+
+#[async_trait]
+impl Protocol for NsheadMcpack {
+    // bound to the only method of the only service of the server
+    fn default() -> Self {
+        NsheadMcpack { service: None }
+    }
+    fn protocol_type(&self) -> ProtocolType {
+        ProtocolType::PROTOCOL_NSHEAD_MCPACK
+    }
+    fn name(&self) -> &'static str {
+        "nshead_mcpack"
+    }
+
+    #[instrument(skip_all)]
+    fn parse(&self, buf: &mut BytesMut) -> Result<CommonMsg> {
+        let head = nshead::peek_frame(buf)?;
+        let body_size = head.body_size as usize;
+        if !is_mcpack(&buf[NSHEAD_SIZE..NSHEAD_SIZE + body_size]) {
+            return Err(ParseErr::TryOther.into());
+        }
+        debug!(%head, "finish to parse nshead_mcpack");
+
+        let meta = buf.split_to(NSHEAD_SIZE);
+        let body = buf.split_to(body_size);
+        let mut msg = CommonMsg::new(body.to_vec());
+        msg.with_meta(meta.to_vec());
+
+        Ok(msg)
+    }
+
+    fn is_enabled_by_default(&self) -> bool {
+        false
+    }
+
+    #[instrument(skip_all)]
+    async fn process_request(
+        &self,
+        conn: &Connection,
+        services: &Services,
+        msg: CommonMsg,
+    ) -> Result<Option<CommonMsg>> {
+        let (svc_name, method) = match &self.service {
+            Some((svc_name, method_name)) => {
+                (svc_name.as_str(), services.method(svc_name, method_name))
+            }
+            None if services.len() == 1 => {
+                let svc_name = services.iter().next().unwrap().0;
+                let methods = &services.descriptor(svc_name).unwrap().methods;
+                (svc_name, methods.first().filter(|_| methods.len() == 1))
+            }
+            None => return Err(SvcErr::NotExist("nshead_mcpack".to_string()).into()),
+        };
+        let method = method.ok_or_else(|| SvcErr::NotExist(format!("method of {svc_name}")))?;
+        let (req_ty, resp_ty) = pb::message_types(method, &format!("{svc_name}.{}", method.name))?;
+
+        let req = pb::mcpack_to_pb(req_ty, &msg.payload)?;
+        let mut ctx = Context::default();
+        ctx.set_peer_certificate(conn.peer_certificate());
+        ctx.set_nshead_header(nshead::header_of(&msg));
+        let svc = services.get(svc_name)?;
+        let resp = svc.call_method(&mut ctx, method.name, &req).await?;
+        let body = pb::pb_to_mcpack(resp_ty, &resp, Format::Mcpack2)?;
+
+        Ok(Some(nshead::response(&ctx, body)))
+    }
+
+    #[instrument(skip_all)]
+    fn pack_response(&self, msg: CommonMsg) -> Vec<u8> {
+        nshead::pack_frame(nshead::header_of(&msg), &msg.payload)
+    }
+
+    // the payload of `msg` is the mcpack body
+    #[instrument(skip_all)]
+    fn pack_request(&self, cntl: &Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        Ok(nshead::pack_frame(
+            nshead::request_header(cntl),
+            &msg.payload,
+        ))
+    }
+
+    #[instrument(skip_all)]
+    async fn process_response(&self, cntl: &mut Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        cntl.set_nshead_response_header(nshead::header_of(&msg));
+        Ok(msg.payload)
+    }
+}
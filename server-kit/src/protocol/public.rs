@@ -0,0 +1,200 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use protobuf::{Enum, Message, MessageField};
+use tracing::{debug, instrument, warn};
+
+use server_kit_protocol::options::{ProtocolType, TalkType};
+use server_kit_protocol::public_pbrpc_meta::{
+    PublicPbrpcRequest, PublicPbrpcResponse, RequestBody, RequestHead, ResponseBody, ResponseHead,
+};
+
+use super::nshead::{self, NSHEAD_SIZE};
+use super::Protocol;
+use crate::compress;
+use crate::error::{ParseErr, RpcErr, ENOMETHOD};
+use crate::message::CommonMsg;
+use crate::socket::Connection;
+use crate::{Context, Controller, Result, Services};
+
+/// public_pbrpc, which is nshead with a `PublicPbrpcRequest` or
+/// `PublicPbrpcResponse` as the body, wrapping the pb message along with the
+/// service name, the index of the method in the service and the compression.
+///
+/// Sniffing can't always tell public_pbrpc from nshead, so servers serve it
+/// only if enabled by name or `add_protocol`, in front of nshead.
+pub struct PublicPbrpc;
+
+// whether `body` is a request with a method to call, or a response
+fn is_public_pbrpc(body: &[u8]) -> bool {
+    match PublicPbrpcRequest::parse_from_bytes(body) {
+        Ok(req) => !req.request_body.is_empty(),
+        Err(_) => PublicPbrpcResponse::parse_from_bytes(body)
+            .is_ok_and(|resp| resp.response_head.is_some()),
+    }
+}
+
+#[async_trait]
+impl Protocol for PublicPbrpc {
+    fn default() -> Self {
+        PublicPbrpc
+    }
+    fn protocol_type(&self) -> ProtocolType {
+        ProtocolType::PROTOCOL_PUBLIC_PBRPC
+    }
+    fn name(&self) -> &'static str {
+        "public_pbrpc"
+    }
+
+    #[instrument(skip_all)]
+    fn parse(&self, buf: &mut BytesMut) -> Result<CommonMsg> {
+        let head = nshead::peek_frame(buf)?;
+        let body_size = head.body_size as usize;
+        if !is_public_pbrpc(&buf[NSHEAD_SIZE..NSHEAD_SIZE + body_size]) {
+            return Err(ParseErr::TryOther.into());
+        }
+        debug!(%head, "finish to parse public_pbrpc");
+
+        let meta = buf.split_to(NSHEAD_SIZE);
+        let body = buf.split_to(body_size);
+        let mut msg = CommonMsg::new(body.to_vec());
+        msg.with_meta(meta.to_vec());
+
+        Ok(msg)
+    }
+
+    fn is_enabled_by_default(&self) -> bool {
+        false
+    }
+
+    #[instrument(skip_all)]
+    async fn process_request(
+        &self,
+        conn: &Connection,
+        services: &Services,
+        msg: CommonMsg,
+    ) -> Result<Option<CommonMsg>> {
+        // request, of which only the first body is served as brpc does
+        let mut req = PublicPbrpcRequest::parse_from_bytes(&msg.payload)?;
+        let body = req.request_body.swap_remove(0);
+        let id = body.id();
+        let compress_type =
+            compress::compress_type_from_i32(req.request_head.compress_type() as i32)?;
+        let payload = compress::decompress(compress_type, body.serialized_request())?;
+        let mut ctx = Context::default();
+        ctx.set_peer_certificate(conn.peer_certificate());
+        ctx.set_request_compress_type(compress_type);
+        ctx.set_nshead_header(nshead::header_of(&msg));
+
+        // process
+        let svc_name = match services.full_name(body.service()) {
+            Ok(svc_name) => svc_name,
+            Err(e) => {
+                let code = super::error_code(&e);
+                return Ok(Some(error_response(&ctx, id, code, e.to_string())?));
+            }
+        };
+        let method = services
+            .descriptor(svc_name)
+            .and_then(|svc_desc| svc_desc.methods.get(body.method_id() as usize));
+        let method = match method {
+            Some(method) => method,
+            None => {
+                let text = format!("no method {} of {svc_name}", body.method_id());
+                return Ok(Some(error_response(&ctx, id, ENOMETHOD, text)?));
+            }
+        };
+        let svc = services.get(svc_name)?;
+        let res = svc.call_method(&mut ctx, method.name, &payload).await;
+        if method.request_talk_type == TalkType::TALK_TYPE_ONEWAY {
+            if let Err(e) = res {
+                warn!("one-way method {svc_name}.{} err:{e}", method.name);
+            }
+            return Ok(None);
+        }
+        let msg = match res {
+            Ok(msg) => msg,
+            Err(e) => {
+                let code = super::error_code(&e);
+                return Ok(Some(error_response(&ctx, id, code, e.to_string())?));
+            }
+        };
+
+        // response, compressed as the request unless the handler says otherwise
+        let compress_type = ctx.response_compress_type().unwrap_or(compress_type);
+        let mut head = ResponseHead::new();
+        head.set_code(0);
+        head.set_text("success".to_string());
+        head.set_compress_type(compress_type.value() as u32);
+        let mut body = ResponseBody::new();
+        body.set_id(id);
+        body.set_serialized_response(compress::compress(compress_type, &msg)?);
+        let mut resp = PublicPbrpcResponse::new();
+        resp.response_head = MessageField::some(head);
+        resp.response_body.push(body);
+
+        Ok(Some(nshead::response(&ctx, resp.write_to_bytes()?)))
+    }
+
+    #[instrument(skip_all)]
+    fn pack_response(&self, msg: CommonMsg) -> Vec<u8> {
+        nshead::pack_frame(nshead::header_of(&msg), &msg.payload)
+    }
+
+    // the meta of `msg` is a `RequestBody` with the service name and method index
+    #[instrument(skip_all)]
+    fn pack_request(&self, cntl: &Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+        let compress_type = cntl.request_compress_type();
+        let mut head = RequestHead::new();
+        head.set_compress_type(compress_type.value() as u32);
+        let mut body = RequestBody::parse_from_bytes(&msg.meta)?;
+        body.set_id(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        body.set_serialized_request(compress::compress(compress_type, &msg.payload)?);
+        let mut req = PublicPbrpcRequest::new();
+        req.request_head = MessageField::some(head);
+        req.request_body.push(body);
+
+        Ok(nshead::pack_frame(
+            nshead::request_header(cntl),
+            &req.write_to_bytes()?,
+        ))
+    }
+
+    #[instrument(skip_all)]
+    async fn process_response(&self, cntl: &mut Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        cntl.set_nshead_response_header(nshead::header_of(&msg));
+        let mut resp = PublicPbrpcResponse::parse_from_bytes(&msg.payload)?;
+        let head = resp.response_head.take().unwrap_or_default();
+        if head.code() != 0 {
+            return Err(RpcErr::Failed(head.code(), head.text().to_string()).into());
+        }
+        let body = match resp.response_body.is_empty() {
+            true => ResponseBody::new(),
+            false => resp.response_body.swap_remove(0),
+        };
+        if body.error() != 0 {
+            return Err(RpcErr::Failed(body.error(), head.text().to_string()).into());
+        }
+        let compress_type = compress::compress_type_from_i32(head.compress_type() as i32)?;
+        cntl.set_response_compress_type(compress_type);
+
+        compress::decompress(compress_type, body.serialized_response())
+    }
+}
+
+// the response of a failed call, with the nshead header of the response in `ctx`
+fn error_response(ctx: &Context, id: u64, code: i32, text: String) -> Result<CommonMsg> {
+    let mut head = ResponseHead::new();
+    head.set_code(code);
+    head.set_text(text);
+    let mut body = ResponseBody::new();
+    body.set_id(id);
+    body.set_error(code);
+    let mut resp = PublicPbrpcResponse::new();
+    resp.response_head = MessageField::some(head);
+    resp.response_body.push(body);
+    Ok(nshead::response(ctx, resp.write_to_bytes()?))
+}
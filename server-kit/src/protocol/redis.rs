@@ -0,0 +1,440 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use tracing::{debug, instrument};
+
+use server_kit_protocol::options::ProtocolType;
+
+use super::Protocol;
+use crate::error::{ParseErr, RedisErr, SvcErr};
+use crate::message::CommonMsg;
+use crate::socket::Connection;
+use crate::{Context, Controller, Result, Services};
+
+// nesting of arrays deeper than this is taken as malformed
+const MAX_DEPTH: usize = 64;
+// same as the default proto-max-bulk-len of redis
+const MAX_BULK_LEN: i64 = 512 * 1024 * 1024;
+
+/// RESP2 spoken by redis. On the server side, commands go to the handlers
+/// of the `RedisService` the protocol is made with.
+///
+/// A message is a raw command or a raw reply.
+///
+/// Servers only serve it if enabled, by name or `add_protocol`, as the first
+/// byte of a command is all sniffing can go by.
+pub struct Redis {
+    service: Option<RedisService>,
+}
+
+impl Redis {
+    pub fn with_service(service: RedisService) -> Self {
+        Self {
+            service: Some(service),
+        }
+    }
+}
+
+/// Handles one redis command on the server side.
+#[async_trait]
+pub trait RedisCommandHandler: Send + Sync + 'static {
+    /// `args` are the command name as sent followed by its arguments. Errors
+    /// are replied as `-ERR` with their message.
+    async fn run(&self, ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisReply>;
+}
+
+/// Handlers of the redis commands a server serves, by case insensitive name.
+#[derive(Default)]
+pub struct RedisService {
+    handlers: HashMap<String, Box<dyn RedisCommandHandler>>,
+}
+
+impl RedisService {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_command_handler<H>(&mut self, name: &str, handler: H) -> Result<()>
+    where
+        H: RedisCommandHandler,
+    {
+        let name = name.to_ascii_lowercase();
+        if self.handlers.contains_key(&name) {
+            return Err(SvcErr::Exist(name).into());
+        }
+        self.handlers.insert(name, Box::new(handler));
+        Ok(())
+    }
+
+    async fn call(&self, ctx: &mut Context, command: &[u8]) -> RedisReply {
+        let args = match parse_reply(command, 0) {
+            Ok(Some((RedisReply::Array(args), _))) => args
+                .into_iter()
+                .map(|arg| match arg {
+                    RedisReply::Bulk(arg) => Some(arg),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>(),
+            _ => None,
+        };
+        let args = match args {
+            Some(args) if !args.is_empty() => args,
+            _ => {
+                return RedisReply::Error(
+                    "ERR Protocol error: expected an array of bulk strings".to_string(),
+                )
+            }
+        };
+        let name = String::from_utf8_lossy(&args[0]).to_ascii_lowercase();
+        let handler = match self.handlers.get(&name) {
+            Some(handler) => handler,
+            None => return RedisReply::Error(format!("ERR unknown command '{name}'")),
+        };
+        match handler.run(ctx, &args).await {
+            Ok(reply) => reply,
+            Err(e) => {
+                debug!("redis command {name} failed: {e}");
+                RedisReply::Error(format!("ERR {e}"))
+            }
+        }
+    }
+}
+
+/// Commands sent in one go by `Channel<Redis>`, replied to in the same order.
+#[derive(Debug, Clone, Default)]
+pub struct RedisRequest {
+    buf: Vec<u8>,
+    count: usize,
+}
+
+impl RedisRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a command given as its name and arguments, as in `["SET", "key", "value"]`.
+    pub fn command<I, A>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = A>,
+        A: AsRef<[u8]>,
+    {
+        let args: Vec<A> = args.into_iter().collect();
+        self.buf
+            .extend_from_slice(format!("*{}\r\n", args.len()).as_bytes());
+        for arg in &args {
+            let arg = arg.as_ref();
+            self.buf
+                .extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            self.buf.extend_from_slice(arg);
+            self.buf.extend_from_slice(b"\r\n");
+        }
+        self.count += 1;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+impl From<RedisRequest> for CommonMsg {
+    fn from(req: RedisRequest) -> Self {
+        CommonMsg::new(req.buf)
+    }
+}
+
+/// A reply to a redis command. Errors replied by the server are replies
+/// like any other, not errors of the call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RedisReply {
+    /// Null bulk string or null array.
+    Nil,
+    Status(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    Array(Vec<RedisReply>),
+}
+
+impl RedisReply {
+    pub fn is_nil(&self) -> bool {
+        matches!(self, RedisReply::Nil)
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, RedisReply::Error(_))
+    }
+
+    /// Content of a bulk string or status.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            RedisReply::Bulk(bytes) => Some(bytes),
+            RedisReply::Status(status) => Some(status.as_bytes()),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        self.as_bytes()
+            .and_then(|bytes| std::str::from_utf8(bytes).ok())
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            RedisReply::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[RedisReply]> {
+        match self {
+            RedisReply::Array(replies) => Some(replies),
+            _ => None,
+        }
+    }
+
+    /// Encode as RESP.
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.encode(&mut buf);
+        buf
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            RedisReply::Nil => buf.extend_from_slice(b"$-1\r\n"),
+            // CR and LF would end the line early
+            RedisReply::Status(status) => {
+                buf.push(b'+');
+                buf.extend(status.bytes().filter(|b| !matches!(b, b'\r' | b'\n')));
+                buf.extend_from_slice(b"\r\n");
+            }
+            RedisReply::Error(err) => {
+                buf.push(b'-');
+                buf.extend(err.bytes().filter(|b| !matches!(b, b'\r' | b'\n')));
+                buf.extend_from_slice(b"\r\n");
+            }
+            RedisReply::Integer(i) => buf.extend_from_slice(format!(":{i}\r\n").as_bytes()),
+            RedisReply::Bulk(bytes) => {
+                buf.extend_from_slice(format!("${}\r\n", bytes.len()).as_bytes());
+                buf.extend_from_slice(bytes);
+                buf.extend_from_slice(b"\r\n");
+            }
+            RedisReply::Array(replies) => {
+                buf.extend_from_slice(format!("*{}\r\n", replies.len()).as_bytes());
+                for reply in replies {
+                    reply.encode(buf);
+                }
+            }
+        }
+    }
+
+    /// Parse `buf`, which must hold exactly one reply, as framed by `Redis::parse`.
+    pub(crate) fn parse(buf: &[u8]) -> Result<Self> {
+        match parse_reply(buf, 0)? {
+            Some((reply, len)) if len == buf.len() => Ok(reply),
+            _ => Err(RedisErr::Invalid("not one reply".to_string()).into()),
+        }
+    }
+}
+
+impl fmt::Display for RedisReply {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RedisReply::Nil => write!(f, "(nil)"),
+            RedisReply::Status(status) => write!(f, "{status}"),
+            RedisReply::Error(err) => write!(f, "(error) {err}"),
+            RedisReply::Integer(i) => write!(f, "(integer) {i}"),
+            RedisReply::Bulk(bytes) => write!(f, "{:?}", String::from_utf8_lossy(bytes)),
+            RedisReply::Array(replies) => {
+                write!(f, "[")?;
+                for (i, reply) in replies.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{reply}")?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+// the line starting at `buf` without its CRLF, and the length taken with it
+fn parse_line(buf: &[u8]) -> Option<(&[u8], usize)> {
+    let end = buf.windows(2).position(|w| w == b"\r\n")?;
+    Some((&buf[..end], end + 2))
+}
+
+fn parse_int(line: &[u8]) -> Result<i64> {
+    std::str::from_utf8(line)
+        .ok()
+        .and_then(|line| line.parse().ok())
+        .ok_or_else(|| RedisErr::Invalid(String::from_utf8_lossy(line).into_owned()).into())
+}
+
+fn parse_string(line: &[u8]) -> String {
+    String::from_utf8_lossy(line).into_owned()
+}
+
+/// The reply at the start of `buf` and its length, none if it is incomplete.
+pub(crate) fn parse_reply(buf: &[u8], depth: usize) -> Result<Option<(RedisReply, usize)>> {
+    if depth > MAX_DEPTH {
+        return Err(RedisErr::Invalid("too deeply nested".to_string()).into());
+    }
+    let Some(&ty) = buf.first() else {
+        return Ok(None);
+    };
+    let Some((line, mut len)) = parse_line(&buf[1..]) else {
+        return Ok(None);
+    };
+    len += 1;
+
+    let reply = match ty {
+        b'+' => RedisReply::Status(parse_string(line)),
+        b'-' => RedisReply::Error(parse_string(line)),
+        b':' => RedisReply::Integer(parse_int(line)?),
+        b'$' => match parse_int(line)? {
+            -1 => RedisReply::Nil,
+            size @ 0..=MAX_BULK_LEN => {
+                let size = size as usize;
+                if buf.len() < len + size + 2 {
+                    return Ok(None);
+                }
+                if &buf[len + size..len + size + 2] != b"\r\n" {
+                    return Err(RedisErr::Invalid("bulk string without crlf".to_string()).into());
+                }
+                let bytes = buf[len..len + size].to_vec();
+                len += size + 2;
+                RedisReply::Bulk(bytes)
+            }
+            size => return Err(RedisErr::Invalid(format!("bulk string of {size}")).into()),
+        },
+        b'*' => match parse_int(line)? {
+            -1 => RedisReply::Nil,
+            count if count >= 0 => {
+                let mut replies = Vec::with_capacity(count.min(1024) as usize);
+                for _ in 0..count {
+                    match parse_reply(&buf[len..], depth + 1)? {
+                        Some((reply, reply_len)) => {
+                            replies.push(reply);
+                            len += reply_len;
+                        }
+                        None => return Ok(None),
+                    }
+                }
+                RedisReply::Array(replies)
+            }
+            count => return Err(RedisErr::Invalid(format!("array of {count}")).into()),
+        },
+        ty => return Err(RedisErr::Invalid(format!("type {:?}", ty as char)).into()),
+    };
+
+    Ok(Some((reply, len)))
+}
+
+#[async_trait]
+impl Protocol for Redis {
+    // without a service, which is all a channel needs
+    fn default() -> Self {
+        Redis { service: None }
+    }
+    fn protocol_type(&self) -> ProtocolType {
+        ProtocolType::PROTOCOL_REDIS
+    }
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    #[instrument(skip_all)]
+    fn parse(&self, buf: &mut BytesMut) -> Result<CommonMsg> {
+        if !matches!(buf.first(), None | Some(b'+' | b'-' | b':' | b'$' | b'*')) {
+            return Err(ParseErr::TryOther.into());
+        }
+        match parse_reply(buf, 0)? {
+            Some((_, len)) => Ok(CommonMsg::new(buf.split_to(len).to_vec())),
+            None => Err(ParseErr::NotEnoughData.into()),
+        }
+    }
+
+    fn is_enabled_by_default(&self) -> bool {
+        false
+    }
+
+    #[instrument(skip_all)]
+    async fn process_request(
+        &self,
+        conn: &Connection,
+        _services: &Services,
+        msg: CommonMsg,
+    ) -> Result<Option<CommonMsg>> {
+        let reply = match &self.service {
+            Some(service) => {
+                let mut ctx = Context::default();
+                ctx.set_peer_certificate(conn.peer_certificate());
+                service.call(&mut ctx, &msg.payload).await
+            }
+            None => RedisReply::Error("ERR no redis service on this server".to_string()),
+        };
+        Ok(Some(CommonMsg::new(reply.to_vec())))
+    }
+
+    fn pack_response(&self, msg: CommonMsg) -> Vec<u8> {
+        msg.to_vec()
+    }
+
+    #[instrument(skip_all)]
+    fn pack_request(&self, _cntl: &Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        Ok(msg.to_vec())
+    }
+
+    #[instrument(skip_all)]
+    async fn process_response(&self, _cntl: &mut Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        Ok(msg.payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    #[test]
+    fn frame_each_reply() {
+        let mut buf = BytesMut::from(&b"+OK\r\n*2\r\n:1\r\n$-1\r\n$3\r\nab"[..]);
+        let first = Redis::default().parse(&mut buf).unwrap();
+        assert_eq!(
+            RedisReply::parse(&first.payload).unwrap(),
+            RedisReply::Status("OK".to_string())
+        );
+        let second = Redis::default().parse(&mut buf).unwrap();
+        assert_eq!(
+            RedisReply::parse(&second.payload).unwrap(),
+            RedisReply::Array(vec![RedisReply::Integer(1), RedisReply::Nil])
+        );
+        let err = Redis::default().parse(&mut buf).unwrap_err();
+        assert!(
+            matches!(err, Error::Parse(ParseErr::NotEnoughData)),
+            "{err}"
+        );
+
+        buf.extend_from_slice(b"c\r\n");
+        let third = Redis::default().parse(&mut buf).unwrap();
+        assert_eq!(third.payload, b"$3\r\nabc\r\n");
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn parse_one_reply() {
+        assert!(RedisReply::parse(b"+OK\r\n:1\r\n").is_err());
+        assert!(RedisReply::parse(b"$3\r\nab").is_err());
+        let reply = RedisReply::Array(vec![RedisReply::Bulk(b"a\r\n".to_vec())]);
+        assert_eq!(RedisReply::parse(&reply.to_vec()).unwrap(), reply);
+    }
+}
@@ -0,0 +1,271 @@
+use std::sync::{OnceLock, RwLock};
+
+use protobuf::Enum;
+
+use server_kit_protocol::options::ProtocolType;
+
+use super::{
+    Brpc, Grpc, Http, HuluPbrpc, NovaPbrpc, Nshead, NsheadMcpack, Protocol, PublicPbrpc, Redis,
+    SofaPbrpc, Streaming, Thrift, UbrpcCompack, UbrpcMcpack2,
+};
+use crate::error::ProtocolErr;
+use crate::Result;
+
+struct Entry {
+    name: &'static str,
+    // none for protocols registered by name only
+    ty: Option<ProtocolType>,
+    order: (bool, i32),
+    new: fn() -> Box<dyn Protocol>,
+}
+
+// in the order the server tries the protocols in
+type Registry = Vec<Entry>;
+
+// by `ProtocolType` value, except that PROTOCOL_ESP stays the last one as the enum asks,
+// even though PROTOCOL_THRIFT has a larger value
+fn sniff_order(ty: ProtocolType) -> (bool, i32) {
+    (ty == ProtocolType::PROTOCOL_ESP, ty.value())
+}
+
+fn new<P>() -> Box<dyn Protocol>
+where
+    P: Protocol,
+{
+    Box::new(P::default())
+}
+
+fn registry() -> &'static RwLock<Registry> {
+    static REGISTRY: OnceLock<RwLock<Registry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let builtin: [fn() -> Box<dyn Protocol>; 14] = [
+            new::<Brpc>,
+            new::<Streaming>,
+            new::<HuluPbrpc>,
+            new::<SofaPbrpc>,
+            new::<Http>,
+            new::<PublicPbrpc>,
+            new::<NovaPbrpc>,
+            new::<Nshead>,
+            new::<UbrpcCompack>,
+            new::<Redis>,
+            new::<NsheadMcpack>,
+            new::<UbrpcMcpack2>,
+            new::<Grpc>,
+            new::<Thrift>,
+        ];
+        let mut registry: Registry = builtin
+            .into_iter()
+            .map(|new| {
+                let protocol = new();
+                let ty = protocol.protocol_type();
+                Entry {
+                    name: protocol.name(),
+                    ty: Some(ty),
+                    order: sniff_order(ty),
+                    new,
+                }
+            })
+            .collect();
+        registry.sort_by_key(|e| e.order);
+        RwLock::new(registry)
+    })
+}
+
+// after the entries of the same order, so registrations keep their order
+fn insert(entry: Entry) -> Result<()> {
+    let mut registry = registry().write().unwrap();
+    let taken = registry
+        .iter()
+        .any(|e| e.name == entry.name || (entry.ty.is_some() && e.ty == entry.ty));
+    if taken {
+        return Err(ProtocolErr::Exist(entry.name.to_string()).into());
+    }
+    let idx = registry.partition_point(|e| e.order <= entry.order);
+    registry.insert(idx, entry);
+
+    Ok(())
+}
+
+/// Make `P` available by its `protocol_type()` and `name()`, sniffed in the
+/// order of its type.
+///
+/// Fails if either of them is already taken, by a builtin protocol or a previous registration.
+pub fn register_protocol<P>() -> Result<()>
+where
+    P: Protocol,
+{
+    let protocol = P::default();
+    let ty = protocol.protocol_type();
+    insert(Entry {
+        name: protocol.name(),
+        ty: Some(ty),
+        order: sniff_order(ty),
+        new: new::<P>,
+    })
+}
+
+/// Make `P` available by its `name()` only, for protocols `ProtocolType` has
+/// no variant for, whatever their `protocol_type()`.
+///
+/// The server tries it after the protocols of types up to `sniff_order`, as a
+/// `ProtocolType` value, and the ones registered before it there, but always
+/// before PROTOCOL_ESP. Fails if the name is already taken.
+pub fn register_protocol_by_name<P>(sniff_order: i32) -> Result<()>
+where
+    P: Protocol,
+{
+    insert(Entry {
+        name: P::default().name(),
+        ty: None,
+        order: (false, sniff_order),
+        new: new::<P>,
+    })
+}
+
+pub fn new_protocol(ty: ProtocolType) -> Option<Box<dyn Protocol>> {
+    let registry = registry().read().unwrap();
+    registry
+        .iter()
+        .find(|e| e.ty == Some(ty))
+        .map(|e| (e.new)())
+}
+
+pub fn new_protocol_by_name(name: &str) -> Option<Box<dyn Protocol>> {
+    let registry = registry().read().unwrap();
+    registry.iter().find(|e| e.name == name).map(|e| (e.new)())
+}
+
+pub fn protocol_type_by_name(name: &str) -> Option<ProtocolType> {
+    let registry = registry().read().unwrap();
+    registry.iter().find(|e| e.name == name).and_then(|e| e.ty)
+}
+
+/// Types of the protocols registered by type, in the order the server tries them.
+pub fn protocol_types() -> Vec<ProtocolType> {
+    let registry = registry().read().unwrap();
+    registry.iter().filter_map(|e| e.ty).collect()
+}
+
+/// Registered protocols in the order the server tries them on a new connection.
+pub fn protocol_names() -> Vec<&'static str> {
+    let registry = registry().read().unwrap();
+    registry.iter().map(|e| e.name).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use bytes::BytesMut;
+
+    use super::*;
+    use crate::error::ParseErr;
+    use crate::message::CommonMsg;
+    use crate::socket::Connection;
+    use crate::{Controller, Error, Services};
+
+    // protocols only registered, and kept off the servers of other tests
+    macro_rules! test_protocol {
+        ($ty:ident, $name:literal, $protocol_type:ident) => {
+            struct $ty;
+
+            #[async_trait]
+            impl Protocol for $ty {
+                fn default() -> Self {
+                    $ty
+                }
+                fn protocol_type(&self) -> ProtocolType {
+                    ProtocolType::$protocol_type
+                }
+                fn name(&self) -> &'static str {
+                    $name
+                }
+                fn parse(&self, _buf: &mut BytesMut) -> Result<CommonMsg> {
+                    Err(ParseErr::TryOther.into())
+                }
+                async fn process_request(
+                    &self,
+                    _conn: &Connection,
+                    _services: &Services,
+                    _msg: CommonMsg,
+                ) -> Result<Option<CommonMsg>> {
+                    Ok(None)
+                }
+                fn pack_response(&self, msg: CommonMsg) -> Vec<u8> {
+                    msg.payload
+                }
+                fn is_enabled_by_default(&self) -> bool {
+                    false
+                }
+                fn pack_request(&self, _cntl: &Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+                    Ok(msg.payload)
+                }
+                async fn process_response(
+                    &self,
+                    _cntl: &mut Controller,
+                    msg: CommonMsg,
+                ) -> Result<Vec<u8>> {
+                    Ok(msg.payload)
+                }
+            }
+        };
+    }
+
+    test_protocol!(Esp, "esp_test", PROTOCOL_ESP);
+    test_protocol!(Named, "named_test", PROTOCOL_UNKNOWN);
+    test_protocol!(Twin, "twin_test", PROTOCOL_BAIDU_STD);
+
+    #[test]
+    fn lookup() {
+        let protocol = new_protocol(ProtocolType::PROTOCOL_THRIFT).unwrap();
+        assert_eq!(protocol.name(), "thrift");
+        let protocol = new_protocol_by_name("baidu_std").unwrap();
+        assert_eq!(protocol.protocol_type(), ProtocolType::PROTOCOL_BAIDU_STD);
+        assert_eq!(protocol_type_by_name("h2"), Some(ProtocolType::PROTOCOL_H2));
+        assert!(new_protocol_by_name("nope").is_none());
+        assert!(new_protocol(ProtocolType::PROTOCOL_UNKNOWN).is_none());
+    }
+
+    #[test]
+    fn reject_duplicates() {
+        for res in [
+            register_protocol::<Brpc>(),
+            register_protocol::<Twin>(),
+            register_protocol_by_name::<Http>(0),
+        ] {
+            let err = res.unwrap_err();
+            assert!(
+                matches!(err, Error::Protocol(ProtocolErr::Exist(_))),
+                "{err}"
+            );
+        }
+        assert!(new_protocol_by_name("twin_test").is_none());
+    }
+
+    #[test]
+    fn sniff_order() {
+        register_protocol::<Esp>().unwrap();
+        let http = ProtocolType::PROTOCOL_HTTP.value();
+        register_protocol_by_name::<Named>(http).unwrap();
+        assert!(register_protocol_by_name::<Named>(http).is_err());
+
+        // by name only, right after the protocols of types up to its order
+        assert_eq!(
+            new_protocol_by_name("named_test").unwrap().name(),
+            "named_test"
+        );
+        assert_eq!(protocol_type_by_name("named_test"), None);
+        let names = protocol_names();
+        let idx = names.iter().position(|n| *n == "named_test").unwrap();
+        assert_eq!(names[idx - 1], "http");
+
+        // ESP last, after thrift even if registered after it
+        assert_eq!(names.last(), Some(&"esp_test"));
+        let types = protocol_types();
+        assert_eq!(types.last(), Some(&ProtocolType::PROTOCOL_ESP));
+        assert_eq!(types[0], ProtocolType::PROTOCOL_BAIDU_STD);
+        assert!(types
+            .windows(2)
+            .all(|w| { w[1] == ProtocolType::PROTOCOL_ESP || w[0].value() < w[1].value() }));
+    }
+}
@@ -0,0 +1,280 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use async_trait::async_trait;
+use bytes::{BufMut, BytesMut};
+use protobuf::Message;
+use tracing::{debug, instrument, warn};
+
+use server_kit_protocol::options::{CompressType, ProtocolType, TalkType};
+use server_kit_protocol::sofa_pbrpc_meta::sofa_rpc_meta::Type;
+use server_kit_protocol::sofa_pbrpc_meta::{SofaCompressType, SofaRpcMeta};
+
+use super::Protocol;
+use crate::compress;
+use crate::error::{ParseErr, RpcErr, ENOMETHOD};
+use crate::message::CommonMsg;
+use crate::socket::Connection;
+use crate::{Context, Controller, Result, Services};
+
+const HEADER_SIZE: usize = 24;
+const TAG: [u8; 4] = *b"SOFA";
+
+/// sofa_pbrpc of the legacy C++ pb-RPC framework, which names methods in
+/// full as in "package.Service.Method", and has its header in host order.
+///
+/// The meta of a message is a `SofaRpcMeta`. There is no attachment.
+pub struct SofaPbrpc;
+
+fn from_sofa(ty: SofaCompressType) -> CompressType {
+    match ty {
+        SofaCompressType::SOFA_COMPRESS_TYPE_NONE => CompressType::COMPRESS_TYPE_NONE,
+        SofaCompressType::SOFA_COMPRESS_TYPE_GZIP => CompressType::COMPRESS_TYPE_GZIP,
+        SofaCompressType::SOFA_COMPRESS_TYPE_ZLIB => CompressType::COMPRESS_TYPE_ZLIB,
+        SofaCompressType::SOFA_COMPRESS_TYPE_SNAPPY => CompressType::COMPRESS_TYPE_SNAPPY,
+        SofaCompressType::SOFA_COMPRESS_TYPE_LZ4 => CompressType::COMPRESS_TYPE_LZ4,
+    }
+}
+
+fn to_sofa(ty: CompressType) -> SofaCompressType {
+    match ty {
+        CompressType::COMPRESS_TYPE_NONE => SofaCompressType::SOFA_COMPRESS_TYPE_NONE,
+        CompressType::COMPRESS_TYPE_GZIP => SofaCompressType::SOFA_COMPRESS_TYPE_GZIP,
+        CompressType::COMPRESS_TYPE_ZLIB => SofaCompressType::SOFA_COMPRESS_TYPE_ZLIB,
+        CompressType::COMPRESS_TYPE_SNAPPY => SofaCompressType::SOFA_COMPRESS_TYPE_SNAPPY,
+        CompressType::COMPRESS_TYPE_LZ4 => SofaCompressType::SOFA_COMPRESS_TYPE_LZ4,
+    }
+}
+
+#[async_trait]
+impl Protocol for SofaPbrpc {
+    fn default() -> Self {
+        SofaPbrpc
+    }
+    fn protocol_type(&self) -> ProtocolType {
+        ProtocolType::PROTOCOL_SOFA_PBRPC
+    }
+    fn name(&self) -> &'static str {
+        "sofa_pbrpc"
+    }
+
+    #[instrument(skip_all)]
+    fn parse(&self, buf: &mut BytesMut) -> Result<CommonMsg> {
+        let n = buf.len().min(TAG.len());
+        if buf[..n] != TAG[..n] {
+            return Err(ParseErr::TryOther.into());
+        }
+        if buf.len() < HEADER_SIZE {
+            return Err(ParseErr::NotEnoughData.into());
+        }
+        let meta_size = i32::from_le_bytes(buf[4..8].try_into().unwrap()) as i64;
+        let data_size = i64::from_le_bytes(buf[8..16].try_into().unwrap());
+        let msg_size = i64::from_le_bytes(buf[16..24].try_into().unwrap());
+        if meta_size < 0 || data_size < 0 {
+            warn!(meta_size, data_size, msg_size, "negative sizes");
+            return Err(ParseErr::TryOther.into());
+        }
+        if meta_size.checked_add(data_size) != Some(msg_size) {
+            return Err(ParseErr::InvalidFrameSize(msg_size).into());
+        }
+        let frame_size = usize::try_from(msg_size)
+            .ok()
+            .and_then(|size| size.checked_add(HEADER_SIZE))
+            .ok_or(ParseErr::InvalidFrameSize(msg_size))?;
+        if buf.len() < frame_size {
+            return Err(ParseErr::NotEnoughData.into());
+        }
+        debug!(meta_size, data_size, "finish to parse sofa header");
+
+        let _ = buf.split_to(HEADER_SIZE);
+        let mut body = buf.split_to(msg_size as usize);
+        let mut msg = CommonMsg::default();
+        msg.with_meta(body.split_to(meta_size as usize).to_vec());
+        msg.with_payload(body.to_vec());
+
+        Ok(msg)
+    }
+
+    #[instrument(skip_all)]
+    async fn process_request(
+        &self,
+        conn: &Connection,
+        services: &Services,
+        msg: CommonMsg,
+    ) -> Result<Option<CommonMsg>> {
+        // request
+        let meta = SofaRpcMeta::parse_from_bytes(&msg.meta)?;
+        let sequence_id = meta.sequence_id();
+        let compress_type = from_sofa(meta.compress_type());
+        let payload = compress::decompress(compress_type, &msg.payload)?;
+
+        // process
+        let (svc_name, method_name) = meta.method().rsplit_once('.').unwrap_or_default();
+        let svc_name = match services.full_name(svc_name) {
+            Ok(svc_name) => svc_name,
+            Err(e) => {
+                let code = super::error_code(&e);
+                return Ok(Some(error_response(sequence_id, code, e.to_string())?));
+            }
+        };
+        let method = match services.method(svc_name, method_name) {
+            Some(method) => method,
+            None => {
+                let text = format!("no method {}", meta.method());
+                return Ok(Some(error_response(sequence_id, ENOMETHOD, text)?));
+            }
+        };
+        let mut ctx = Context::default();
+        ctx.set_peer_certificate(conn.peer_certificate());
+        ctx.set_request_compress_type(compress_type);
+        let svc = services.get(svc_name)?;
+        let res = svc.call_method(&mut ctx, method_name, &payload).await;
+        if method.request_talk_type == TalkType::TALK_TYPE_ONEWAY {
+            if let Err(e) = res {
+                warn!("one-way method {} err:{e}", meta.method());
+            }
+            return Ok(None);
+        }
+        let msg = match res {
+            Ok(msg) => msg,
+            Err(e) => {
+                let code = super::error_code(&e);
+                return Ok(Some(error_response(sequence_id, code, e.to_string())?));
+            }
+        };
+
+        // response, compressed as the client expects unless the handler says otherwise
+        let compress_type = match ctx.response_compress_type() {
+            Some(compress_type) => compress_type,
+            None if meta.has_expected_response_compress_type() => {
+                from_sofa(meta.expected_response_compress_type())
+            }
+            None => compress_type,
+        };
+        let mut meta = SofaRpcMeta::new();
+        meta.set_type(Type::RESPONSE);
+        meta.set_sequence_id(sequence_id);
+        meta.set_failed(false);
+        meta.set_compress_type(to_sofa(compress_type));
+        let mut msg = CommonMsg::new(compress::compress(compress_type, &msg)?);
+        msg.with_meta(meta.write_to_bytes()?);
+
+        Ok(Some(msg))
+    }
+
+    #[instrument(skip_all)]
+    fn pack_response(&self, msg: CommonMsg) -> Vec<u8> {
+        pack_frame(msg)
+    }
+
+    // the meta of `msg` is a `SofaRpcMeta` with the full name of the method
+    #[instrument(skip_all)]
+    fn pack_request(&self, cntl: &Controller, mut msg: CommonMsg) -> Result<Vec<u8>> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+        let mut meta = SofaRpcMeta::parse_from_bytes(&msg.meta)?;
+        meta.set_type(Type::REQUEST);
+        meta.set_sequence_id(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        let compress_type = cntl.request_compress_type();
+        if compress_type != CompressType::COMPRESS_TYPE_NONE {
+            meta.set_compress_type(to_sofa(compress_type));
+            msg.with_payload(compress::compress(compress_type, &msg.payload)?);
+        }
+        msg.with_meta(meta.write_to_bytes()?);
+
+        Ok(pack_frame(msg))
+    }
+
+    #[instrument(skip_all)]
+    async fn process_response(&self, cntl: &mut Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        let meta = SofaRpcMeta::parse_from_bytes(&msg.meta)?;
+        if meta.failed() {
+            let text = meta.reason().to_string();
+            return Err(RpcErr::Failed(meta.error_code(), text).into());
+        }
+        let compress_type = from_sofa(meta.compress_type());
+        cntl.set_response_compress_type(compress_type);
+
+        compress::decompress(compress_type, &msg.payload)
+    }
+}
+
+fn error_response(sequence_id: u64, code: i32, text: String) -> Result<CommonMsg> {
+    let mut meta = SofaRpcMeta::new();
+    meta.set_type(Type::RESPONSE);
+    meta.set_sequence_id(sequence_id);
+    meta.set_failed(true);
+    meta.set_error_code(code);
+    meta.set_reason(text);
+    let mut msg = CommonMsg::default();
+    msg.with_meta(meta.write_to_bytes()?);
+    Ok(msg)
+}
+
+// "SOFA", meta size, payload size and both together, all in little endian
+// as sofa puts them in host order
+fn pack_frame(msg: CommonMsg) -> Vec<u8> {
+    let mut buffer = BytesMut::with_capacity(HEADER_SIZE + msg.body_size() as usize);
+    buffer.put_slice(&TAG);
+    buffer.put_i32_le(msg.meta_size() as i32);
+    buffer.put_i64_le(msg.payload_size() as i64);
+    buffer.put_i64_le(msg.body_size() as i64);
+    buffer.put(msg.to_vec().as_slice());
+
+    buffer.to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Error;
+
+    use super::*;
+
+    fn header(meta_size: i32, data_size: i64, msg_size: i64) -> BytesMut {
+        let mut buf = BytesMut::from(&TAG[..]);
+        buf.put_i32_le(meta_size);
+        buf.put_i64_le(data_size);
+        buf.put_i64_le(msg_size);
+        buf
+    }
+
+    #[test]
+    fn request_round_trip() {
+        let mut meta = SofaRpcMeta::new();
+        meta.set_type(Type::REQUEST);
+        meta.set_sequence_id(0);
+        meta.set_method("test.echo.m".to_string());
+        let mut msg = CommonMsg::new(b"hello".to_vec());
+        msg.with_meta(meta.write_to_bytes().unwrap());
+        let buf = SofaPbrpc.pack_request(&Controller::new(), msg).unwrap();
+
+        for n in [2, HEADER_SIZE - 1, buf.len() - 1] {
+            let err = SofaPbrpc.parse(&mut BytesMut::from(&buf[..n])).unwrap_err();
+            assert!(err.to_string().contains("not enough"), "{n}: {err}");
+        }
+        let mut buf = BytesMut::from(&buf[..]);
+        let msg = SofaPbrpc.parse(&mut buf).unwrap();
+        assert!(buf.is_empty());
+        let meta = SofaRpcMeta::parse_from_bytes(&msg.meta).unwrap();
+        assert_eq!(
+            (meta.type_(), meta.method()),
+            (Type::REQUEST, "test.echo.m")
+        );
+        assert_eq!(msg.payload, b"hello");
+    }
+
+    #[test]
+    fn reject_invalid_sizes() {
+        for (meta_size, data_size, msg_size) in [
+            (1, i64::MAX, i64::MAX),
+            (i32::MAX, i64::MAX, i64::MIN),
+            (1, 2, 4),
+        ] {
+            let err = SofaPbrpc
+                .parse(&mut header(meta_size, data_size, msg_size))
+                .unwrap_err();
+            assert!(err.to_string().contains("invalid frame size"), "{err}");
+        }
+        let err = SofaPbrpc.parse(&mut header(-1, 2, 1)).unwrap_err();
+        assert!(matches!(err, Error::Parse(ParseErr::TryOther)), "{err}");
+    }
+}
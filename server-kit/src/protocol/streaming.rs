@@ -0,0 +1,101 @@
+use async_trait::async_trait;
+use bytes::{BufMut, BytesMut};
+use tracing::{debug, instrument};
+
+use server_kit_protocol::options::ProtocolType;
+
+use super::Protocol;
+use crate::error::ParseErr;
+use crate::message::CommonMsg;
+use crate::socket::Connection;
+use crate::{stream, Controller, Result, Services};
+
+const HEADER_SIZE: usize = 12;
+const TAG: [u8; 4] = *b"STRM";
+const TAG_SIZE: usize = 4;
+const BODY_START: usize = TAG_SIZE;
+const META_START: usize = BODY_START + 4;
+
+/// Frames of the streams set up by brpc requests, laid out as brpc messages
+/// with a `StreamFrameMeta` as meta.
+pub struct Streaming;
+
+#[async_trait]
+impl Protocol for Streaming {
+    fn default() -> Self {
+        Streaming
+    }
+    fn protocol_type(&self) -> ProtocolType {
+        ProtocolType::PROTOCOL_STREAMING_RPC
+    }
+    fn name(&self) -> &'static str {
+        "streaming_rpc"
+    }
+    // frames only reach streams set up by requests on the same connection,
+    // which were authenticated
+    fn supports_authentication(&self) -> bool {
+        true
+    }
+
+    #[instrument(skip_all)]
+    fn parse(&self, buf: &mut BytesMut) -> Result<CommonMsg> {
+        let n = buf.len().min(TAG_SIZE);
+        if buf[..n] != TAG[..n] {
+            return Err(ParseErr::TryOther.into());
+        }
+        if buf.len() < HEADER_SIZE {
+            return Err(ParseErr::NotEnoughData.into());
+        }
+        let body_size = u32::from_be_bytes(buf[BODY_START..META_START].try_into().unwrap());
+        let meta_size = u32::from_be_bytes(buf[META_START..HEADER_SIZE].try_into().unwrap());
+        if body_size < meta_size {
+            debug!(body_size, meta_size, "body_size less than meta_size");
+            return Err(ParseErr::TryOther.into());
+        }
+        if buf.len() < HEADER_SIZE + body_size as usize {
+            return Err(ParseErr::NotEnoughData.into());
+        }
+
+        let _ = buf.split_to(HEADER_SIZE);
+        let mut body = buf.split_to(body_size as usize);
+        let mut msg = CommonMsg::default();
+        msg.with_meta(body.split_to(meta_size as usize).to_vec());
+        msg.with_payload(body.to_vec());
+
+        Ok(msg)
+    }
+
+    // frames are handed to their streams and never answered
+    #[instrument(skip_all)]
+    async fn process_request(
+        &self,
+        conn: &Connection,
+        _services: &Services,
+        msg: CommonMsg,
+    ) -> Result<Option<CommonMsg>> {
+        stream::on_frame(conn, msg)?;
+        Ok(None)
+    }
+
+    fn pack_response(&self, msg: CommonMsg) -> Vec<u8> {
+        pack_frame(msg)
+    }
+
+    async fn process_response(&self, _cntl: &mut Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        Ok(msg.payload)
+    }
+
+    fn pack_request(&self, _cntl: &Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        Ok(pack_frame(msg))
+    }
+}
+
+pub(crate) fn pack_frame(msg: CommonMsg) -> Vec<u8> {
+    let mut buffer = BytesMut::with_capacity(HEADER_SIZE + msg.body_size() as usize);
+    buffer.put(TAG.as_slice());
+    buffer.put_u32(msg.body_size());
+    buffer.put_u32(msg.meta_size());
+    buffer.put(msg.to_vec().as_slice());
+
+    buffer.to_vec()
+}
@@ -0,0 +1,371 @@
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use async_trait::async_trait;
+use bytes::{Buf, BufMut, BytesMut};
+use tracing::{debug, instrument};
+
+use server_kit_protocol::options::ProtocolType;
+
+use super::Protocol;
+use crate::error::{ParseErr, RpcErr, SvcErr, ThriftErr};
+use crate::message::CommonMsg;
+use crate::socket::Connection;
+use crate::{Context, Controller, Error, Result, Services};
+
+// same as the default max frame size of apache thrift
+const MAX_FRAME_SIZE: usize = 16384000;
+const VERSION_1: [u8; 3] = [0x80, 0x01, 0x00];
+
+const CALL: u8 = 1;
+const REPLY: u8 = 2;
+const EXCEPTION: u8 = 3;
+const ONEWAY: u8 = 4;
+
+// field types of TBinaryProtocol
+const T_STOP: u8 = 0;
+const T_BOOL: u8 = 2;
+const T_BYTE: u8 = 3;
+const T_DOUBLE: u8 = 4;
+const T_I16: u8 = 6;
+const T_I32: u8 = 8;
+const T_I64: u8 = 10;
+const T_STRING: u8 = 11;
+const T_STRUCT: u8 = 12;
+const T_MAP: u8 = 13;
+const T_SET: u8 = 14;
+const T_LIST: u8 = 15;
+
+// nesting of containers deeper than this is taken as malformed
+const MAX_DEPTH: usize = 64;
+
+/// Types of `TApplicationException`, the error replied to a failed call.
+pub mod exception {
+    pub const UNKNOWN: i32 = 0;
+    pub const UNKNOWN_METHOD: i32 = 1;
+    pub const INVALID_MESSAGE_TYPE: i32 = 2;
+    pub const INTERNAL_ERROR: i32 = 6;
+}
+
+/// TBinaryProtocol messages in TFramedTransport.
+///
+/// The meta of a message is its header of type, method name and seqid, the
+/// payload its struct, which is the args of the method for a call and its
+/// result for a reply. Methods of services get and return these structs as
+/// they are.
+///
+/// Calls go to the bound service, or to the only service of the server.
+/// Method names as in "ServiceName:method" of TMultiplexedProtocol go to that service.
+pub struct Thrift {
+    svc_name: Option<String>,
+    // seqid of the next call of the channel
+    seq_id: AtomicI32,
+}
+
+impl Thrift {
+    pub fn with_service(svc_name: impl Into<String>) -> Self {
+        Self {
+            svc_name: Some(svc_name.into()),
+            seq_id: AtomicI32::new(1),
+        }
+    }
+
+    pub(crate) fn next_seq_id(&self) -> i32 {
+        self.seq_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    // the service of the call of `name`, and the method name
+    fn resolve<'a>(&'a self, services: &'a Services, name: &'a str) -> Result<(&'a str, &'a str)> {
+        if let Some((svc_name, method_name)) = name.split_once(':') {
+            return Ok((svc_name, method_name));
+        }
+        match &self.svc_name {
+            Some(svc_name) => Ok((svc_name, name)),
+            None if services.len() == 1 => Ok((services.iter().next().unwrap().0, name)),
+            None => Err(SvcErr::NotExist("thrift".to_string()).into()),
+        }
+    }
+}
+
+struct MessageHeader {
+    name: String,
+    ty: u8,
+    seq_id: i32,
+}
+
+impl MessageHeader {
+    fn decode(mut buf: &[u8]) -> Result<Self> {
+        let version = take(&mut buf, 4)?;
+        if version[..3] != VERSION_1 {
+            return Err(ThriftErr::Invalid(format!("version {version:?}")).into());
+        }
+        let name = read_binary(&mut buf)?;
+        let name = String::from_utf8(name.to_vec())
+            .map_err(|_| ThriftErr::Invalid("method name".to_string()))?;
+        let seq_id = take(&mut buf, 4)?.get_i32();
+
+        Ok(Self {
+            name,
+            ty: version[3],
+            seq_id,
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(12 + self.name.len());
+        buf.put_slice(&VERSION_1);
+        buf.put_u8(self.ty);
+        buf.put_i32(self.name.len() as i32);
+        buf.put_slice(self.name.as_bytes());
+        buf.put_i32(self.seq_id);
+        buf
+    }
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if buf.len() < len {
+        return Err(ThriftErr::Invalid("truncated".to_string()).into());
+    }
+    let (head, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(head)
+}
+
+fn read_binary<'a>(buf: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let len = take(buf, 4)?.get_i32();
+    if len < 0 {
+        return Err(ThriftErr::Invalid(format!("length {len}")).into());
+    }
+    take(buf, len as usize)
+}
+
+// skip over a value of type `ty`
+fn skip(buf: &mut &[u8], ty: u8, depth: usize) -> Result<()> {
+    if depth > MAX_DEPTH {
+        return Err(ThriftErr::Invalid("too deeply nested".to_string()).into());
+    }
+    match ty {
+        T_BOOL | T_BYTE => {
+            take(buf, 1)?;
+        }
+        T_I16 => {
+            take(buf, 2)?;
+        }
+        T_I32 => {
+            take(buf, 4)?;
+        }
+        T_DOUBLE | T_I64 => {
+            take(buf, 8)?;
+        }
+        T_STRING => {
+            read_binary(buf)?;
+        }
+        T_STRUCT => loop {
+            let field_ty = take(buf, 1)?[0];
+            if field_ty == T_STOP {
+                break;
+            }
+            take(buf, 2)?;
+            skip(buf, field_ty, depth + 1)?;
+        },
+        T_MAP => {
+            let head = take(buf, 6)?;
+            let (key_ty, value_ty) = (head[0], head[1]);
+            for _ in 0..(&head[2..]).get_i32() {
+                skip(buf, key_ty, depth + 1)?;
+                skip(buf, value_ty, depth + 1)?;
+            }
+        }
+        T_SET | T_LIST => {
+            let head = take(buf, 5)?;
+            let elem_ty = head[0];
+            for _ in 0..(&head[1..]).get_i32() {
+                skip(buf, elem_ty, depth + 1)?;
+            }
+        }
+        ty => return Err(ThriftErr::Invalid(format!("type {ty}")).into()),
+    }
+    Ok(())
+}
+
+// TApplicationException { 1: string message, 2: i32 type }
+fn encode_exception(message: &str, ty: i32) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(16 + message.len());
+    buf.put_u8(T_STRING);
+    buf.put_i16(1);
+    buf.put_i32(message.len() as i32);
+    buf.put_slice(message.as_bytes());
+    buf.put_u8(T_I32);
+    buf.put_i16(2);
+    buf.put_i32(ty);
+    buf.put_u8(T_STOP);
+    buf
+}
+
+fn decode_exception(mut buf: &[u8]) -> Result<(String, i32)> {
+    let (mut message, mut ty) = (String::new(), exception::UNKNOWN);
+    loop {
+        let field_ty = take(&mut buf, 1)?[0];
+        if field_ty == T_STOP {
+            break;
+        }
+        match (take(&mut buf, 2)?.get_i16(), field_ty) {
+            (1, T_STRING) => message = String::from_utf8_lossy(read_binary(&mut buf)?).into_owned(),
+            (2, T_I32) => ty = take(&mut buf, 4)?.get_i32(),
+            _ => skip(&mut buf, field_ty, 0)?,
+        }
+    }
+    Ok((message, ty))
+}
+
+fn pack_frame(head: &MessageHeader, body: &[u8]) -> Vec<u8> {
+    let head = head.encode();
+    let mut buf = Vec::with_capacity(4 + head.len() + body.len());
+    buf.put_u32((head.len() + body.len()) as u32);
+    buf.put_slice(&head);
+    buf.put_slice(body);
+    buf
+}
+
+#[async_trait]
+impl Protocol for Thrift {
+    fn default() -> Self {
+        Thrift {
+            svc_name: None,
+            seq_id: AtomicI32::new(1),
+        }
+    }
+    fn protocol_type(&self) -> ProtocolType {
+        ProtocolType::PROTOCOL_THRIFT
+    }
+    fn name(&self) -> &'static str {
+        "thrift"
+    }
+
+    #[instrument(skip_all)]
+    fn parse(&self, buf: &mut BytesMut) -> Result<CommonMsg> {
+        // only strict messages, whose version tells them apart from other protocols
+        let version = buf.get(4..).unwrap_or_default();
+        if version.iter().zip(VERSION_1).any(|(a, b)| *a != b)
+            || buf.get(7).is_some_and(|ty| !(CALL..=ONEWAY).contains(ty))
+        {
+            return Err(ParseErr::TryOther.into());
+        }
+        if buf.len() < 8 {
+            return Err(ParseErr::NotEnoughData.into());
+        }
+        let size = (&buf[..4]).get_u32() as usize;
+        if size > MAX_FRAME_SIZE {
+            return Err(ThriftErr::FrameTooLarge(size).into());
+        }
+        if buf.len() < 4 + size {
+            return Err(ParseErr::NotEnoughData.into());
+        }
+
+        let mut frame = buf.split_to(4 + size);
+        frame.advance(4);
+        let head = MessageHeader::decode(&frame)?;
+        let head_len = 12 + head.name.len();
+        debug!(
+            name = head.name,
+            seq_id = head.seq_id,
+            "finish to parse thrift"
+        );
+        let mut msg = CommonMsg::new(frame[head_len..].to_vec());
+        msg.with_meta(frame[..head_len].to_vec());
+
+        Ok(msg)
+    }
+
+    #[instrument(skip_all)]
+    async fn process_request(
+        &self,
+        conn: &Connection,
+        services: &Services,
+        msg: CommonMsg,
+    ) -> Result<Option<CommonMsg>> {
+        let head = MessageHeader::decode(&msg.meta)?;
+        let res = match head.ty {
+            CALL | ONEWAY => {
+                let mut ctx = Context::default();
+                ctx.set_peer_certificate(conn.peer_certificate());
+                match self.resolve(services, &head.name) {
+                    Ok((svc_name, method_name))
+                        if services.method(svc_name, method_name).is_some() =>
+                    {
+                        let svc = services.get(svc_name)?;
+                        svc.call_method(&mut ctx, method_name, &msg.payload)
+                            .await
+                            .map_err(|e| (e.to_string(), exception::INTERNAL_ERROR))
+                    }
+                    Ok(_) => Err((
+                        format!("unknown method {}", head.name),
+                        exception::UNKNOWN_METHOD,
+                    )),
+                    Err(e) => Err((e.to_string(), exception::UNKNOWN_METHOD)),
+                }
+            }
+            ty => Err((
+                format!("unexpected message type {ty}"),
+                exception::INVALID_MESSAGE_TYPE,
+            )),
+        };
+        if head.ty == ONEWAY {
+            if let Err((message, _)) = res {
+                debug!("oneway {} failed: {message}", head.name);
+            }
+            return Ok(None);
+        }
+
+        let (ty, body) = match res {
+            Ok(body) => (REPLY, body),
+            Err((message, ty)) => (EXCEPTION, encode_exception(&message, ty)),
+        };
+        let head = MessageHeader {
+            name: head.name,
+            ty,
+            seq_id: head.seq_id,
+        };
+        let mut msg = CommonMsg::new(body);
+        msg.with_meta(head.encode());
+        Ok(Some(msg))
+    }
+
+    #[instrument(skip_all)]
+    fn pack_response(&self, msg: CommonMsg) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(4 + msg.body_size() as usize);
+        buf.put_u32(msg.body_size());
+        buf.put_slice(&msg.meta);
+        buf.put_slice(&msg.payload);
+        buf
+    }
+
+    // the meta of `msg` is the method name
+    #[instrument(skip_all)]
+    fn pack_request(&self, cntl: &Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        let head = MessageHeader {
+            name: String::from_utf8(msg.meta)
+                .map_err(|_| ThriftErr::Invalid("method name".to_string()))?,
+            ty: CALL,
+            seq_id: cntl.thrift_seq_id(),
+        };
+        Ok(pack_frame(&head, &msg.payload))
+    }
+
+    #[instrument(skip_all)]
+    async fn process_response(&self, cntl: &mut Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        let head = MessageHeader::decode(&msg.meta)?;
+        if head.seq_id != cntl.thrift_seq_id() {
+            return Err(ThriftErr::UnexpectedSeqId(cntl.thrift_seq_id(), head.seq_id).into());
+        }
+        match head.ty {
+            REPLY => Ok(msg.payload),
+            EXCEPTION => {
+                let (message, ty) = decode_exception(&msg.payload)?;
+                Err(RpcErr::Failed(ty, message).into())
+            }
+            ty => Err(Error::from(ThriftErr::Invalid(format!(
+                "message type {ty}"
+            )))),
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use tracing::{debug, instrument};
+
+use server_kit_protocol::options::ProtocolType;
+
+use super::nshead::{self, NSHEAD_SIZE};
+use super::nshead_mcpack::is_mcpack;
+use super::Protocol;
+use crate::error::{McpackErr, ParseErr, RpcErr, ENOMETHOD};
+use crate::mcpack::{pb, Format, Value};
+use crate::message::CommonMsg;
+use crate::socket::Connection;
+use crate::{Context, Controller, Result, Services};
+
+fn object<const N: usize>(fields: [(&str, Value); N]) -> Value {
+    Value::Object(fields.map(|(n, v)| (n.to_string(), v)).into())
+}
+
+// the first item of the content, which is the only one served as brpc does
+fn content(root: &Value) -> Result<&Value> {
+    match root.get("content") {
+        Some(Value::Array(items)) if !items.is_empty() => Ok(&items[0]),
+        _ => Err(McpackErr::Invalid("no content".to_string()).into()),
+    }
+}
+
+fn parse(buf: &mut BytesMut) -> Result<CommonMsg> {
+    let head = nshead::peek_frame(buf)?;
+    let body_size = head.body_size as usize;
+    let body = &buf[NSHEAD_SIZE..NSHEAD_SIZE + body_size];
+    if !is_mcpack(body) || Value::decode(body).map_or(true, |root| content(&root).is_err()) {
+        return Err(ParseErr::TryOther.into());
+    }
+    debug!(%head, "finish to parse ubrpc");
+
+    let meta = buf.split_to(NSHEAD_SIZE);
+    let body = buf.split_to(body_size);
+    let mut msg = CommonMsg::new(body.to_vec());
+    msg.with_meta(meta.to_vec());
+
+    Ok(msg)
+}
+
+// the result params of calling the method of `content`
+async fn call(ctx: &mut Context, services: &Services, content: &Value) -> Result<Value> {
+    let svc_name = content.get("service_name").and_then(Value::as_str);
+    let svc_name = services.full_name(svc_name.unwrap_or_default())?;
+    let method_name = content.get("method").and_then(Value::as_str);
+    let method_name = method_name.unwrap_or_default();
+    let method = services.method(svc_name, method_name).ok_or_else(|| {
+        let text = format!("no method {method_name} of {svc_name}");
+        RpcErr::Failed(ENOMETHOD, text)
+    })?;
+    let (req_ty, resp_ty) = pb::message_types(method, &format!("{svc_name}.{method_name}"))?;
+
+    let params = content.get("params").cloned();
+    let params = params.unwrap_or(Value::Object(vec![]));
+    let req = pb::value_to_message(req_ty, params)?.write_to_bytes_dyn()?;
+    let svc = services.get(svc_name)?;
+    let resp = svc.call_method(ctx, method_name, &req).await?;
+
+    Ok(pb::message_to_value(&*resp_ty.parse_from_bytes(&resp)?))
+}
+
+async fn process_request(
+    conn: &Connection,
+    services: &Services,
+    msg: CommonMsg,
+    format: Format,
+) -> Result<Option<CommonMsg>> {
+    let root = Value::decode(&msg.payload)?;
+    let content = content(&root)?;
+    let id = Value::Int64(
+        content
+            .get("id")
+            .and_then(Value::as_i64)
+            .unwrap_or_default(),
+    );
+    let mut ctx = Context::default();
+    ctx.set_peer_certificate(conn.peer_certificate());
+    ctx.set_nshead_header(nshead::header_of(&msg));
+    let item = match call(&mut ctx, services, content).await {
+        Ok(result) => object([("id", id), ("result_params", result)]),
+        Err(e) => {
+            let error = object([
+                ("code", Value::Int32(super::error_code(&e))),
+                ("message", Value::String(e.to_string())),
+            ]);
+            object([("id", id), ("error", error)])
+        }
+    };
+    let resp = object([("content", Value::Array(vec![item]))]);
+
+    Ok(Some(nshead::response(&ctx, resp.encode(format))))
+}
+
+// the meta of `msg` is the method name as in "ServiceName.method", the
+// payload its params as a mcpack object
+fn pack_request(cntl: &Controller, msg: CommonMsg, format: Format) -> Result<Vec<u8>> {
+    static NEXT_ID: AtomicI64 = AtomicI64::new(1);
+
+    let name = std::str::from_utf8(&msg.meta).unwrap_or_default();
+    let (svc_name, method_name) = name
+        .rsplit_once('.')
+        .ok_or_else(|| McpackErr::Invalid(format!("method name {name:?}")))?;
+    let params = match msg.payload.is_empty() {
+        true => Value::Object(vec![]),
+        false => Value::decode(&msg.payload)?,
+    };
+    let item = object([
+        ("service_name", Value::String(svc_name.to_string())),
+        ("id", Value::Int64(NEXT_ID.fetch_add(1, Ordering::Relaxed))),
+        ("method", Value::String(method_name.to_string())),
+        ("params", params),
+    ]);
+    let req = object([
+        ("header", Value::Object(vec![])),
+        ("content", Value::Array(vec![item])),
+    ]);
+
+    Ok(nshead::pack_frame(
+        nshead::request_header(cntl),
+        &req.encode(format),
+    ))
+}
+
+fn process_response(cntl: &mut Controller, msg: CommonMsg, format: Format) -> Result<Vec<u8>> {
+    cntl.set_nshead_response_header(nshead::header_of(&msg));
+    let root = Value::decode(&msg.payload)?;
+    let content = content(&root)?;
+    if let Some(error) = content.get("error") {
+        let code = error
+            .get("code")
+            .and_then(Value::as_i64)
+            .unwrap_or_default();
+        let message = error.get("message").and_then(Value::as_str);
+        let message = message.unwrap_or_default().to_string();
+        return Err(RpcErr::Failed(code as i32, message).into());
+    }
+    let result = content.get("result_params").cloned();
+
+    Ok(result.unwrap_or(Value::Object(vec![])).encode(format))
+}
+
+/// ubrpc over nshead with compack bodies, see `UbrpcMcpack2`.
+pub struct UbrpcCompack;
+
+/// ubrpc over nshead with mcpack2 bodies, which are objects of requests as
+/// `{content: [{service_name, id, method, params}]}` and responses as
+/// `{content: [{id, result_params}]}` or `{content: [{id, error: {code, message}}]}`.
+///
+/// Servers convert params and result_params from and to the pb request and
+/// response of the method. Channels send the meta of a message, the method
+/// name as in "ServiceName.method", with its payload as params, and receive
+/// result_params as they are.
+///
+/// Sniffing can't tell it from nshead, so servers serve it only if enabled by
+/// name or `add_protocol`, and without nshead which is tried first.
+pub struct UbrpcMcpack2;
+
+#[async_trait]
+impl Protocol for UbrpcCompack {
+    fn default() -> Self {
+        UbrpcCompack
+    }
+    fn protocol_type(&self) -> ProtocolType {
+        ProtocolType::PROTOCOL_UBRPC_COMPACK
+    }
+    fn name(&self) -> &'static str {
+        "ubrpc_compack"
+    }
+
+    #[instrument(skip_all)]
+    fn parse(&self, buf: &mut BytesMut) -> Result<CommonMsg> {
+        parse(buf)
+    }
+
+    fn is_enabled_by_default(&self) -> bool {
+        false
+    }
+
+    #[instrument(skip_all)]
+    async fn process_request(
+        &self,
+        conn: &Connection,
+        services: &Services,
+        msg: CommonMsg,
+    ) -> Result<Option<CommonMsg>> {
+        process_request(conn, services, msg, Format::Compack).await
+    }
+
+    #[instrument(skip_all)]
+    fn pack_response(&self, msg: CommonMsg) -> Vec<u8> {
+        nshead::pack_frame(nshead::header_of(&msg), &msg.payload)
+    }
+
+    #[instrument(skip_all)]
+    fn pack_request(&self, cntl: &Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        pack_request(cntl, msg, Format::Compack)
+    }
+
+    #[instrument(skip_all)]
+    async fn process_response(&self, cntl: &mut Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        process_response(cntl, msg, Format::Compack)
+    }
+}
+
+#[async_trait]
+impl Protocol for UbrpcMcpack2 {
+    fn default() -> Self {
+        UbrpcMcpack2
+    }
+    fn protocol_type(&self) -> ProtocolType {
+        ProtocolType::PROTOCOL_UBRPC_MCPACK2
+    }
+    fn name(&self) -> &'static str {
+        "ubrpc_mcpack2"
+    }
+
+    #[instrument(skip_all)]
+    fn parse(&self, buf: &mut BytesMut) -> Result<CommonMsg> {
+        parse(buf)
+    }
+
+    fn is_enabled_by_default(&self) -> bool {
+        false
+    }
+
+    #[instrument(skip_all)]
+    async fn process_request(
+        &self,
+        conn: &Connection,
+        services: &Services,
+        msg: CommonMsg,
+    ) -> Result<Option<CommonMsg>> {
+        process_request(conn, services, msg, Format::Mcpack2).await
+    }
+
+    #[instrument(skip_all)]
+    fn pack_response(&self, msg: CommonMsg) -> Vec<u8> {
+        nshead::pack_frame(nshead::header_of(&msg), &msg.payload)
+    }
+
+    #[instrument(skip_all)]
+    fn pack_request(&self, cntl: &Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        pack_request(cntl, msg, Format::Mcpack2)
+    }
+
+    #[instrument(skip_all)]
+    async fn process_response(&self, cntl: &mut Controller, msg: CommonMsg) -> Result<Vec<u8>> {
+        process_response(cntl, msg, Format::Mcpack2)
+    }
+}
@@ -0,0 +1,147 @@
+use crate::error::ConfErr;
+use crate::Result;
+
+/// One `PATH => METHOD` entry of the restful mappings of a service, where
+/// PATH may have a `*` matching any part of the url path.
+#[derive(Debug)]
+pub(crate) struct RestfulMapping {
+    pub svc_name: String,
+    pub method_name: String,
+    prefix: String,
+    // after the wildcard, none if there is no wildcard
+    suffix: Option<String>,
+}
+
+impl RestfulMapping {
+    /// Parse mappings separated by commas, as in "/v1/users/* => GetUser, /v1/users => ListUsers".
+    pub fn parse_all(svc_name: &str, mappings: &str) -> Result<Vec<Self>> {
+        mappings
+            .split(',')
+            .filter(|mapping| !mapping.trim().is_empty())
+            .map(|mapping| Self::parse(svc_name, mapping))
+            .collect()
+    }
+
+    fn parse(svc_name: &str, mapping: &str) -> Result<Self> {
+        let invalid = || ConfErr::Invalid("restful_mappings", mapping.trim().to_string());
+        let (path, method_name) = mapping.split_once("=>").ok_or_else(invalid)?;
+        let (path, method_name) = (path.trim(), method_name.trim());
+        if !path.starts_with('/') || method_name.is_empty() || path.matches('*').count() > 1 {
+            return Err(invalid().into());
+        }
+        let (prefix, suffix) = match path.split_once('*') {
+            Some((prefix, suffix)) => (prefix, Some(suffix.to_string())),
+            None => (path, None),
+        };
+
+        Ok(Self {
+            svc_name: svc_name.to_string(),
+            method_name: method_name.to_string(),
+            prefix: prefix.to_string(),
+            suffix,
+        })
+    }
+
+    fn path(&self) -> String {
+        match &self.suffix {
+            Some(suffix) => format!("{}*{suffix}", self.prefix),
+            None => self.prefix.clone(),
+        }
+    }
+
+    pub fn same_path(&self, other: &Self) -> bool {
+        self.prefix == other.prefix && self.suffix == other.suffix
+    }
+
+    /// The part of `path` matched by the wildcard, empty if there is no wildcard.
+    fn matches<'a>(&self, path: &'a str) -> Option<&'a str> {
+        let suffix = match &self.suffix {
+            Some(suffix) => suffix,
+            None => return (path == self.prefix).then_some(""),
+        };
+        if path.len() < self.prefix.len() + suffix.len() {
+            return None;
+        }
+        path.strip_prefix(self.prefix.as_str())?
+            .strip_suffix(suffix.as_str())
+    }
+}
+
+/// The mapping `path` goes to and the part matched by its wildcard. Exact
+/// paths go before wildcards, which go by the longest literal parts.
+pub(crate) fn resolve<'a, 'p>(
+    mappings: &'a [RestfulMapping],
+    path: &'p str,
+) -> Option<(&'a RestfulMapping, &'p str)> {
+    mappings
+        .iter()
+        .filter_map(|mapping| {
+            mapping
+                .matches(path)
+                .map(|unresolved| (mapping, unresolved))
+        })
+        .max_by_key(|(mapping, _)| {
+            let literal = mapping.prefix.len() + mapping.suffix.as_ref().map_or(0, String::len);
+            (mapping.suffix.is_none(), literal)
+        })
+}
+
+impl std::fmt::Display for RestfulMapping {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} => {}.{}",
+            self.path(),
+            self.svc_name,
+            self.method_name
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolved<'p>(mappings: &[RestfulMapping], path: &'p str) -> Option<(String, &'p str)> {
+        resolve(mappings, path)
+            .map(|(mapping, unresolved)| (mapping.method_name.clone(), unresolved))
+    }
+
+    #[test]
+    fn resolve_paths() {
+        let mappings = RestfulMapping::parse_all(
+            "test.echo",
+            "/v1/users => List, /v1/users/* => Get, /v1/users/*/posts => Posts, /v1/* => Any,",
+        )
+        .unwrap();
+        assert_eq!(mappings.len(), 4);
+
+        let cases = [
+            ("/v1/users", Some(("List", ""))),
+            ("/v1/users/alice", Some(("Get", "alice"))),
+            ("/v1/users/alice/posts", Some(("Posts", "alice"))),
+            ("/v1/users/", Some(("Get", ""))),
+            ("/v1/groups/a", Some(("Any", "groups/a"))),
+            ("/v2/users", None),
+            ("/v1", None),
+        ];
+        for (path, expected) in cases {
+            let expected = expected.map(|(method, unresolved)| (method.to_string(), unresolved));
+            assert_eq!(resolved(&mappings, path), expected, "{path}");
+        }
+    }
+
+    #[test]
+    fn invalid_mappings() {
+        for mappings in ["v1 => Get", "/v1 =>", "/v1 Get", "/*/* => Get"] {
+            assert!(
+                RestfulMapping::parse_all("test.echo", mappings).is_err(),
+                "{mappings}"
+            );
+        }
+
+        let mappings = RestfulMapping::parse_all("test.echo", "/a/* => A, /a/*  => B").unwrap();
+        assert!(mappings[0].same_path(&mappings[1]));
+        assert_eq!(mappings[1].to_string(), "/a/* => test.echo.B");
+    }
+}
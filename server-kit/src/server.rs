@@ -8,23 +8,30 @@ use tracing::instrument;
 use tracing::Instrument;
 use tracing::{debug, error, trace_span, warn};
 
+use crate::auth::Authenticator;
 use crate::conf::{self, Conf};
+use crate::protocol::Protocol;
 use crate::service::ServiceManger;
 use crate::socket::Socket;
+use crate::tls::ServerTls;
 use crate::Result;
 use crate::Service;
 
 pub struct Server {
     conf: Conf,
     svc_manager: Arc<ServiceManger>,
+    tls: Option<ServerTls>,
 }
 
 impl Server {
     pub async fn new(conf: impl AsRef<Path>) -> Result<Self> {
         let conf: Conf = conf::read_conf(conf).await?;
+        let svc_manager = ServiceManger::new(&conf.protocols)?;
+        let tls = conf.tls.as_ref().map(ServerTls::new).transpose()?;
         Ok(Self {
             conf,
-            svc_manager: Default::default(),
+            svc_manager: Arc::new(svc_manager),
+            tls,
         })
     }
 
@@ -37,6 +44,39 @@ impl Server {
             .unwrap()
     }
 
+    /// Add `svc` whose methods are also served over http by the paths of
+    /// `mappings`, as in "/v1/users/* => GetUser, /v1/users => ListUsers".
+    pub fn add_service_with_restful_mappings<S>(&mut self, svc: S, mappings: &str) -> Result<()>
+    where
+        S: Service,
+    {
+        Arc::get_mut(&mut self.svc_manager)
+            .map(|m| m.add_service_with_restful_mappings(svc, mappings))
+            .unwrap()
+    }
+
+    pub fn add_protocol<P>(&mut self, protocol: P) -> Result<()>
+    where
+        P: Protocol,
+    {
+        Arc::get_mut(&mut self.svc_manager)
+            .map(|m| m.add_protocol(protocol))
+            .unwrap()
+    }
+
+    /// Verify the credential of requests, on the first request of each
+    /// connection unless the authenticator asks for every one. Requests of
+    /// protocols carrying no credential are refused from then on, see
+    /// `Protocol::supports_authentication`.
+    pub fn set_authenticator<A>(&mut self, authenticator: A)
+    where
+        A: Authenticator,
+    {
+        Arc::get_mut(&mut self.svc_manager)
+            .unwrap()
+            .set_authenticator(authenticator)
+    }
+
     #[instrument(skip_all)]
     pub async fn start(&mut self) -> Result<()> {
         let addr = format!("{}:{}", &self.conf.ip, self.conf.port);
@@ -62,17 +102,16 @@ impl Server {
     #[instrument(level = "trace", skip_all)]
     async fn process(&self, addr: SocketAddr, stream: TcpStream) -> Result<()> {
         let svc_manager = Arc::clone(&self.svc_manager);
-        if let Err(e) = tokio::spawn(
+        let tls = self.tls.clone();
+        tokio::spawn(
             async move {
-                let mut socket = Socket::new(addr, stream);
-                socket.process(svc_manager).await
+                let socket = Socket::new(addr, stream);
+                if let Err(e) = socket.process(svc_manager, tls).await {
+                    warn!("process err:{}", e)
+                }
             }
             .instrument(trace_span!("worker")),
-        )
-        .await
-        {
-            warn!("process err:{}", e)
-        }
+        );
 
         Ok(())
     }
@@ -1,25 +1,69 @@
-use std::any::Any;
-use std::any::TypeId;
 use std::collections::HashMap;
-use tokio::io::AsyncWriteExt;
-use tokio::net::TcpStream;
+use std::sync::Arc;
 
 use async_trait::async_trait;
-use tracing::debug;
+use bytes::BytesMut;
+use protobuf::reflect::MessageDescriptor;
+use protobuf::MessageFull;
 use tracing::instrument;
 
-use crate::error::ParseErr;
-use crate::protocol::Protocol;
+use server_kit_protocol::options::{CompressType, ProtocolType, TalkType};
+
+use crate::auth::Authenticator;
+use crate::error::{AuthErr, ConfErr, ParseErr, ProtocolErr, SvcErr};
+use crate::message::CommonMsg;
+use crate::protocol::{self, Frames, Protocol};
+use crate::restful::{self, RestfulMapping};
+use crate::socket::{Connection, ConnectionIo};
+use crate::Context;
 use crate::Error;
 use crate::Result;
 
 pub struct ServiceDescriptor {
-    pub protocol: Box<dyn Protocol>,
     // The name of the service, not including its containing scope.
     // fn name(&self) -> &'static str;
     // The fully-qualified name of the service, scope delimited by periods.
     // fn full_name(&self) -> &'static str;
     pub full_name: &'static str,
+    pub methods: Vec<MethodDescriptor>,
+}
+
+impl ServiceDescriptor {
+    pub fn method(&self, name: &str) -> Option<&MethodDescriptor> {
+        self.methods.iter().find(|m| m.name == name)
+    }
+}
+
+/// Per-method options, the counterparts of the method options in `options.proto`.
+#[derive(Default, Debug, Clone)]
+pub struct MethodDescriptor {
+    pub name: &'static str,
+    pub request_compression: CompressType,
+    pub response_compression: CompressType,
+    /// `TALK_TYPE_ONEWAY` to run the method without sending any response.
+    pub request_talk_type: TalkType,
+    /// Message types of the request and response, to transcode them from and to json.
+    pub request_type: Option<MessageDescriptor>,
+    pub response_type: Option<MessageDescriptor>,
+}
+
+impl MethodDescriptor {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_message_types<Req, Resp>(mut self) -> Self
+    where
+        Req: MessageFull,
+        Resp: MessageFull,
+    {
+        self.request_type = Some(Req::descriptor());
+        self.response_type = Some(Resp::descriptor());
+        self
+    }
 }
 
 #[async_trait]
@@ -27,48 +71,285 @@ pub trait Service: Sync + Send + 'static {
     fn descriptor(&self) -> ServiceDescriptor
     where
         Self: Sized;
-    async fn call_method(&self, method_name: &str, req: &[u8]) -> Result<Vec<u8>>;
+    async fn call_method(
+        &self,
+        ctx: &mut Context,
+        method_name: &str,
+        req: &[u8],
+    ) -> Result<Vec<u8>>;
 }
 
+/// Services of a server keyed by full name, shared by all of its protocols.
 #[derive(Default)]
+pub struct Services {
+    services: HashMap<String, (ServiceDescriptor, Box<dyn Service>)>,
+    restful: Vec<RestfulMapping>,
+}
+
+impl Services {
+    pub fn add_service<S>(&mut self, svc: S) -> Result<()>
+    where
+        S: Service,
+    {
+        self.add_service_with_restful_mappings(svc, "")
+    }
+
+    /// Also serve methods of `svc` over http by the paths of `mappings`, as in
+    /// "/v1/users/* => GetUser, /v1/users => ListUsers".
+    pub fn add_service_with_restful_mappings<S>(&mut self, svc: S, mappings: &str) -> Result<()>
+    where
+        S: Service,
+    {
+        let svc_desc = svc.descriptor();
+        let svc_name = svc_desc.full_name;
+        if self.services.contains_key(svc_name) {
+            return Err(SvcErr::Exist(svc_name.to_string()).into());
+        }
+        let mappings = RestfulMapping::parse_all(svc_name, mappings)?;
+        for (idx, mapping) in mappings.iter().enumerate() {
+            if svc_desc.method(&mapping.method_name).is_none() {
+                return Err(ConfErr::Invalid("restful_mappings", mapping.to_string()).into());
+            }
+            let mut others = self.restful.iter().chain(&mappings[..idx]);
+            if others.any(|other| other.same_path(mapping)) {
+                return Err(ConfErr::Invalid("restful_mappings", mapping.to_string()).into());
+            }
+        }
+        self.services
+            .insert(svc_name.to_string(), (svc_desc, Box::new(svc)));
+        self.restful.extend(mappings);
+        Ok(())
+    }
+
+    pub fn get(&self, svc_name: &str) -> Result<&dyn Service> {
+        self.services
+            .get(svc_name)
+            .map(|(_, svc)| svc.as_ref())
+            .ok_or_else(|| SvcErr::NotExist(svc_name.to_string()).into())
+    }
+
+    pub fn descriptor(&self, svc_name: &str) -> Option<&ServiceDescriptor> {
+        self.services.get(svc_name).map(|(svc_desc, _)| svc_desc)
+    }
+
+    pub fn method(&self, svc_name: &str, method_name: &str) -> Option<&MethodDescriptor> {
+        self.descriptor(svc_name)
+            .and_then(|svc_desc| svc_desc.method(method_name))
+    }
+
+    /// Full name of the service named `name` in full or without its package.
+    pub fn full_name(&self, name: &str) -> Result<&str> {
+        if let Some((full_name, _)) = self.services.get_key_value(name) {
+            return Ok(full_name);
+        }
+        self.services
+            .keys()
+            .find(|full_name| full_name.rsplit('.').next() == Some(name))
+            .map(String::as_str)
+            .ok_or_else(|| SvcErr::NotExist(name.to_string()).into())
+    }
+
+    pub fn len(&self) -> usize {
+        self.services.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.services.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &dyn Service)> {
+        self.services
+            .iter()
+            .map(|(name, (_, svc))| (name.as_str(), svc.as_ref()))
+    }
+
+    /// Whether `svc_name` is served over http by restful mappings only.
+    pub fn has_restful_mappings(&self, svc_name: &str) -> bool {
+        self.restful
+            .iter()
+            .any(|mapping| mapping.svc_name == svc_name)
+    }
+
+    /// Service and method names the restful mappings map `path` to, and the
+    /// part of it matched by the wildcard.
+    pub fn resolve_restful<'p>(&self, path: &'p str) -> Option<(&str, &str, &'p str)> {
+        restful::resolve(&self.restful, path).map(|(mapping, unresolved)| {
+            (
+                mapping.svc_name.as_str(),
+                mapping.method_name.as_str(),
+                unresolved,
+            )
+        })
+    }
+}
+
 pub struct ServiceManger {
-    services: HashMap<TypeId, Box<dyn Protocol>>,
+    services: Services,
+    authenticator: Option<Arc<dyn Authenticator>>,
+    // enabled protocols in the order of the protocol registry
+    protocols: Vec<Box<dyn Protocol>>,
 }
 
 impl ServiceManger {
+    /// Enable the registered protocols of `names`, or the ones enabled by default if empty.
+    pub fn new(names: &[String]) -> Result<Self> {
+        let protocols = match names.is_empty() {
+            true => protocol::protocol_names()
+                .into_iter()
+                .filter_map(protocol::new_protocol_by_name)
+                .filter(|p| p.is_enabled_by_default())
+                .collect(),
+            false => {
+                let order = protocol::protocol_names();
+                let mut ranks = names
+                    .iter()
+                    .map(|name| {
+                        order
+                            .iter()
+                            .position(|n| n == name)
+                            .ok_or_else(|| ProtocolErr::NotExist(name.to_string()))
+                    })
+                    .collect::<std::result::Result<Vec<_>, _>>()?;
+                ranks.sort();
+                ranks.dedup();
+                ranks
+                    .into_iter()
+                    .filter_map(|rank| protocol::new_protocol_by_name(order[rank]))
+                    .collect()
+            }
+        };
+
+        Ok(Self {
+            services: Default::default(),
+            authenticator: None,
+            protocols,
+        })
+    }
+
     pub fn add_service<S>(&mut self, svc: S) -> Result<()>
     where
         S: Service,
     {
-        let svc_desc = svc.descriptor();
-        let type_id = svc_desc.protocol.type_id();
-        let protocol = self
-            .services
-            .entry(type_id)
-            .or_insert_with(|| svc_desc.protocol);
+        self.services.add_service(svc)
+    }
 
-        let svc_name = svc_desc.full_name;
-        protocol.add_service(svc_name.to_string(), Box::new(svc))
+    pub fn add_service_with_restful_mappings<S>(&mut self, svc: S, mappings: &str) -> Result<()>
+    where
+        S: Service,
+    {
+        self.services
+            .add_service_with_restful_mappings(svc, mappings)
     }
 
-    #[instrument(skip_all)]
-    pub async fn process(&self, stream: &mut TcpStream) -> Result<()> {
-        for protocol in self.services.values() {
-            let msg = match protocol.parse(stream).await {
-                Ok(msg) => msg,
-                Err(Error::Parse(ParseErr::TryOther)) => continue,
-                Err(err) => return Err(err),
-            };
+    pub fn set_authenticator<A>(&mut self, authenticator: A)
+    where
+        A: Authenticator,
+    {
+        self.authenticator = Some(Arc::new(authenticator));
+    }
 
-            // parse request
-            let msg = protocol.process_request(msg).await?;
-            let msg = protocol.pack_response(msg);
+    pub fn authenticator(&self) -> Option<Arc<dyn Authenticator>> {
+        self.authenticator.clone()
+    }
+
+    /// Replace the enabled protocol of the same name by `protocol`, which is
+    /// how a protocol needing options gets them.
+    pub fn add_protocol<P>(&mut self, protocol: P) -> Result<()>
+    where
+        P: Protocol,
+    {
+        let name = protocol.name();
+        let order = protocol::protocol_names();
+        let rank = |name: &str| order.iter().position(|n| *n == name);
+        let rank_of_protocol = rank(name).ok_or_else(|| ProtocolErr::NotExist(name.to_string()))?;
 
-            // write response
-            debug!("write message: {msg:?}");
-            stream.write_all(&msg).await?;
+        match self.protocols.iter().position(|p| p.name() == name) {
+            Some(idx) => self.protocols[idx] = Box::new(protocol),
+            None => {
+                let idx = self
+                    .protocols
+                    .partition_point(|p| rank(p.name()) < Some(rank_of_protocol));
+                self.protocols.insert(idx, Box::new(protocol));
+            }
         }
 
         Ok(())
     }
+
+    /// Cut the next message off `buf`, trying the `preferred` protocol of the
+    /// connection before sniffing the others.
+    pub fn parse(
+        &self,
+        buf: &mut BytesMut,
+        preferred: Option<usize>,
+    ) -> Result<(usize, CommonMsg)> {
+        if let Some(idx) = preferred {
+            match self.protocols[idx].parse(buf) {
+                Err(Error::Parse(ParseErr::TryOther)) => {}
+                res => return res.map(|msg| (idx, msg)),
+            }
+        }
+
+        let mut not_enough_data = false;
+        for (idx, protocol) in self.protocols.iter().enumerate() {
+            if Some(idx) == preferred {
+                continue;
+            }
+            match protocol.parse(buf) {
+                Ok(msg) => return Ok((idx, msg)),
+                Err(Error::Parse(ParseErr::TryOther)) => continue,
+                Err(Error::Parse(ParseErr::NotEnoughData)) => not_enough_data = true,
+                Err(err) => return Err(err),
+            }
+        }
+
+        match not_enough_data {
+            true => Err(ParseErr::NotEnoughData.into()),
+            false => Err(ParseErr::TryOther.into()),
+        }
+    }
+
+    pub fn is_multiplexed(&self, idx: usize) -> bool {
+        self.protocols[idx].is_multiplexed()
+    }
+
+    /// Whether messages of protocol `idx` are frames of streams, which are never answered.
+    pub fn is_streaming(&self, idx: usize) -> bool {
+        self.protocols[idx].protocol_type() == ProtocolType::PROTOCOL_STREAMING_RPC
+    }
+
+    #[instrument(skip_all)]
+    pub async fn serve_connection(
+        &self,
+        conn: &Connection,
+        idx: usize,
+        io: ConnectionIo<'_>,
+    ) -> Result<()> {
+        self.check_authentication(idx)?;
+        self.protocols[idx]
+            .serve_connection(conn, &self.services, io)
+            .await
+    }
+
+    #[instrument(skip_all)]
+    pub async fn process(
+        &self,
+        conn: &Connection,
+        idx: usize,
+        msg: CommonMsg,
+    ) -> Result<Option<Frames>> {
+        self.check_authentication(idx)?;
+        let protocol = &self.protocols[idx];
+        let msg = protocol.process_request(conn, &self.services, msg).await?;
+        Ok(msg.map(|msg| protocol.pack_response_frames(msg)))
+    }
+
+    // with an authenticator, requests are only served by protocols which verify them
+    fn check_authentication(&self, idx: usize) -> Result<()> {
+        let protocol = &self.protocols[idx];
+        match self.authenticator.is_some() && !protocol.supports_authentication() {
+            true => Err(AuthErr::Unsupported(protocol.name()).into()),
+            false => Ok(()),
+        }
+    }
 }
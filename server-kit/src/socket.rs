@@ -1,11 +1,217 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::io;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::task::{Context as TaskContext, Poll};
 
+use bytes::BytesMut;
+use futures_util::future::OptionFuture;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
 use tokio::net::TcpStream;
-use tracing::instrument;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, instrument, warn};
 
+use crate::auth::Authenticator;
+use crate::error::ParseErr;
+use crate::global::BUF_SIZE;
+use crate::message::{Chunks, CommonMsg};
+use crate::protocol::Frames;
 use crate::service::ServiceManger;
-use crate::Result;
+use crate::tls::{self, PeerCertificate, ServerTls};
+use crate::{stream, Error, Result};
+
+// requests read ahead of the one being processed, beyond which reading waits
+const MAX_QUEUED: usize = 64;
+
+/// The writing side of a connection, shared by the responses and the streams on it.
+#[derive(Clone)]
+pub struct Connection {
+    id: u64,
+    remote_addr: SocketAddr,
+    // bytes to write, and who to tell once they are
+    writer: mpsc::UnboundedSender<(Vec<u8>, Option<oneshot::Sender<()>>)>,
+    authenticator: Option<Arc<dyn Authenticator>>,
+    // verified by the first request
+    identity: Arc<OnceLock<String>>,
+    peer_certificate: Option<Arc<PeerCertificate>>,
+}
+
+impl fmt::Debug for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Connection")
+            .field("id", &self.id)
+            .field("remote_addr", &self.remote_addr)
+            .field("identity", &self.identity.get())
+            .field("peer_certificate", &self.peer_certificate)
+            .finish()
+    }
+}
+
+impl Connection {
+    pub(crate) fn new<W>(remote_addr: SocketAddr, mut stream: W) -> Self
+    where
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+        let (writer, mut rx) = mpsc::unbounded_channel::<(Vec<u8>, Option<oneshot::Sender<()>>)>();
+        tokio::spawn(async move {
+            while let Some((buf, written)) = rx.recv().await {
+                // tls streams hold the records back until flushed
+                let res = match stream.write_all(&buf).await {
+                    Ok(()) => stream.flush().await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = res {
+                    warn!("write to {remote_addr} err:{e}");
+                    break;
+                }
+                if let Some(written) = written {
+                    let _ = written.send(());
+                }
+            }
+        });
+
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            remote_addr,
+            writer,
+            authenticator: None,
+            identity: Default::default(),
+            peer_certificate: None,
+        }
+    }
+
+    pub(crate) fn with_authenticator(
+        mut self,
+        authenticator: Option<Arc<dyn Authenticator>>,
+    ) -> Self {
+        self.authenticator = authenticator;
+        self
+    }
+
+    pub(crate) fn with_peer_certificate(mut self, cert: Option<PeerCertificate>) -> Self {
+        self.peer_certificate = cert.map(Arc::new);
+        self
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote_addr
+    }
+
+    /// The certificate the peer presented, on TLS connections requiring one.
+    pub fn peer_certificate(&self) -> Option<Arc<PeerCertificate>> {
+        self.peer_certificate.clone()
+    }
+
+    /// Identity of the peer proven by `auth_data`, none if no authenticator is set.
+    pub(crate) async fn authenticate(&self, auth_data: &[u8]) -> Result<Option<String>> {
+        let authenticator = match &self.authenticator {
+            Some(authenticator) => authenticator,
+            None => return Ok(None),
+        };
+        if !authenticator.verify_every_request() {
+            if let Some(identity) = self.identity.get() {
+                return Ok(Some(identity.clone()));
+            }
+        }
+        let identity = authenticator.verify(auth_data, self.remote_addr).await?;
+        let _ = self.identity.set(identity.clone());
+
+        Ok(Some(identity))
+    }
+
+    /// Queue `buf` to be written in order with everything else on the connection.
+    pub fn write(&self, buf: Vec<u8>) -> Result<()> {
+        self.writer
+            .send((buf, None))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe).into())
+    }
+
+    /// Queue each of `frames` once the one before is written, so that no more
+    /// than one is held at a time and what is queued meanwhile goes in between.
+    pub(crate) async fn write_frames(&self, frames: Frames) -> Result<()> {
+        let mut written: Option<oneshot::Receiver<()>> = None;
+        for frame in frames {
+            if let Some(written) = written.take() {
+                written
+                    .await
+                    .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            }
+            let (tx, rx) = oneshot::channel();
+            self.writer
+                .send((frame, Some(tx)))
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            written = Some(rx);
+        }
+        Ok(())
+    }
+}
+
+/// The rest of a connection for a protocol serving all of it, reading the
+/// bytes already buffered first and writing through its `Connection`.
+pub struct ConnectionIo<'a> {
+    buf: BytesMut,
+    reader: &'a mut (dyn AsyncRead + Unpin + Send),
+    conn: Connection,
+}
+
+impl<'a> ConnectionIo<'a> {
+    pub(crate) fn new(
+        conn: Connection,
+        reader: &'a mut (dyn AsyncRead + Unpin + Send),
+        buf: BytesMut,
+    ) -> Self {
+        Self { buf, reader, conn }
+    }
+}
+
+impl AsyncRead for ConnectionIo<'_> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.buf.is_empty() {
+            return Pin::new(&mut *self.reader).poll_read(cx, buf);
+        }
+        let n = self.buf.len().min(buf.remaining());
+        buf.put_slice(&self.buf.split_to(n));
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for ConnectionIo<'_> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let res = self
+            .conn
+            .write(buf.to_vec())
+            .map(|_| buf.len())
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe));
+        Poll::Ready(res)
+    }
+
+    // written by the writer task of the connection
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
 
 pub struct Socket {
     pub addr: SocketAddr,
@@ -18,7 +224,151 @@ impl Socket {
     }
 
     #[instrument(name = "worker", skip_all, fields(remote_addr = %self.addr))]
-    pub async fn process(&mut self, svc_manager: Arc<ServiceManger>) -> Result<()> {
-        svc_manager.as_ref().process(&mut self.stream).await
+    pub(crate) async fn process(
+        self,
+        svc_manager: Arc<ServiceManger>,
+        tls: Option<ServerTls>,
+    ) -> Result<()> {
+        self.stream.set_nodelay(true)?;
+        let tls = match tls {
+            Some(tls) if tls::is_client_hello(&self.stream).await? => Some(tls),
+            Some(tls) if !tls.allow_plaintext => {
+                let msg = "plaintext connection to a TLS port";
+                return Err(io::Error::new(io::ErrorKind::InvalidData, msg).into());
+            }
+            _ => None,
+        };
+        match tls {
+            Some(tls) => {
+                let stream = tls.acceptor.accept(self.stream).await?;
+                let cert = tls::peer_certificate(stream.get_ref().1.peer_certificates());
+                debug!(?cert, "finish tls handshake");
+                let (reader, writer) = tokio::io::split(stream);
+                Self::serve(self.addr, reader, writer, &svc_manager, cert).await
+            }
+            None => {
+                let (reader, writer) = self.stream.into_split();
+                Self::serve(self.addr, reader, writer, &svc_manager, None).await
+            }
+        }
+    }
+
+    async fn serve<R, W>(
+        addr: SocketAddr,
+        mut reader: R,
+        writer: W,
+        svc_manager: &ServiceManger,
+        cert: Option<PeerCertificate>,
+    ) -> Result<()>
+    where
+        R: AsyncRead + Unpin + Send,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let conn = Connection::new(addr, writer)
+            .with_authenticator(svc_manager.authenticator())
+            .with_peer_certificate(cert);
+        let res = Self::read_loop(&conn, &mut reader, svc_manager).await;
+        stream::close_connection(conn.id());
+        res
+    }
+
+    async fn read_loop<R>(
+        conn: &Connection,
+        reader: &mut R,
+        svc_manager: &ServiceManger,
+    ) -> Result<()>
+    where
+        R: AsyncRead + Unpin + Send,
+    {
+        let mut buf = BytesMut::with_capacity(BUF_SIZE);
+        // protocol of the last message, most likely the one of the next
+        let mut preferred = None;
+        let mut chunks = Chunks::default();
+        // requests are processed one at a time and answered in order, while
+        // frames of streams go to their streams as they come, as the request
+        // being processed may be waiting on them
+        let mut queued = VecDeque::new();
+        let mut processing = None;
+        loop {
+            loop {
+                let (idx, msg) = match svc_manager.parse(&mut buf, preferred) {
+                    Ok(res) => res,
+                    Err(Error::Parse(ParseErr::NotEnoughData)) => break,
+                    Err(e) => {
+                        Self::finish(conn, svc_manager, processing, queued).await?;
+                        return Err(e);
+                    }
+                };
+                preferred = Some(idx);
+                if svc_manager.is_multiplexed(idx) {
+                    Self::finish(conn, svc_manager, processing, queued).await?;
+                    let io = ConnectionIo::new(conn.clone(), reader, buf);
+                    return svc_manager.serve_connection(conn, idx, io).await;
+                }
+                let msg = match chunks.merge(msg) {
+                    Ok(Some(msg)) => msg,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        Self::finish(conn, svc_manager, processing, queued).await?;
+                        return Err(e);
+                    }
+                };
+                match svc_manager.is_streaming(idx) {
+                    true => {
+                        svc_manager.process(conn, idx, msg).await?;
+                    }
+                    false => queued.push_back((idx, msg)),
+                }
+            }
+            if processing.is_none() {
+                processing = queued
+                    .pop_front()
+                    .map(|(idx, msg)| Box::pin(svc_manager.process(conn, idx, msg)));
+            }
+
+            tokio::select! {
+                // write response, if there is one
+                Some(res) = OptionFuture::from(processing.as_mut()), if processing.is_some() => {
+                    processing = None;
+                    if let Some(frames) = res? {
+                        conn.write_frames(frames).await?;
+                    }
+                }
+                res = reader.read_buf(&mut buf), if queued.len() < MAX_QUEUED => {
+                    if res? == 0 {
+                        Self::finish(conn, svc_manager, processing, queued).await?;
+                        if buf.is_empty() {
+                            debug!("connection closed");
+                            return Ok(());
+                        }
+                        return Err(ParseErr::UnexpectedEof.into());
+                    }
+                    debug!("read from stream: {buf:?}");
+                }
+            }
+        }
+    }
+
+    // answer the requests read before the connection ends
+    async fn finish<F>(
+        conn: &Connection,
+        svc_manager: &ServiceManger,
+        processing: Option<Pin<Box<F>>>,
+        queued: VecDeque<(usize, CommonMsg)>,
+    ) -> Result<()>
+    where
+        F: Future<Output = Result<Option<Frames>>>,
+    {
+        if let Some(processing) = processing {
+            if let Some(frames) = processing.await? {
+                conn.write_frames(frames).await?;
+            }
+        }
+        for (idx, msg) in queued {
+            if let Some(frames) = svc_manager.process(conn, idx, msg).await? {
+                conn.write_frames(frames).await?;
+            }
+        }
+        Ok(())
     }
 }
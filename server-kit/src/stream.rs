@@ -0,0 +1,338 @@
+//! Brpc streams: a request carrying `StreamSettings` sets up a stream on its
+//! connection, after which both sides exchange `StreamFrameMeta` frames.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context as TaskContext, Poll};
+
+use futures_util::task::AtomicWaker;
+use futures_util::{Sink, Stream as AsyncStream};
+use protobuf::{Enum, Message, MessageField};
+use tokio::sync::{mpsc, Notify};
+use tracing::{debug, warn};
+
+use server_kit_protocol::streaming_rpc_meta::{
+    Feedback, FrameType, StreamFrameMeta, StreamSettings,
+};
+
+use crate::error::StreamErr;
+use crate::message::CommonMsg;
+use crate::protocol::pack_frame;
+use crate::socket::Connection;
+use crate::{Error, Result};
+
+#[derive(Debug, Clone)]
+pub struct StreamOptions {
+    /// Bytes written but not yet consumed by the peer before writes wait, 0 for no limit.
+    pub max_buf_size: i64,
+}
+
+impl Default for StreamOptions {
+    fn default() -> Self {
+        // same as brpc
+        Self {
+            max_buf_size: 2 * 1024 * 1024,
+        }
+    }
+}
+
+struct Shared {
+    id: i64,
+    conn: Connection,
+    max_buf_size: i64,
+    // settings of the peer, known once the stream is accepted
+    remote: OnceLock<StreamSettings>,
+    produced: AtomicI64,
+    remote_consumed: AtomicI64,
+    writable: AtomicWaker,
+    closed: AtomicBool,
+    on_close: Notify,
+}
+
+impl Shared {
+    fn remote_id(&self) -> i64 {
+        self.remote.get().map_or(0, |remote| remote.stream_id())
+    }
+
+    fn write_frame(
+        &self,
+        frame_type: FrameType,
+        feedback: Option<Feedback>,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        let mut meta = StreamFrameMeta::new();
+        meta.set_stream_id(self.remote_id());
+        meta.set_source_stream_id(self.id);
+        meta.set_frame_type(frame_type);
+        meta.feedback = MessageField::from_option(feedback);
+        let mut msg = CommonMsg::new(data);
+        msg.with_meta(meta.write_to_bytes()?);
+        self.conn.write(pack_frame(msg))
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.writable.wake();
+        self.on_close.notify_one();
+    }
+}
+
+struct Entry {
+    shared: Arc<Shared>,
+    tx: mpsc::UnboundedSender<Result<Vec<u8>>>,
+    // data of a message continued in following frames
+    partial: Vec<u8>,
+}
+
+fn streams() -> &'static Mutex<HashMap<i64, Entry>> {
+    static STREAMS: OnceLock<Mutex<HashMap<i64, Entry>>> = OnceLock::new();
+    STREAMS.get_or_init(Default::default)
+}
+
+/// One end of a stream, read as a `futures::Stream` of messages and written
+/// as a `futures::Sink`. Dropping it closes the stream.
+pub struct Stream {
+    shared: Arc<Shared>,
+    rx: mpsc::UnboundedReceiver<Result<Vec<u8>>>,
+    consumed: i64,
+}
+
+impl Stream {
+    /// Register a new local stream on `conn`, and the settings to tell the peer about it.
+    pub(crate) fn create(conn: &Connection, options: &StreamOptions) -> (Self, StreamSettings) {
+        static NEXT_ID: AtomicI64 = AtomicI64::new(1);
+
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let shared = Arc::new(Shared {
+            id,
+            conn: conn.clone(),
+            max_buf_size: options.max_buf_size.max(0),
+            remote: OnceLock::new(),
+            produced: AtomicI64::new(0),
+            remote_consumed: AtomicI64::new(0),
+            writable: AtomicWaker::new(),
+            closed: AtomicBool::new(false),
+            on_close: Notify::new(),
+        });
+        let (tx, rx) = mpsc::unbounded_channel();
+        let entry = Entry {
+            shared: Arc::clone(&shared),
+            tx,
+            partial: vec![],
+        };
+        streams().lock().unwrap().insert(id, entry);
+
+        let mut settings = StreamSettings::new();
+        settings.set_stream_id(id);
+        settings.set_need_feedback(shared.max_buf_size > 0);
+        settings.set_writable(true);
+        let stream = Self {
+            shared,
+            rx,
+            consumed: 0,
+        };
+        (stream, settings)
+    }
+
+    /// Accept the stream the peer described by `remote`.
+    pub(crate) fn accept(
+        conn: &Connection,
+        remote: StreamSettings,
+        options: &StreamOptions,
+    ) -> (Self, StreamSettings) {
+        let (stream, settings) = Self::create(conn, options);
+        stream.connect(remote);
+        (stream, settings)
+    }
+
+    pub(crate) fn connect(&self, remote: StreamSettings) {
+        let _ = self.shared.remote.set(remote);
+    }
+
+    pub fn id(&self) -> i64 {
+        self.shared.id
+    }
+
+    /// Resolves once the stream is closed by either side.
+    pub(crate) fn closed(&self) -> impl Future<Output = ()> + Send + 'static {
+        let shared = Arc::clone(&self.shared);
+        async move {
+            if !shared.closed.load(Ordering::Acquire) {
+                shared.on_close.notified().await;
+            }
+        }
+    }
+
+    fn check_open(&self) -> Result<()> {
+        match self.shared.closed.load(Ordering::Acquire) {
+            true => Err(StreamErr::Closed(self.shared.id).into()),
+            false => Ok(()),
+        }
+    }
+}
+
+impl AsyncStream for Stream {
+    type Item = Result<Vec<u8>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        let data = match self.rx.poll_recv(cx) {
+            Poll::Ready(Some(Ok(data))) => data,
+            res => return res,
+        };
+
+        // tell the writer how much has been consumed, if it waits on it
+        self.consumed += data.len() as i64;
+        let need_feedback = self
+            .shared
+            .remote
+            .get()
+            .is_some_and(|remote| remote.need_feedback());
+        if need_feedback {
+            let mut feedback = Feedback::new();
+            feedback.set_consumed_size(self.consumed);
+            if let Err(e) =
+                self.shared
+                    .write_frame(FrameType::FRAME_TYPE_FEEDBACK, Some(feedback), vec![])
+            {
+                warn!(id = self.shared.id, "send feedback err:{e}");
+            }
+        }
+
+        Poll::Ready(Some(Ok(data)))
+    }
+}
+
+impl Sink<Vec<u8>> for Stream {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Result<()>> {
+        let shared = &self.shared;
+        if shared.max_buf_size > 0 {
+            shared.writable.register(cx.waker());
+        }
+        self.check_open()?;
+        let unconsumed = shared.produced.load(Ordering::Acquire)
+            - shared.remote_consumed.load(Ordering::Acquire);
+        match shared.max_buf_size > 0 && unconsumed >= shared.max_buf_size {
+            true => Poll::Pending,
+            false => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, data: Vec<u8>) -> Result<()> {
+        self.check_open()?;
+        self.shared
+            .produced
+            .fetch_add(data.len() as i64, Ordering::AcqRel);
+        self.shared
+            .write_frame(FrameType::FRAME_TYPE_DATA, None, data)
+    }
+
+    // frames are handed to the connection as they are sent
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut TaskContext<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut TaskContext<'_>) -> Poll<Result<()>> {
+        if self.shared.closed.swap(true, Ordering::AcqRel) {
+            return Poll::Ready(Ok(()));
+        }
+        streams().lock().unwrap().remove(&self.shared.id);
+        let res = self
+            .shared
+            .write_frame(FrameType::FRAME_TYPE_CLOSE, None, vec![]);
+        self.shared.close();
+        Poll::Ready(res)
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        streams().lock().unwrap().remove(&self.shared.id);
+        if !self.shared.closed.swap(true, Ordering::AcqRel) {
+            // the peer never knew of a stream it didn't accept
+            if self.shared.remote.get().is_some() {
+                let _ = self
+                    .shared
+                    .write_frame(FrameType::FRAME_TYPE_CLOSE, None, vec![]);
+            }
+            self.shared.close();
+        }
+    }
+}
+
+/// Deliver a frame read from `conn` to the local stream it is addressed to.
+pub(crate) fn on_frame(conn: &Connection, msg: CommonMsg) -> Result<()> {
+    let meta = StreamFrameMeta::parse_from_bytes(&msg.meta)?;
+    let frame_type = meta.frame_type();
+    let mut streams = streams().lock().unwrap();
+    let entry = match streams.get_mut(&meta.stream_id()) {
+        Some(entry) if entry.shared.conn.id() == conn.id() => entry,
+        _ => {
+            debug!(
+                id = meta.stream_id(),
+                ?frame_type,
+                "frame of unknown stream"
+            );
+            if frame_type != FrameType::FRAME_TYPE_RST {
+                let mut rst = StreamFrameMeta::new();
+                rst.set_stream_id(meta.source_stream_id());
+                rst.set_source_stream_id(meta.stream_id());
+                rst.set_frame_type(FrameType::FRAME_TYPE_RST);
+                let mut msg = CommonMsg::default();
+                msg.with_meta(rst.write_to_bytes()?);
+                conn.write(pack_frame(msg))?;
+            }
+            return Ok(());
+        }
+    };
+
+    match frame_type {
+        FrameType::FRAME_TYPE_DATA => {
+            entry.partial.extend_from_slice(&msg.payload);
+            if !meta.has_continuation() {
+                let data = std::mem::take(&mut entry.partial);
+                let _ = entry.tx.send(Ok(data));
+            }
+        }
+        FrameType::FRAME_TYPE_FEEDBACK => {
+            let consumed = meta.feedback.consumed_size();
+            let shared = &entry.shared;
+            shared.remote_consumed.fetch_max(consumed, Ordering::AcqRel);
+            shared.writable.wake();
+        }
+        FrameType::FRAME_TYPE_CLOSE => {
+            // dropping the sender ends the reading side
+            let entry = streams.remove(&meta.stream_id()).unwrap();
+            entry.shared.close();
+        }
+        FrameType::FRAME_TYPE_RST => {
+            let entry = streams.remove(&meta.stream_id()).unwrap();
+            let _ = entry.tx.send(Err(StreamErr::Reset(entry.shared.id).into()));
+            entry.shared.close();
+        }
+        FrameType::FRAME_TYPE_UNKNOWN => {
+            warn!(value = frame_type.value(), "unknown frame type");
+        }
+    }
+
+    Ok(())
+}
+
+/// Close the streams on a connection which is gone.
+pub(crate) fn close_connection(conn_id: u64) {
+    let mut streams = streams().lock().unwrap();
+    streams.retain(|_, entry| {
+        if entry.shared.conn.id() != conn_id {
+            return true;
+        }
+        let err = io::Error::from(io::ErrorKind::ConnectionReset);
+        let _ = entry.tx.send(Err(err.into()));
+        entry.shared.close();
+        false
+    });
+}
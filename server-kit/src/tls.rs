@@ -0,0 +1,149 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::sync::Arc;
+
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::crypto::{ring, CryptoProvider};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+use crate::conf::{ChannelTlsConf, TlsConf};
+use crate::error::ConfErr;
+use crate::Result;
+
+// first byte of a TLS handshake record, which a ClientHello comes in
+const HANDSHAKE: u8 = 0x16;
+
+/// The certificate a peer presented during the TLS handshake.
+#[derive(Debug, Clone)]
+pub struct PeerCertificate {
+    /// DER of the certificate.
+    pub der: Vec<u8>,
+    /// Distinguished name of the subject, as in "CN=client, O=example".
+    pub subject: String,
+    pub common_name: Option<String>,
+}
+
+impl PeerCertificate {
+    fn new(cert: &CertificateDer) -> Self {
+        let der = cert.as_ref().to_vec();
+        // rustls has verified it, so failing to parse hardly happens
+        let (subject, common_name) = match x509_parser::parse_x509_certificate(&der) {
+            Ok((_, x509)) => {
+                let common_name = x509
+                    .subject()
+                    .iter_common_name()
+                    .next()
+                    .and_then(|cn| cn.as_str().ok())
+                    .map(String::from);
+                (x509.subject().to_string(), common_name)
+            }
+            Err(_) => (String::new(), None),
+        };
+        Self {
+            der,
+            subject,
+            common_name,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct ServerTls {
+    pub acceptor: TlsAcceptor,
+    pub allow_plaintext: bool,
+}
+
+impl ServerTls {
+    pub fn new(conf: &TlsConf) -> Result<Self> {
+        let builder = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?;
+        let builder = match &conf.client_ca {
+            Some(client_ca) => {
+                let verifier =
+                    WebPkiClientVerifier::builder_with_provider(roots(client_ca)?, provider())
+                        .build()
+                        .map_err(|e| ConfErr::Invalid("client_ca", e.to_string()))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder.with_single_cert(certs(&conf.cert)?, private_key(&conf.key)?)?;
+
+        Ok(Self {
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+            allow_plaintext: conf.allow_plaintext,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct ClientTls {
+    pub connector: TlsConnector,
+    pub server_name: ServerName<'static>,
+}
+
+impl ClientTls {
+    pub fn new(conf: &ChannelTlsConf) -> Result<Self> {
+        let server_name = ServerName::try_from(conf.server_name.clone())
+            .map_err(|_| ConfErr::Invalid("server_name", conf.server_name.clone()))?;
+        let builder = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots(&conf.ca)?);
+        let config = match (&conf.cert, &conf.key) {
+            (Some(cert), Some(key)) => {
+                builder.with_client_auth_cert(certs(cert)?, private_key(key)?)?
+            }
+            (None, None) => builder.with_no_client_auth(),
+            (Some(cert), None) => return Err(ConfErr::Invalid("key", cert.clone()).into()),
+            (None, Some(key)) => return Err(ConfErr::Invalid("cert", key.clone()).into()),
+        };
+
+        Ok(Self {
+            connector: TlsConnector::from(Arc::new(config)),
+            server_name,
+        })
+    }
+}
+
+/// Whether the client opens the connection with a TLS handshake rather than plaintext.
+pub(crate) async fn is_client_hello(stream: &TcpStream) -> io::Result<bool> {
+    let mut buf = [0; 1];
+    let n = stream.peek(&mut buf).await?;
+    Ok(n == 1 && buf[0] == HANDSHAKE)
+}
+
+pub(crate) fn peer_certificate(certs: Option<&[CertificateDer]>) -> Option<PeerCertificate> {
+    certs
+        .and_then(|certs| certs.first())
+        .map(PeerCertificate::new)
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn certs(path: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader).collect::<io::Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        return Err(ConfErr::Invalid("cert", path.to_string()).into());
+    }
+    Ok(certs)
+}
+
+fn private_key(path: &str) -> Result<PrivateKeyDer<'static>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    rustls_pemfile::private_key(&mut reader)?
+        .ok_or_else(|| ConfErr::Invalid("key", path.to_string()).into())
+}
+
+fn roots(path: &str) -> Result<Arc<RootCertStore>> {
+    let mut roots = RootCertStore::empty();
+    for cert in certs(path)? {
+        roots.add(cert)?;
+    }
+    Ok(Arc::new(roots))
+}
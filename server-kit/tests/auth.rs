@@ -0,0 +1,145 @@
+mod common;
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use protobuf::Message;
+
+use server_kit::auth::{Authenticator, SharedToken};
+use server_kit::channel::Channel;
+use server_kit::error::{RpcErr, ERPCAUTH};
+use server_kit::message::CommonMsg;
+use server_kit::protocol::{Brpc, SofaPbrpc};
+use server_kit::{
+    Context, Controller, Error, MethodDescriptor, Result, Service, ServiceDescriptor,
+};
+use server_kit_protocol::sofa_pbrpc_meta::sofa_rpc_meta::Type;
+use server_kit_protocol::sofa_pbrpc_meta::SofaRpcMeta;
+
+use common::{brpc_request, start_server};
+
+/// "test.echo", answering "m" with the identity of the caller.
+struct Whoami;
+
+#[async_trait]
+impl Service for Whoami {
+    fn descriptor(&self) -> ServiceDescriptor {
+        ServiceDescriptor {
+            full_name: "test.echo",
+            methods: vec![MethodDescriptor::new("m")],
+        }
+    }
+
+    async fn call_method(&self, ctx: &mut Context, _method: &str, _req: &[u8]) -> Result<Vec<u8>> {
+        Ok(ctx.identity().unwrap_or("nobody").as_bytes().to_vec())
+    }
+}
+
+/// `SharedToken` counting its verifications.
+struct Counting {
+    token: SharedToken,
+    every_request: bool,
+    verified: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl Authenticator for Counting {
+    async fn verify(&self, auth_data: &[u8], remote_addr: SocketAddr) -> Result<String> {
+        self.verified.fetch_add(1, Ordering::SeqCst);
+        self.token.verify(auth_data, remote_addr).await
+    }
+
+    fn verify_every_request(&self) -> bool {
+        self.every_request
+    }
+}
+
+async fn start_auth_server(conf: &str, every_request: bool) -> (String, Arc<AtomicUsize>) {
+    let verified = Arc::new(AtomicUsize::new(0));
+    let authenticator = Counting {
+        token: SharedToken::new("alice", "secret"),
+        every_request,
+        verified: Arc::clone(&verified),
+    };
+    let addr = start_server(conf, |server| {
+        server.add_service(Whoami).unwrap();
+        server.set_authenticator(authenticator);
+    })
+    .await;
+    (addr, verified)
+}
+
+#[tokio::test]
+async fn right_token() {
+    let (addr, verified) = start_auth_server("", false).await;
+    let ch = Channel::<Brpc>::new(addr).with_credential(SharedToken::new("alice", "secret"));
+
+    for _ in 0..2 {
+        let resp = ch
+            .call(&mut Controller::new(), brpc_request("m", b""))
+            .await
+            .unwrap();
+        assert_eq!(resp, b"alice");
+    }
+    // once per connection
+    assert_eq!(verified.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn wrong_token() {
+    let (addr, _) = start_auth_server("", false).await;
+    for ch in [
+        Channel::<Brpc>::new(addr.clone()).with_credential(SharedToken::new("alice", "guess")),
+        Channel::<Brpc>::new(addr.clone()),
+    ] {
+        let err = ch
+            .call(&mut Controller::new(), brpc_request("m", b""))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::Rpc(RpcErr::Failed(ERPCAUTH, _))),
+            "{err}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn verify_every_request() {
+    let (addr, verified) = start_auth_server("", true).await;
+    let ch = Channel::<Brpc>::new(addr).with_credential(SharedToken::new("alice", "secret"));
+
+    for _ in 0..3 {
+        let resp = ch
+            .call(&mut Controller::new(), brpc_request("m", b""))
+            .await
+            .unwrap();
+        assert_eq!(resp, b"alice");
+    }
+    assert_eq!(verified.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn refuse_protocol_without_credential() {
+    let conf = "protocols = [\"baidu_std\", \"sofa_pbrpc\"]";
+    let (addr, verified) = start_auth_server(conf, false).await;
+    let ch = Channel::<SofaPbrpc>::new(addr.clone());
+
+    let mut meta = SofaRpcMeta::new();
+    meta.set_type(Type::REQUEST);
+    meta.set_sequence_id(0);
+    meta.set_method("test.echo.m".to_string());
+    let mut req = CommonMsg::default();
+    req.with_meta(meta.write_to_bytes().unwrap());
+    assert!(ch.call(&mut Controller::new(), req).await.is_err());
+    assert_eq!(verified.load(Ordering::SeqCst), 0);
+
+    // while brpc is served alongside
+    let ch = Channel::<Brpc>::new(addr).with_credential(SharedToken::new("alice", "secret"));
+    let resp = ch
+        .call(&mut Controller::new(), brpc_request("m", b""))
+        .await
+        .unwrap();
+    assert_eq!(resp, b"alice");
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use protobuf::{Enum, Message};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use server_kit::error::{RpcErr, ENOSERVICE, EREQUEST};
+use server_kit::message::CommonMsg;
+use server_kit::protocol::{Brpc, Protocol};
+use server_kit::{Context, Controller, MethodDescriptor, Result, Service, ServiceDescriptor};
+use server_kit_protocol::baidu_rpc_meta::{RpcMeta, RpcRequestMeta};
+use server_kit_protocol::options::CompressType;
+
+use common::{brpc_request, start_server, Echo};
+
+/// "test.fail", failing every call with error code 42.
+struct Fail;
+
+#[async_trait]
+impl Service for Fail {
+    fn descriptor(&self) -> ServiceDescriptor {
+        ServiceDescriptor {
+            full_name: "test.fail",
+            methods: vec![MethodDescriptor::new("m")],
+        }
+    }
+
+    async fn call_method(&self, _ctx: &mut Context, _method: &str, _req: &[u8]) -> Result<Vec<u8>> {
+        Err(RpcErr::Failed(42, "failed as asked".to_string()).into())
+    }
+}
+
+fn with_meta(msg: &mut CommonMsg, edit: impl FnOnce(&mut RpcMeta)) {
+    let mut meta = RpcMeta::parse_from_bytes(&msg.meta).unwrap();
+    edit(&mut meta);
+    msg.with_meta(meta.write_to_bytes().unwrap());
+}
+
+// write `msg` on `stream` and read the response back
+async fn exchange(stream: &mut TcpStream, msg: CommonMsg) -> (i32, String, Vec<u8>) {
+    let brpc = Brpc::default();
+    let req = brpc.pack_request(&Controller::new(), msg).unwrap();
+    stream.write_all(&req).await.unwrap();
+
+    let mut buf = BytesMut::new();
+    let msg = loop {
+        match brpc.parse(&mut buf) {
+            Ok(msg) => break msg,
+            Err(_) => assert!(stream.read_buf(&mut buf).await.unwrap() > 0),
+        }
+    };
+    let meta = RpcMeta::parse_from_bytes(&msg.meta).unwrap();
+    let text = meta.response.error_text().to_string();
+    (meta.response.error_code(), text, msg.payload)
+}
+
+#[tokio::test]
+async fn errors_keep_connection() {
+    let addr = start_server("", |server| {
+        server.add_service(Echo).unwrap();
+        server.add_service(Fail).unwrap();
+    })
+    .await;
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+
+    let mut nope = RpcRequestMeta::new();
+    nope.set_service_name("test.nope".to_string());
+    nope.set_method_name("m".to_string());
+    let mut msg = brpc_request("m", b"");
+    with_meta(&mut msg, |meta| meta.request = Some(nope).into());
+    let (code, text, _) = exchange(&mut stream, msg).await;
+    assert_eq!(code, ENOSERVICE, "{text}");
+
+    let mut msg = brpc_request("m", b"");
+    with_meta(&mut msg, |meta| {
+        meta.request
+            .mut_or_insert_default()
+            .set_service_name("test.fail".to_string())
+    });
+    let (code, text, _) = exchange(&mut stream, msg).await;
+    assert_eq!(code, 42);
+    assert!(text.contains("failed as asked"), "{text}");
+
+    let malformed: [fn(&mut RpcMeta); 3] = [
+        |meta| meta.set_compress_type(99),
+        |meta| meta.set_attachment_size(1 << 20),
+        |meta| meta.set_compress_type(CompressType::COMPRESS_TYPE_GZIP.value()),
+    ];
+    for edit in malformed {
+        let mut msg = brpc_request("m", b"not gzip");
+        with_meta(&mut msg, edit);
+        let (code, text, _) = exchange(&mut stream, msg).await;
+        assert_eq!(code, EREQUEST, "{text}");
+    }
+
+    // still served on the same connection
+    let (code, _, payload) = exchange(&mut stream, brpc_request("m", b"again")).await;
+    assert_eq!((code, payload.as_slice()), (0, &b"magain"[..]));
+}
@@ -0,0 +1,33 @@
+mod common;
+
+use server_kit::channel::Channel;
+use server_kit::protocol::Brpc;
+use server_kit::Controller;
+use server_kit_protocol::options::CompressType;
+
+use common::{brpc_request, start_server, Echo};
+
+#[tokio::test]
+async fn call_in_chunks() {
+    let addr = start_server("", |server| {
+        server.add_service(Echo).unwrap();
+        server.add_protocol(Brpc::with_chunk_size(10)).unwrap();
+    })
+    .await;
+    let ch = Channel::<Brpc>::new(addr);
+
+    let body: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+    let mut cntl = Controller::new();
+    cntl.set_chunk_size(7);
+    cntl.set_request_compress_type(CompressType::COMPRESS_TYPE_GZIP);
+    cntl.set_request_attachment(vec![9; 33]);
+    let resp = ch.call(&mut cntl, brpc_request("m", &body)).await.unwrap();
+    assert_eq!(resp, [b"m".as_slice(), &body].concat());
+
+    // calls in one piece go on the same connection
+    let resp = ch
+        .call(&mut Controller::new(), brpc_request("m", b"x"))
+        .await
+        .unwrap();
+    assert_eq!(resp, b"mx");
+}
@@ -0,0 +1,110 @@
+#![allow(dead_code)]
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use protobuf::{Message, MessageField};
+use tokio::net::{TcpListener, TcpStream};
+
+use server_kit::message::CommonMsg;
+use server_kit::{Context, MethodDescriptor, Result, Server, Service, ServiceDescriptor};
+use server_kit_protocol::baidu_rpc_meta::{RpcMeta, RpcRequestMeta};
+use server_kit_protocol::options::TalkType;
+
+/// Payloads of the calls to the one-way method "log" of `Echo`.
+pub static LOGGED: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+
+/// "test.echo", answering calls with the method name followed by the request.
+pub struct Echo;
+
+#[async_trait]
+impl Service for Echo {
+    fn descriptor(&self) -> ServiceDescriptor {
+        ServiceDescriptor {
+            full_name: "test.echo",
+            methods: vec![
+                MethodDescriptor::new("m"),
+                MethodDescriptor::new("slow"),
+                MethodDescriptor {
+                    name: "log",
+                    request_talk_type: TalkType::TALK_TYPE_ONEWAY,
+                    ..Default::default()
+                },
+            ],
+        }
+    }
+
+    async fn call_method(&self, ctx: &mut Context, method: &str, req: &[u8]) -> Result<Vec<u8>> {
+        if let Some(v) = ctx.request_metadata().get("user_id") {
+            let v = v.to_string();
+            ctx.response_metadata_mut().insert("seen", v);
+        }
+        match method {
+            "slow" => tokio::time::sleep(Duration::from_millis(300)).await,
+            "log" => LOGGED.lock().unwrap().push(req.to_vec()),
+            _ => {}
+        }
+        Ok([method.as_bytes(), req].concat())
+    }
+}
+
+/// How many times `req` was logged by `Echo`, once it was or after a second.
+pub async fn logged(req: &[u8]) -> usize {
+    for _ in 0..50 {
+        let count = LOGGED.lock().unwrap().iter().filter(|r| *r == req).count();
+        if count > 0 {
+            return count;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    0
+}
+
+/// A port nothing listens on, for a while at least.
+pub async fn free_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+/// Start a server on a free port with `conf` appended to its configuration,
+/// set up by `setup`, and return its address once it accepts connections.
+pub async fn start_server<F>(conf: &str, setup: F) -> String
+where
+    F: FnOnce(&mut Server),
+{
+    let port = free_port().await;
+    let path = std::env::temp_dir().join(format!("server_kit_test_{port}.toml"));
+    let conf = format!("ip = \"127.0.0.1\"\nport = {port}\n{conf}");
+    std::fs::write(&path, conf).unwrap();
+    let mut server = Server::new(&path).await.unwrap();
+    setup(&mut server);
+    tokio::spawn(async move { server.start().await.unwrap() });
+
+    let addr = format!("127.0.0.1:{port}");
+    wait_for(&addr).await;
+    addr
+}
+
+/// Wait until `addr` accepts connections.
+pub async fn wait_for(addr: &str) {
+    for _ in 0..100 {
+        if TcpStream::connect(addr).await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("{addr} never came up");
+}
+
+/// A baidu_std request of `method` of "test.echo".
+pub fn brpc_request(method: &str, payload: &[u8]) -> CommonMsg {
+    let mut req_meta = RpcRequestMeta::new();
+    req_meta.set_service_name("test.echo".to_string());
+    req_meta.set_method_name(method.to_string());
+    let mut meta = RpcMeta::new();
+    meta.request = MessageField::some(req_meta);
+    let mut msg = CommonMsg::new(payload.to_vec());
+    msg.with_meta(meta.write_to_bytes().unwrap());
+    msg
+}
@@ -0,0 +1,167 @@
+mod common;
+
+use server_kit::channel::{Channel, ChannelManager};
+use server_kit::conf::ChannelConf;
+use server_kit::error::{ConfErr, ProtocolErr};
+use server_kit::protocol::{Brpc, Protocol};
+use server_kit::{Controller, Error};
+use server_kit_protocol::options::ChannelAttribute;
+
+use common::{brpc_request, free_port, start_server, Echo};
+
+// a client conf with a channel to `addr`, then `extra` lines
+async fn write_client_conf(addr: &str, extra: &str) -> std::path::PathBuf {
+    let port = free_port().await;
+    let path = std::env::temp_dir().join(format!("server_kit_client_{port}.toml"));
+    let conf = format!(
+        "[channels.echo]\n\
+         protocol_name = \"baidu_std\"\n\
+         ns_url = \"list://{addr}\"\n\
+         connection_type_name = \"pooled\"\n\
+         lb_name = \"rr\"\n\
+         connect_timeout_ms = 200\n\
+         timeout_ms = 500\n\
+         max_retry = 3\n\
+         {extra}"
+    );
+    std::fs::write(&path, conf).unwrap();
+    path
+}
+
+fn channel_err(conf: ChannelConf) -> Error {
+    match Channel::<dyn Protocol>::from_conf(&conf) {
+        Ok(_) => panic!("{conf:?} accepted"),
+        Err(e) => e,
+    }
+}
+
+#[tokio::test]
+async fn client_conf() {
+    let addr = start_server("", |server| server.add_service(Echo).unwrap()).await;
+    let path = write_client_conf(&addr, "").await;
+    let manager = ChannelManager::new(&path).await.unwrap();
+
+    let ch = manager.get::<dyn Protocol>("echo").unwrap();
+    let resp = ch
+        .call(&mut Controller::new(), brpc_request("m", b"conf"))
+        .await
+        .unwrap();
+    assert_eq!(resp, b"mconf");
+
+    // shared afterwards, as the protocol it was first built for
+    assert!(manager.get::<dyn Protocol>("echo").is_ok());
+    assert!(manager.get::<Brpc>("echo").is_err());
+    assert!(matches!(
+        manager.get::<Brpc>("nope"),
+        Err(Error::Conf(ConfErr::ChannelNotExist(_)))
+    ));
+}
+
+#[tokio::test]
+async fn channel_from_attribute() {
+    let addr = start_server("", |server| server.add_service(Echo).unwrap()).await;
+    let mut attr = ChannelAttribute::new();
+    attr.set_protocol_name("baidu_std".to_string());
+    attr.set_ns_url(addr);
+    attr.set_timeout_ms(500);
+    let ch = Channel::<Brpc>::from_attribute(&attr).unwrap();
+    let resp = ch
+        .call(&mut Controller::new(), brpc_request("m", b"attr"))
+        .await
+        .unwrap();
+    assert_eq!(resp, b"mattr");
+
+    attr.set_protocol_name("http".to_string());
+    assert!(matches!(
+        Channel::<Brpc>::from_attribute(&attr),
+        Err(Error::Conf(ConfErr::Invalid("protocol_name", _)))
+    ));
+}
+
+#[tokio::test]
+async fn malformed_client_conf() {
+    let path = write_client_conf("127.0.0.1:1", "timeout_ms = \"soon\"\n").await;
+    assert!(matches!(
+        ChannelManager::new(&path).await,
+        Err(Error::Toml(_))
+    ));
+    assert!(matches!(
+        ChannelManager::new("no/such/client.toml").await,
+        Err(Error::Io(_))
+    ));
+}
+
+#[test]
+fn invalid_channel_conf() {
+    let conf = |ns_url: &str| ChannelConf {
+        ns_url: Some(ns_url.to_string()),
+        ..Default::default()
+    };
+
+    let err = channel_err(ChannelConf {
+        protocol_name: Some("nope".to_string()),
+        ..conf("127.0.0.1:1")
+    });
+    assert!(
+        matches!(err, Error::Protocol(ProtocolErr::NotExist(_))),
+        "{err}"
+    );
+
+    for ns_url in ["", "list://", "list:// , ", "bns://echo"] {
+        let err = channel_err(conf(ns_url));
+        assert!(
+            matches!(err, Error::Conf(ConfErr::Invalid("ns_url", _))),
+            "{ns_url}: {err}"
+        );
+    }
+
+    let invalid = [
+        (
+            "connection_type_name",
+            ChannelConf {
+                connection_type_name: Some("sticky".to_string()),
+                ..conf("127.0.0.1:1")
+            },
+        ),
+        (
+            "lb_name",
+            ChannelConf {
+                lb_name: Some("fastest".to_string()),
+                ..conf("127.0.0.1:1")
+            },
+        ),
+        (
+            "mesh_state",
+            ChannelConf {
+                mesh_state: Some("mesh_heavy".to_string()),
+                ..conf("127.0.0.1:1")
+            },
+        ),
+        (
+            "ns_url",
+            ChannelConf {
+                mesh_state: Some("mesh".to_string()),
+                mesh_addr: Some("list://".to_string()),
+                ..conf("127.0.0.1:1")
+            },
+        ),
+    ];
+    for (field, conf) in invalid {
+        let err = channel_err(conf);
+        assert!(
+            matches!(err, Error::Conf(ConfErr::Invalid(f, _)) if f == field),
+            "{field}: {err}"
+        );
+    }
+}
+
+#[test]
+fn missing_tls_files() {
+    let toml = "ns_url = \"127.0.0.1:1\"\n\
+                [tls]\n\
+                ca = \"no/such/ca.pem\"\n\
+                server_name = \"localhost\"\n";
+    let conf: ChannelConf = toml::from_str(toml).unwrap();
+    let err = channel_err(conf);
+    assert!(matches!(err, Error::Io(_)), "{err}");
+}
@@ -0,0 +1,291 @@
+mod common;
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::task::{Context as TaskContext, Poll};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use prost::Message;
+use tokio::sync::oneshot;
+use tonic::codec::{CompressionEncoding, ProstCodec};
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::codegen::{http, BoxFuture, Service as TowerService};
+use tonic::server::{NamedService, UnaryService};
+use tonic::transport::{Endpoint, Server as TonicServer};
+use tonic::{Code, Request, Response, Status};
+
+use server_kit::channel::{Channel, ChannelOptions};
+use server_kit::error::RpcErr;
+use server_kit::protocol::Grpc;
+use server_kit::{Context, Controller, Error, MethodDescriptor, Result};
+use server_kit::{Service, ServiceDescriptor};
+use server_kit_protocol::options::CompressType;
+
+use common::{free_port, start_server, wait_for};
+
+#[derive(Clone, PartialEq, Message)]
+struct HelloRequest {
+    #[prost(string, tag = "1")]
+    name: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct HelloReply {
+    #[prost(string, tag = "1")]
+    message: String,
+}
+
+const SAY_HELLO: &str = "/test.Greeter/SayHello";
+const NOT_FOUND: i32 = 5;
+
+fn greeting(name: &str, deadline: bool) -> String {
+    let name = name.repeat(if name == "long" { 100 } else { 1 });
+    format!("hello {name}, deadline {deadline}")
+}
+
+/// "test.Greeter" served by server-kit, "Fail" fails with NOT_FOUND.
+struct Greeter;
+
+#[async_trait]
+impl Service for Greeter {
+    fn descriptor(&self) -> ServiceDescriptor {
+        ServiceDescriptor {
+            full_name: "test.Greeter",
+            methods: vec![
+                MethodDescriptor::new("SayHello"),
+                MethodDescriptor::new("Fail"),
+            ],
+        }
+    }
+
+    async fn call_method(&self, ctx: &mut Context, method: &str, req: &[u8]) -> Result<Vec<u8>> {
+        let req = HelloRequest::decode(req).map_err(|e| RpcErr::Failed(3, e.to_string()))?;
+        if method == "Fail" {
+            return Err(RpcErr::Failed(NOT_FOUND, format!("no {}", req.name)).into());
+        }
+        if let Some(v) = ctx.request_metadata().get("user_id") {
+            let v = v.to_string();
+            ctx.response_metadata_mut().insert("seen", v);
+        }
+        let deadline = ctx.request_metadata().get("grpc-timeout").is_some();
+        let message = greeting(&req.name, deadline);
+        Ok(HelloReply { message }.encode_to_vec())
+    }
+}
+
+/// "test.Greeter" served by tonic, sending and taking gzip.
+#[derive(Clone)]
+struct TonicGreeter;
+
+struct SayHello;
+
+impl UnaryService<HelloRequest> for SayHello {
+    type Response = HelloReply;
+    type Future = BoxFuture<Response<HelloReply>, Status>;
+
+    fn call(&mut self, req: Request<HelloRequest>) -> Self::Future {
+        Box::pin(async move {
+            let deadline = req.metadata().get("grpc-timeout").is_some();
+            let req = req.into_inner();
+            match req.name.as_str() {
+                "nobody" => Err(Status::not_found("no nobody")),
+                name => Ok(Response::new(HelloReply {
+                    message: greeting(name, deadline),
+                })),
+            }
+        })
+    }
+}
+
+impl TowerService<http::Request<tonic::body::BoxBody>> for TonicGreeter {
+    type Response = http::Response<tonic::body::BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut TaskContext<'_>) -> Poll<std::result::Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<tonic::body::BoxBody>) -> Self::Future {
+        Box::pin(async move {
+            if req.uri().path() != SAY_HELLO {
+                return Ok(Status::unimplemented(req.uri().path()).into_http());
+            }
+            let mut grpc = tonic::server::Grpc::new(ProstCodec::default())
+                .accept_compressed(CompressionEncoding::Gzip)
+                .send_compressed(CompressionEncoding::Gzip);
+            Ok(grpc.unary(SayHello, req).await)
+        })
+    }
+}
+
+impl NamedService for TonicGreeter {
+    const NAME: &'static str = "test.Greeter";
+}
+
+// the server on `addr` until `shutdown` is sent or dropped
+async fn start_tonic(addr: SocketAddr, shutdown: oneshot::Receiver<()>) {
+    tokio::spawn(
+        TonicServer::builder()
+            .add_service(TonicGreeter)
+            .serve_with_shutdown(addr, async {
+                let _ = shutdown.await;
+            }),
+    );
+    wait_for(&addr.to_string()).await;
+}
+
+async fn say_hello(ch: &Channel<Grpc>, cntl: &mut Controller, name: &str) -> Result<String> {
+    let req = HelloRequest {
+        name: name.to_string(),
+    };
+    let resp = ch
+        .unary(cntl, "test.Greeter", "SayHello", req.encode_to_vec())
+        .await?;
+    Ok(HelloReply::decode(resp.as_slice()).unwrap().message)
+}
+
+async fn tonic_client(addr: &str) -> tonic::client::Grpc<tonic::transport::Channel> {
+    let channel = Endpoint::from_shared(format!("http://{addr}"))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    tonic::client::Grpc::new(channel)
+}
+
+async fn tonic_call(
+    client: &mut tonic::client::Grpc<tonic::transport::Channel>,
+    path: &'static str,
+    req: Request<HelloRequest>,
+) -> std::result::Result<Response<HelloReply>, Status> {
+    client.ready().await.unwrap();
+    let codec: ProstCodec<HelloRequest, HelloReply> = ProstCodec::default();
+    client
+        .unary(req, PathAndQuery::from_static(path), codec)
+        .await
+}
+
+fn hello(name: &str) -> Request<HelloRequest> {
+    Request::new(HelloRequest {
+        name: name.to_string(),
+    })
+}
+
+#[tokio::test]
+async fn tonic_client_to_server() {
+    let addr = start_server("", |server| server.add_service(Greeter).unwrap()).await;
+    let mut client = tonic_client(&addr).await;
+
+    let mut req = hello("tonic");
+    req.metadata_mut().insert("user_id", "7".parse().unwrap());
+    req.set_timeout(Duration::from_secs(5));
+    let resp = tonic_call(&mut client, SAY_HELLO, req).await.unwrap();
+    assert_eq!(resp.metadata().get("seen").unwrap(), "7");
+    assert_eq!(resp.into_inner().message, greeting("tonic", true));
+
+    let status = tonic_call(&mut client, "/test.Greeter/Fail", hello("one"))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        (status.code(), status.message()),
+        (Code::NotFound, "no one")
+    );
+
+    let status = tonic_call(&mut client, "/test.Nope/SayHello", hello("one"))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::Unimplemented);
+}
+
+#[tokio::test]
+async fn request_too_large() {
+    let addr = start_server("", |server| server.add_service(Greeter).unwrap()).await;
+    let mut client = tonic_client(&addr).await;
+
+    let status = tonic_call(&mut client, SAY_HELLO, hello(&"x".repeat(64 << 20)))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::ResourceExhausted, "{status}");
+
+    // the connection serves the calls after it
+    let resp = tonic_call(&mut client, SAY_HELLO, hello("again"))
+        .await
+        .unwrap();
+    assert_eq!(resp.into_inner().message, greeting("again", false));
+}
+
+#[tokio::test]
+async fn tonic_client_compressed() {
+    let addr = start_server("", |server| server.add_service(Greeter).unwrap()).await;
+
+    // compressed both ways once the client takes gzip
+    let mut client = tonic_client(&addr)
+        .await
+        .send_compressed(CompressionEncoding::Gzip)
+        .accept_compressed(CompressionEncoding::Gzip);
+    let resp = tonic_call(&mut client, SAY_HELLO, hello("long"))
+        .await
+        .unwrap();
+    assert_eq!(resp.metadata().get("grpc-encoding").unwrap(), "gzip");
+    assert_eq!(resp.into_inner().message, greeting("long", false));
+
+    // and not when it doesn't, though the request was
+    let mut client = tonic_client(&addr)
+        .await
+        .send_compressed(CompressionEncoding::Gzip);
+    let resp = tonic_call(&mut client, SAY_HELLO, hello("long"))
+        .await
+        .unwrap();
+    assert!(resp.metadata().get("grpc-encoding").is_none());
+    assert_eq!(resp.into_inner().message, greeting("long", false));
+}
+
+#[tokio::test]
+async fn channel_to_tonic_server() {
+    let addr = SocketAddr::from(([127, 0, 0, 1], free_port().await));
+    let (_shutdown, rx) = oneshot::channel();
+    start_tonic(addr, rx).await;
+    let options = ChannelOptions {
+        timeout: Some(Duration::from_secs(5)),
+        ..Default::default()
+    };
+    let ch = Channel::<Grpc>::new(addr.to_string()).with_options(options);
+
+    let mut cntl = Controller::new();
+    cntl.set_request_compress_type(CompressType::COMPRESS_TYPE_GZIP);
+    let resp = say_hello(&ch, &mut cntl, "long").await.unwrap();
+    assert_eq!(resp, greeting("long", true));
+    assert_eq!(
+        cntl.response_compress_type(),
+        CompressType::COMPRESS_TYPE_GZIP
+    );
+
+    let err = say_hello(&ch, &mut Controller::new(), "nobody")
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(&err, Error::Rpc(RpcErr::Failed(NOT_FOUND, text)) if text == "no nobody"),
+        "{err}"
+    );
+}
+
+#[tokio::test]
+async fn channel_reconnects() {
+    let addr = SocketAddr::from(([127, 0, 0, 1], free_port().await));
+    let (shutdown, rx) = oneshot::channel();
+    start_tonic(addr, rx).await;
+    let ch = Channel::<Grpc>::new(addr.to_string());
+    let resp = say_hello(&ch, &mut Controller::new(), "one").await.unwrap();
+    assert_eq!(resp, greeting("one", false));
+
+    // the connection to the server gone is not used for the calls after the first failing
+    shutdown.send(()).unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let (_shutdown, rx) = oneshot::channel();
+    start_tonic(addr, rx).await;
+    let _ = say_hello(&ch, &mut Controller::new(), "two").await;
+    let resp = say_hello(&ch, &mut Controller::new(), "two").await.unwrap();
+    assert_eq!(resp, greeting("two", false));
+}
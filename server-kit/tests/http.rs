@@ -0,0 +1,244 @@
+mod common;
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use server_kit::channel::{Channel, ChannelOptions};
+use server_kit::conf::ChannelConf;
+use server_kit::error::{ConfErr, RpcErr};
+use server_kit::protocol::{Http, HttpRequest};
+use server_kit::{
+    Context, Controller, Error, MethodDescriptor, Result, Service, ServiceDescriptor,
+};
+
+use common::{start_server, Echo};
+
+/// "test.other", for mappings conflicting with those of `Echo`.
+struct Other;
+
+#[async_trait]
+impl Service for Other {
+    fn descriptor(&self) -> ServiceDescriptor {
+        ServiceDescriptor {
+            full_name: "test.other",
+            methods: vec![MethodDescriptor::new("m")],
+        }
+    }
+
+    async fn call_method(&self, _ctx: &mut Context, _method: &str, _req: &[u8]) -> Result<Vec<u8>> {
+        Ok(b"other".to_vec())
+    }
+}
+
+// a server answering each request, read up to the end of its head, by `respond`
+async fn start_raw_server(respond: fn(&str, &str) -> String) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let local = addr.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let local = local.clone();
+            tokio::spawn(async move {
+                let mut buf = vec![];
+                loop {
+                    let end = buf.windows(4).position(|w| w == b"\r\n\r\n");
+                    if let Some(end) = end {
+                        let head = String::from_utf8(buf.drain(..end + 4).collect()).unwrap();
+                        let resp = respond(&local, &head);
+                        stream.write_all(resp.as_bytes()).await.unwrap();
+                        continue;
+                    }
+                    let mut chunk = [0; 1024];
+                    match stream.read(&mut chunk).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                    }
+                }
+            });
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn head_response_without_body() {
+    // a server answering HEAD with the Content-Length of the body it leaves out
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buf = vec![0; 1024];
+        loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            if n == 0 {
+                return;
+            }
+            let resp: &[u8] = match buf.starts_with(b"HEAD ") {
+                true => b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n",
+                false => b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello",
+            };
+            stream.write_all(resp).await.unwrap();
+        }
+    });
+
+    let options = ChannelOptions {
+        timeout: Some(Duration::from_secs(1)),
+        ..Default::default()
+    };
+    let ch = Channel::<Http>::new(addr).with_options(options);
+    let mut cntl = Controller::new();
+    let resp = ch
+        .request(&mut cntl, HttpRequest::new("HEAD", "/"))
+        .await
+        .unwrap();
+    assert_eq!(resp.headers.get("content-length"), Some("5"));
+    assert!(resp.body.is_empty());
+
+    // the connection is left ready for the next response
+    let resp = ch
+        .request(&mut Controller::new(), HttpRequest::get("/"))
+        .await
+        .unwrap();
+    assert_eq!(resp.body, b"hello");
+}
+
+#[tokio::test]
+async fn restful_mappings() {
+    let mut conflicts = vec![];
+    let addr = start_server("", |server| {
+        server
+            .add_service_with_restful_mappings(Echo, "/v1/echo/* => m, /v1/echo => slow")
+            .unwrap();
+        conflicts.push(server.add_service_with_restful_mappings(Other, "/v1/echo/* => m"));
+        conflicts.push(server.add_service_with_restful_mappings(Other, "/v2 => m, /v2 => m"));
+        conflicts.push(server.add_service_with_restful_mappings(Other, "/v2 => nope"));
+        server.add_service(Other).unwrap();
+    })
+    .await;
+    for res in conflicts {
+        assert!(
+            matches!(
+                res,
+                Err(Error::Conf(ConfErr::Invalid("restful_mappings", _)))
+            ),
+            "{res:?}"
+        );
+    }
+
+    let ch = Channel::<Http>::new(addr);
+    let get = |path: &str| HttpRequest::get(path).header("Accept", "application/proto");
+    for (path, body) in [("/v1/echo/a/b", &b"m"[..]), ("/v1/echo", b"slow")] {
+        let resp = ch.request(&mut Controller::new(), get(path)).await.unwrap();
+        assert_eq!(resp.body, body, "{path}");
+    }
+
+    // unmapped paths don't fall through to /Service/Method of mapped services
+    for path in ["/v1/other", "/test.echo/m", "/echo/m"] {
+        let resp = ch.request(&mut Controller::new(), get(path)).await.unwrap();
+        assert_eq!(resp.status, 404, "{path}");
+    }
+    // unlike those of services without mappings
+    let resp = ch
+        .request(&mut Controller::new(), get("/test.other/m"))
+        .await
+        .unwrap();
+    assert_eq!(resp.body, b"other");
+}
+
+#[tokio::test]
+async fn host_of_each_server() {
+    // answering with the address it listens on and the Host it is sent
+    fn respond(local: &str, head: &str) -> String {
+        let host = head
+            .lines()
+            .filter_map(|line| line.strip_prefix("Host: "))
+            .collect::<Vec<_>>()
+            .join(",");
+        let body = format!("{local} {host}");
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+    }
+    let servers = [
+        start_raw_server(respond).await,
+        start_raw_server(respond).await,
+    ];
+    let conf = ChannelConf {
+        ns_url: Some(format!("list://{},{}", servers[0], servers[1])),
+        ..Default::default()
+    };
+    let ch = Channel::<Http>::from_conf(&conf).unwrap();
+
+    let mut seen = vec![];
+    for _ in 0..4 {
+        let resp = ch
+            .request(&mut Controller::new(), HttpRequest::get("/"))
+            .await
+            .unwrap();
+        let body = String::from_utf8(resp.body).unwrap();
+        let (local, host) = body.split_once(' ').unwrap();
+        assert_eq!(local, host);
+        seen.push(local.to_string());
+    }
+    assert!(
+        servers.iter().all(|server| seen.contains(server)),
+        "{seen:?}"
+    );
+
+    // unless the request has one
+    let req = HttpRequest::get("http://example.com/");
+    let resp = ch.request(&mut Controller::new(), req).await.unwrap();
+    assert!(resp.body.ends_with(b" example.com"));
+}
+
+#[tokio::test]
+async fn error_status_kept() {
+    fn respond(_: &str, head: &str) -> String {
+        let status = match head.starts_with("GET /missing ") {
+            true => "404 Not Found",
+            false => "500 Internal Server Error",
+        };
+        format!("HTTP/1.1 {status}\r\nX-Reason: why\r\nContent-Length: 4\r\n\r\noops")
+    }
+    let ch = Channel::<Http>::new(start_raw_server(respond).await);
+
+    for (path, status) in [("/missing", 404), ("/broken", 500)] {
+        let mut cntl = Controller::new();
+        let resp = ch.request(&mut cntl, HttpRequest::get(path)).await.unwrap();
+        assert_eq!(resp.status, status);
+        assert_eq!(resp.headers.get("x-reason"), Some("why"));
+        assert_eq!(resp.body, b"oops");
+        assert_eq!(cntl.http_status(), Some(status));
+
+        let err = resp.into_success().unwrap_err();
+        assert!(
+            matches!(&err, Error::Rpc(RpcErr::Failed(code, text)) if *code == status as i32 && text == "oops"),
+            "{err}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn chunked_response() {
+    fn respond(_: &str, _: &str) -> String {
+        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+         5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n"
+            .to_string()
+    }
+    let ch = Channel::<Http>::new(start_raw_server(respond).await);
+
+    // twice on the same connection, as the whole body was read
+    for _ in 0..2 {
+        let resp = ch
+            .request(&mut Controller::new(), HttpRequest::get("/"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.body, b"hello world");
+    }
+}
@@ -0,0 +1,104 @@
+mod common;
+
+use async_trait::async_trait;
+
+use server_kit::channel::Channel;
+use server_kit::protocol::{NsheadMcpack, UbrpcCompack, UbrpcMcpack2};
+use server_kit::{Context, Controller, MethodDescriptor, Result, Service, ServiceDescriptor};
+use server_kit_protocol::baidu_rpc_meta::RpcRequestMeta;
+
+use common::start_server;
+
+/// "test.meta", answering "echo" with the request, and with `reserved` of
+/// the nshead header of the response set to the log_id of the request.
+struct MetaEcho;
+
+#[async_trait]
+impl Service for MetaEcho {
+    fn descriptor(&self) -> ServiceDescriptor {
+        ServiceDescriptor {
+            full_name: "test.meta",
+            methods: vec![MethodDescriptor::new("echo")
+                .with_message_types::<RpcRequestMeta, RpcRequestMeta>()],
+        }
+    }
+
+    async fn call_method(&self, ctx: &mut Context, _method: &str, req: &[u8]) -> Result<Vec<u8>> {
+        let log_id = ctx.nshead_header().unwrap().log_id();
+        ctx.nshead_response_header_mut()
+            .unwrap()
+            .set_reserved(log_id);
+        Ok(req.to_vec())
+    }
+}
+
+fn request() -> RpcRequestMeta {
+    let mut req = RpcRequestMeta::new();
+    req.set_service_name("s".to_string());
+    req.set_method_name("m".to_string());
+    req.set_log_id(-1);
+    req
+}
+
+fn controller(log_id: u32) -> Controller {
+    let mut cntl = Controller::new();
+    cntl.set_nshead_log_id(log_id);
+    cntl.set_nshead_provider("tester");
+    cntl
+}
+
+// the nshead header of the response echoes the request but as the handler changed it
+fn assert_echoed(cntl: &Controller, log_id: u32) {
+    let head = cntl.nshead_response_header().unwrap();
+    assert_eq!(head.log_id(), log_id);
+    assert_eq!(head.provider(), b"tester");
+    assert_eq!(head.reserved(), log_id);
+}
+
+#[tokio::test]
+async fn nshead_mcpack_echoes_header() {
+    let conf = "protocols = [\"nshead_mcpack\"]";
+    let addr = start_server(conf, |server| server.add_service(MetaEcho).unwrap()).await;
+    let ch = Channel::<NsheadMcpack>::new(addr);
+
+    for log_id in [3, 4] {
+        let mut cntl = controller(log_id);
+        let resp: RpcRequestMeta = ch.call_message(&mut cntl, &request()).await.unwrap();
+        assert_eq!(resp, request());
+        assert_echoed(&cntl, log_id);
+    }
+}
+
+#[tokio::test]
+async fn ubrpc_echoes_header() {
+    let conf = "protocols = [\"ubrpc_compack\", \"ubrpc_mcpack2\"]";
+    let addr = start_server(conf, |server| server.add_service(MetaEcho).unwrap()).await;
+
+    let ch = Channel::<UbrpcCompack>::new(addr.clone());
+    let mut cntl = controller(5);
+    let resp: RpcRequestMeta = ch
+        .call_method(&mut cntl, "test.meta", "echo", &request())
+        .await
+        .unwrap();
+    assert_eq!(resp, request());
+    assert_echoed(&cntl, 5);
+
+    let ch = Channel::<UbrpcMcpack2>::new(addr);
+    let mut cntl = controller(6);
+    let resp: RpcRequestMeta = ch
+        .call_method(&mut cntl, "test.meta", "echo", &request())
+        .await
+        .unwrap();
+    assert_eq!(resp, request());
+    assert_echoed(&cntl, 6);
+
+    // and so does the one of an error, as it was
+    let mut cntl = controller(7);
+    let err = ch
+        .call_method::<_, RpcRequestMeta>(&mut cntl, "test.meta", "nope", &request())
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("no method nope"), "{err}");
+    let head = cntl.nshead_response_header().unwrap();
+    assert_eq!((head.log_id(), head.reserved()), (7, 0));
+}
@@ -0,0 +1,220 @@
+mod common;
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use protobuf::Message;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use server_kit::channel::{Channel, ChannelOptions, MeshOptions};
+use server_kit::conf::ChannelConf;
+use server_kit::error::RpcErr;
+use server_kit::protocol::Brpc;
+use server_kit::{Controller, Error};
+use server_kit_protocol::baidu_rpc_meta::RpcMeta;
+use server_kit_protocol::options::MeshState;
+
+use common::{brpc_request, logged, start_server, Echo};
+
+async fn read_frame(stream: &mut TcpStream) -> Option<(Vec<u8>, Vec<u8>)> {
+    let mut head = [0; 12];
+    stream.read_exact(&mut head).await.ok()?;
+    let body_size = u32::from_be_bytes(head[4..8].try_into().unwrap()) as usize;
+    let meta_size = u32::from_be_bytes(head[8..12].try_into().unwrap()) as usize;
+    let mut meta = vec![0; body_size];
+    stream.read_exact(&mut meta).await.ok()?;
+    let payload = meta.split_off(meta_size);
+    Some((meta, payload))
+}
+
+fn frame(meta: &[u8], payload: &[u8]) -> Vec<u8> {
+    let mut buf = b"PRPC".to_vec();
+    buf.extend(((meta.len() + payload.len()) as u32).to_be_bytes());
+    buf.extend((meta.len() as u32).to_be_bytes());
+    buf.extend(meta);
+    buf.extend(payload);
+    buf
+}
+
+// a stand-in sidecar routing every baidu_std request to `backend`, and telling
+// the caller about it in the response meta
+async fn start_sidecar(backend: SocketAddr) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        loop {
+            let (mut client, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut upstream = TcpStream::connect(backend).await.unwrap();
+                while let Some((meta, payload)) = read_frame(&mut client).await {
+                    upstream.write_all(&frame(&meta, &payload)).await.unwrap();
+                    let (meta, payload) = read_frame(&mut upstream).await.unwrap();
+                    let mut meta = RpcMeta::parse_from_bytes(&meta).unwrap();
+                    let resp_meta = meta.response.mut_or_insert_default();
+                    let SocketAddr::V4(backend) = backend else {
+                        unreachable!()
+                    };
+                    resp_meta.set_real_remote_ip(u32::from(*backend.ip()));
+                    resp_meta.set_real_remote_port(backend.port() as u32);
+                    let meta = meta.write_to_bytes().unwrap();
+                    client.write_all(&frame(&meta, &payload)).await.unwrap();
+                }
+            });
+        }
+    });
+    addr
+}
+
+// a sidecar that takes requests and never answers them
+async fn start_silent_sidecar() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        let mut conns = vec![];
+        loop {
+            conns.push(listener.accept().await.unwrap());
+        }
+    });
+    addr
+}
+
+async fn dead_addr() -> String {
+    format!("127.0.0.1:{}", common::free_port().await)
+}
+
+async fn start_backend() -> String {
+    start_server("", |server| server.add_service(Echo).unwrap()).await
+}
+
+#[tokio::test]
+async fn mesh_reports_real_backend() {
+    let backend = start_backend().await;
+    let sidecar = start_sidecar(backend.parse().unwrap()).await;
+    // the servers of the channel are never reached directly
+    let ch = Channel::<Brpc>::new(dead_addr().await)
+        .with_mesh(MeshOptions::new(MeshState::MESH_STATE_MESH, &sidecar))
+        .unwrap();
+
+    for _ in 0..2 {
+        let mut cntl = Controller::new();
+        let resp = ch.call(&mut cntl, brpc_request("m", b"x")).await.unwrap();
+        assert_eq!(resp, b"mx");
+        assert_eq!(cntl.remote_addr().unwrap().to_string(), sidecar);
+        assert_eq!(cntl.real_remote_addr().unwrap().to_string(), backend);
+    }
+    // metadata of the caller goes through as it is
+    let mut cntl = Controller::new();
+    cntl.request_metadata_mut().insert("user_id", "7");
+    ch.call(&mut cntl, brpc_request("m", b"")).await.unwrap();
+    assert_eq!(cntl.response_metadata().get("seen"), Some("7"));
+    assert_eq!(cntl.request_metadata().len(), 1);
+}
+
+#[tokio::test]
+async fn mesh_sidecar_list() {
+    let backend = start_backend().await;
+    let first = start_sidecar(backend.parse().unwrap()).await;
+    let second = start_sidecar(backend.parse().unwrap()).await;
+    let mesh = MeshOptions {
+        lb_name: "rr".to_string(),
+        ..MeshOptions::new(
+            MeshState::MESH_STATE_MESH,
+            format!("list://{first},{second}"),
+        )
+    };
+    let ch = Channel::<Brpc>::new(backend).with_mesh(mesh).unwrap();
+
+    let mut sidecars = vec![];
+    for _ in 0..2 {
+        let mut cntl = Controller::new();
+        ch.call(&mut cntl, brpc_request("m", b"")).await.unwrap();
+        sidecars.push(cntl.remote_addr().unwrap().to_string());
+    }
+    sidecars.sort();
+    let mut expected = vec![first, second];
+    expected.sort();
+    assert_eq!(sidecars, expected);
+
+    let bad = MeshOptions::new(MeshState::MESH_STATE_MESH, "list://");
+    assert!(Channel::<Brpc>::new(dead_addr().await)
+        .with_mesh(bad)
+        .is_err());
+}
+
+#[tokio::test]
+async fn mesh_timeout() {
+    let sidecar = start_silent_sidecar().await;
+    let options = ChannelOptions {
+        timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let mesh = MeshOptions {
+        timeout: Some(Duration::from_millis(200)),
+        ..MeshOptions::new(MeshState::MESH_STATE_MESH, sidecar)
+    };
+    let ch = Channel::<Brpc>::new(dead_addr().await)
+        .with_options(options)
+        .with_mesh(mesh)
+        .unwrap();
+
+    let start = Instant::now();
+    let err = ch
+        .call(&mut Controller::new(), brpc_request("m", b""))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::Rpc(RpcErr::Timeout(_))), "{err}");
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn mesh_without_sidecar() {
+    let backend = start_backend().await;
+    let sidecar = dead_addr().await;
+
+    // mesh mode only ever goes through the sidecar
+    let ch = Channel::<Brpc>::new(backend.clone())
+        .with_mesh(MeshOptions::new(MeshState::MESH_STATE_MESH, &sidecar))
+        .unwrap();
+    let mut cntl = Controller::new();
+    assert!(ch.call(&mut cntl, brpc_request("m", b"")).await.is_err());
+    let res = ch.call_oneway(&mut cntl, brpc_request("log", b"")).await;
+    assert!(res.is_err());
+
+    // mesh_lite goes to the servers instead, for both kinds of calls
+    let ch = Channel::<Brpc>::new(backend.clone())
+        .with_mesh(MeshOptions::new(MeshState::MESH_STATE_MESH_LITE, &sidecar))
+        .unwrap();
+    let mut cntl = Controller::new();
+    let resp = ch.call(&mut cntl, brpc_request("m", b"y")).await.unwrap();
+    assert_eq!(resp, b"my");
+    assert_eq!(cntl.remote_addr().unwrap().to_string(), backend);
+    assert_eq!(cntl.real_remote_addr(), None);
+
+    let mut cntl = Controller::new();
+    ch.call_oneway(&mut cntl, brpc_request("log", b"mesh-lite-oneway"))
+        .await
+        .unwrap();
+    assert_eq!(cntl.remote_addr().unwrap().to_string(), backend);
+    assert_eq!(logged(b"mesh-lite-oneway").await, 1);
+}
+
+#[tokio::test]
+async fn mesh_from_conf() {
+    let backend = start_backend().await;
+    let sidecar = start_sidecar(backend.parse().unwrap()).await;
+    let conf = ChannelConf {
+        ns_url: Some(dead_addr().await),
+        mesh_state: Some("mesh".to_string()),
+        mesh_timeout_ms: Some(1000),
+        mesh_addr: Some(sidecar.clone()),
+        ..Default::default()
+    };
+    let ch = Channel::<Brpc>::from_conf(&conf).unwrap();
+
+    let mut cntl = Controller::new();
+    ch.call(&mut cntl, brpc_request("m", b"")).await.unwrap();
+    assert_eq!(cntl.remote_addr().unwrap().to_string(), sidecar);
+    let ip = Ipv4Addr::LOCALHOST;
+    assert_eq!(cntl.real_remote_addr().unwrap().ip(), ip);
+}
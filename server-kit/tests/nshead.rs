@@ -0,0 +1,121 @@
+mod common;
+
+use async_trait::async_trait;
+
+use server_kit::channel::Channel;
+use server_kit::error::{HttpErr, RpcErr};
+use server_kit::message::CommonMsg;
+use server_kit::protocol::{Nshead, NsheadHeader, NsheadPbServiceAdaptor};
+use server_kit::{Context, Controller, MethodDescriptor, Result, Service, ServiceDescriptor};
+
+use common::{start_server, Echo};
+
+/// "test.fail", failing every call.
+struct Fail;
+
+#[async_trait]
+impl Service for Fail {
+    fn descriptor(&self) -> ServiceDescriptor {
+        ServiceDescriptor {
+            full_name: "test.fail",
+            methods: vec![MethodDescriptor::new("m")],
+        }
+    }
+
+    async fn call_method(&self, _ctx: &mut Context, _method: &str, _req: &[u8]) -> Result<Vec<u8>> {
+        Err(RpcErr::Failed(42, "failed as asked".to_string()).into())
+    }
+}
+
+/// Routes bodies of "service.method:request", answering "ok:" followed by
+/// the response, or "err:" followed by the error.
+struct Prefixed;
+
+impl NsheadPbServiceAdaptor for Prefixed {
+    fn parse_method_info(&self, _head: &NsheadHeader, body: &[u8]) -> Result<(String, String)> {
+        let body = String::from_utf8_lossy(body);
+        let (name, _) = body
+            .split_once(':')
+            .ok_or_else(|| HttpErr::InvalidBody("no method".to_string()))?;
+        let (svc_name, method_name) = name
+            .rsplit_once('.')
+            .ok_or_else(|| HttpErr::InvalidBody(name.to_string()))?;
+        Ok((svc_name.to_string(), method_name.to_string()))
+    }
+
+    fn parse_request_from_body(&self, _method: &MethodDescriptor, body: &[u8]) -> Result<Vec<u8>> {
+        let idx = body.iter().position(|b| *b == b':').unwrap();
+        Ok(body[idx + 1..].to_vec())
+    }
+
+    fn serialize_response_to_body(
+        &self,
+        _method: Option<&MethodDescriptor>,
+        res: Result<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        match res {
+            Ok(resp) => Ok([&b"ok:"[..], &resp].concat()),
+            Err(e) => Ok(format!("err:{e}").into_bytes()),
+        }
+    }
+}
+
+async fn call(ch: &Channel<Nshead>, body: &[u8]) -> String {
+    let mut cntl = Controller::new();
+    cntl.set_nshead_log_id(7);
+    let resp = ch
+        .call(&mut cntl, CommonMsg::new(body.to_vec()))
+        .await
+        .unwrap();
+    // the header of the request echoed
+    assert_eq!(cntl.nshead_response_header().unwrap().log_id(), 7);
+    String::from_utf8(resp).unwrap()
+}
+
+#[tokio::test]
+async fn adaptor_round_trip() {
+    let addr = start_server("protocols = [\"nshead\"]", |server| {
+        server.add_service(Echo).unwrap();
+        server.add_service(Fail).unwrap();
+        server.add_protocol(Nshead::with_adaptor(Prefixed)).unwrap();
+    })
+    .await;
+    let ch = Channel::<Nshead>::new(addr);
+
+    // by full or short service name
+    assert_eq!(call(&ch, b"test.echo.m:hello").await, "ok:mhello");
+    assert_eq!(call(&ch, b"echo.slow:hi").await, "ok:slowhi");
+
+    // failures are answered, on the same connection
+    let resp = call(&ch, b"echo.nope:hello").await;
+    assert!(
+        resp.starts_with("err:") && resp.contains("no method nope"),
+        "{resp}"
+    );
+    let resp = call(&ch, b"fail.m:hello").await;
+    assert!(
+        resp.starts_with("err:") && resp.contains("failed as asked"),
+        "{resp}"
+    );
+    let resp = call(&ch, b"nope.m:hello").await;
+    assert!(resp.starts_with("err:"), "{resp}");
+    let resp = call(&ch, b"no method").await;
+    assert!(resp.starts_with("err:"), "{resp}");
+
+    assert_eq!(call(&ch, b"echo.m:again").await, "ok:magain");
+}
+
+#[tokio::test]
+async fn unbound_with_services() {
+    // nshead of no service nor adaptor, but more than one service
+    let addr = start_server("protocols = [\"nshead\"]", |server| {
+        server.add_service(Echo).unwrap();
+        server.add_service(Fail).unwrap();
+    })
+    .await;
+    let ch = Channel::<Nshead>::new(addr);
+
+    for _ in 0..2 {
+        assert_eq!(call(&ch, b"hello").await, "");
+    }
+}
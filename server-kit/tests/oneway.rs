@@ -0,0 +1,71 @@
+mod common;
+
+use std::time::Duration;
+
+use bytes::BytesMut;
+use protobuf::Message;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use server_kit::channel::Channel;
+use server_kit::protocol::{Brpc, Protocol};
+use server_kit::Controller;
+use server_kit_protocol::baidu_rpc_meta::RpcMeta;
+
+use common::{brpc_request, logged, start_server, Echo};
+
+#[tokio::test]
+async fn call_oneway() {
+    let addr = start_server("", |server| server.add_service(Echo).unwrap()).await;
+    let ch = Channel::<Brpc>::new(addr.clone());
+
+    let mut cntl = Controller::new();
+    ch.call_oneway(&mut cntl, brpc_request("log", b"fire-and-forget"))
+        .await
+        .unwrap();
+    assert_eq!(cntl.remote_addr().unwrap().to_string(), addr);
+    assert_eq!(logged(b"fire-and-forget").await, 1);
+
+    // the connection has no response left on it for the next call
+    let resp = ch
+        .call(&mut Controller::new(), brpc_request("m", b"x"))
+        .await
+        .unwrap();
+    assert_eq!(resp, b"mx");
+}
+
+#[tokio::test]
+async fn oneway_method_sends_no_response() {
+    let addr = start_server("", |server| server.add_service(Echo).unwrap()).await;
+    let brpc = Brpc::default();
+    let pack = |method: &str, payload: &[u8]| {
+        brpc.pack_request(&Controller::new(), brpc_request(method, payload))
+            .unwrap()
+    };
+
+    // a one-way request followed by a normal one on the same connection
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+    stream
+        .write_all(&pack("log", b"no-response"))
+        .await
+        .unwrap();
+    stream.write_all(&pack("m", b"y")).await.unwrap();
+
+    let mut buf = BytesMut::new();
+    let msg = loop {
+        match brpc.parse(&mut buf) {
+            Ok(msg) => break msg,
+            Err(_) => assert!(stream.read_buf(&mut buf).await.unwrap() > 0),
+        }
+    };
+    assert!(RpcMeta::parse_from_bytes(&msg.meta)
+        .unwrap()
+        .response
+        .is_some());
+    assert_eq!(msg.payload, b"my");
+    assert_eq!(logged(b"no-response").await, 1);
+
+    // and nothing else comes
+    let res = tokio::time::timeout(Duration::from_millis(200), stream.read_buf(&mut buf)).await;
+    assert!(res.is_err() && buf.is_empty());
+}
@@ -0,0 +1,241 @@
+mod common;
+
+use bytes::BytesMut;
+use protobuf::Message;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use server_kit::channel::Channel;
+use server_kit::error::{RpcErr, ENOMETHOD, EREQUEST};
+use server_kit::message::CommonMsg;
+use server_kit::protocol::{HuluPbrpc, NovaPbrpc, Protocol, PublicPbrpc, SofaPbrpc};
+use server_kit::{Controller, Error};
+use server_kit_protocol::hulu_pbrpc_meta::{HuluRpcRequestMeta, HuluRpcResponseMeta};
+use server_kit_protocol::options::CompressType;
+use server_kit_protocol::public_pbrpc_meta::RequestBody;
+use server_kit_protocol::sofa_pbrpc_meta::sofa_rpc_meta::Type;
+use server_kit_protocol::sofa_pbrpc_meta::SofaRpcMeta;
+
+use common::{start_server, Echo};
+
+fn sofa_request(method: &str, payload: &[u8]) -> CommonMsg {
+    let mut meta = SofaRpcMeta::new();
+    meta.set_type(Type::REQUEST);
+    meta.set_sequence_id(0);
+    meta.set_method(method.to_string());
+    let mut msg = CommonMsg::new(payload.to_vec());
+    msg.with_meta(meta.write_to_bytes().unwrap());
+    msg
+}
+
+fn hulu_request(method_index: i32, payload: &[u8]) -> CommonMsg {
+    let mut meta = HuluRpcRequestMeta::new();
+    meta.set_service_name("test.echo".to_string());
+    meta.set_method_index(method_index);
+    let mut msg = CommonMsg::new(payload.to_vec());
+    msg.with_meta(meta.write_to_bytes().unwrap());
+    msg
+}
+
+fn nova_request(method_index: u32, payload: &[u8]) -> CommonMsg {
+    let mut msg = CommonMsg::new(payload.to_vec());
+    msg.with_meta(method_index.to_le_bytes().to_vec());
+    msg
+}
+
+fn public_request(method_id: u32, payload: &[u8]) -> CommonMsg {
+    let mut body = RequestBody::new();
+    body.set_service("test.echo".to_string());
+    body.set_method_id(method_id);
+    body.set_id(0);
+    let mut msg = CommonMsg::new(payload.to_vec());
+    msg.with_meta(body.write_to_bytes().unwrap());
+    msg
+}
+
+#[tokio::test]
+async fn sofa_round_trip() {
+    let addr = start_server("", |server| server.add_service(Echo).unwrap()).await;
+    let ch = Channel::<SofaPbrpc>::new(addr);
+
+    let resp = ch
+        .call(
+            &mut Controller::new(),
+            sofa_request("test.echo.m", b"hello"),
+        )
+        .await
+        .unwrap();
+    assert_eq!(resp, b"mhello");
+
+    // answered compressed as asked
+    let mut cntl = Controller::new();
+    cntl.set_request_compress_type(CompressType::COMPRESS_TYPE_GZIP);
+    let resp = ch
+        .call(&mut cntl, sofa_request("test.echo.m", b"zipped"))
+        .await
+        .unwrap();
+    assert_eq!(resp, b"mzipped");
+    assert_eq!(
+        cntl.response_compress_type(),
+        CompressType::COMPRESS_TYPE_GZIP
+    );
+
+    let err = ch
+        .call(&mut Controller::new(), sofa_request("test.echo.nope", b""))
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(err, Error::Rpc(RpcErr::Failed(ENOMETHOD, _))),
+        "{err}"
+    );
+}
+
+#[tokio::test]
+async fn hulu_round_trip() {
+    let addr = start_server("", |server| server.add_service(Echo).unwrap()).await;
+    let ch = Channel::<HuluPbrpc>::new(addr);
+
+    // the attachment is told apart from the request by user_message_size
+    let mut cntl = Controller::new();
+    cntl.set_request_attachment(b"att".to_vec());
+    cntl.set_request_compress_type(CompressType::COMPRESS_TYPE_ZLIB);
+    let resp = ch.call(&mut cntl, hulu_request(0, b"hello")).await.unwrap();
+    assert_eq!(resp, b"mhello");
+    assert_eq!(
+        cntl.response_compress_type(),
+        CompressType::COMPRESS_TYPE_ZLIB
+    );
+
+    let err = ch
+        .call(&mut Controller::new(), hulu_request(7, b""))
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(err, Error::Rpc(RpcErr::Failed(ENOMETHOD, _))),
+        "{err}"
+    );
+}
+
+#[tokio::test]
+async fn hulu_invalid_user_message_size() {
+    let addr = start_server("", |server| server.add_service(Echo).unwrap()).await;
+    let hulu = HuluPbrpc::default();
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+
+    for size in [-1, 6, i32::MAX] {
+        let mut msg = hulu_request(0, b"hello");
+        let mut meta = HuluRpcRequestMeta::parse_from_bytes(&msg.meta).unwrap();
+        meta.set_user_message_size(size);
+        msg.with_meta(meta.write_to_bytes().unwrap());
+        let req = hulu.pack_request(&Controller::new(), msg).unwrap();
+        stream.write_all(&req).await.unwrap();
+
+        let mut buf = BytesMut::new();
+        let msg = loop {
+            match hulu.parse(&mut buf) {
+                Ok(msg) => break msg,
+                Err(_) => assert!(stream.read_buf(&mut buf).await.unwrap() > 0),
+            }
+        };
+        let meta = HuluRpcResponseMeta::parse_from_bytes(&msg.meta).unwrap();
+        assert_eq!(meta.error_code(), EREQUEST, "{size}");
+        assert!(meta.error_text().contains("user_message_size"), "{size}");
+    }
+}
+
+#[tokio::test]
+async fn nova_round_trip() {
+    let addr = start_server("protocols = [\"nova_pbrpc\"]", |server| {
+        server.add_service(Echo).unwrap()
+    })
+    .await;
+    let ch = Channel::<NovaPbrpc>::new(addr);
+
+    let mut cntl = Controller::new();
+    cntl.set_nshead_log_id(42);
+    let resp = ch.call(&mut cntl, nova_request(0, b"hello")).await.unwrap();
+    assert_eq!(resp, b"mhello");
+    assert_eq!(cntl.nshead_response_header().unwrap().log_id(), 42);
+
+    // answered compressed as asked
+    let mut cntl = Controller::new();
+    cntl.set_request_compress_type(CompressType::COMPRESS_TYPE_SNAPPY);
+    let resp = ch
+        .call(&mut cntl, nova_request(0, b"snappy"))
+        .await
+        .unwrap();
+    assert_eq!(resp, b"msnappy");
+    assert_eq!(
+        cntl.response_compress_type(),
+        CompressType::COMPRESS_TYPE_SNAPPY
+    );
+
+    // nova can't compress otherwise, nor reply an error but by closing the connection
+    let mut cntl = Controller::new();
+    cntl.set_request_compress_type(CompressType::COMPRESS_TYPE_GZIP);
+    assert!(ch.call(&mut cntl, nova_request(0, b"")).await.is_err());
+    assert!(ch
+        .call(&mut Controller::new(), nova_request(7, b""))
+        .await
+        .is_err());
+    let resp = ch
+        .call(&mut Controller::new(), nova_request(0, b"again"))
+        .await
+        .unwrap();
+    assert_eq!(resp, b"magain");
+}
+
+#[tokio::test]
+async fn public_round_trip() {
+    let addr = start_server("protocols = [\"public_pbrpc\"]", |server| {
+        server.add_service(Echo).unwrap()
+    })
+    .await;
+    let ch = Channel::<PublicPbrpc>::new(addr);
+
+    let resp = ch
+        .call(&mut Controller::new(), public_request(0, b"hello"))
+        .await
+        .unwrap();
+    assert_eq!(resp, b"mhello");
+
+    // answered compressed as asked
+    let mut cntl = Controller::new();
+    cntl.set_request_compress_type(CompressType::COMPRESS_TYPE_GZIP);
+    let resp = ch
+        .call(&mut cntl, public_request(0, b"zipped"))
+        .await
+        .unwrap();
+    assert_eq!(resp, b"mzipped");
+    assert_eq!(
+        cntl.response_compress_type(),
+        CompressType::COMPRESS_TYPE_GZIP
+    );
+
+    let err = ch
+        .call(&mut Controller::new(), public_request(7, b""))
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(err, Error::Rpc(RpcErr::Failed(ENOMETHOD, _))),
+        "{err}"
+    );
+}
+
+#[tokio::test]
+async fn public_echoes_header() {
+    let addr = start_server("protocols = [\"public_pbrpc\"]", |server| {
+        server.add_service(Echo).unwrap()
+    })
+    .await;
+    let ch = Channel::<PublicPbrpc>::new(addr);
+
+    for (method_id, log_id) in [(0, 3), (7, 4)] {
+        let mut cntl = Controller::new();
+        cntl.set_nshead_log_id(log_id);
+        cntl.set_nshead_provider("tester");
+        let _ = ch.call(&mut cntl, public_request(method_id, b"")).await;
+        let head = cntl.nshead_response_header().unwrap();
+        assert_eq!((head.log_id(), head.provider()), (log_id, &b"tester"[..]));
+    }
+}
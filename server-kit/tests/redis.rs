@@ -0,0 +1,208 @@
+mod common;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use server_kit::channel::{Channel, ChannelOptions};
+use server_kit::protocol::{Redis, RedisCommandHandler, RedisReply, RedisRequest, RedisService};
+use server_kit::{Context, Controller, Result};
+
+use common::{start_server, Echo};
+
+type Store = Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>;
+
+/// A RESP stub of PING, SET, GET, INCR and MGET, which reads commands a few
+/// bytes at a time and writes replies the same way. Returns its address.
+async fn redis_stub() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let store = Store::default();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(serve(stream, store.clone()));
+        }
+    });
+    addr
+}
+
+async fn serve(mut stream: TcpStream, store: Store) {
+    let mut buf = vec![];
+    let mut read = [0; 7];
+    loop {
+        while let Some((args, len)) = parse_command(&buf) {
+            buf.drain(..len);
+            let reply = run(&store, &args);
+            for part in reply.chunks(5) {
+                stream.write_all(part).await.unwrap();
+            }
+        }
+        let n = stream.read(&mut read).await.unwrap();
+        if n == 0 {
+            return;
+        }
+        buf.extend_from_slice(&read[..n]);
+    }
+}
+
+fn run(store: &Store, args: &[Vec<u8>]) -> Vec<u8> {
+    let mut store = store.lock().unwrap();
+    let name = String::from_utf8_lossy(&args[0]).to_uppercase();
+    match name.as_str() {
+        "PING" => b"+PONG\r\n".to_vec(),
+        "SET" => {
+            store.insert(args[1].clone(), args[2].clone());
+            b"+OK\r\n".to_vec()
+        }
+        "GET" => bulk(store.get(&args[1])),
+        "INCR" => {
+            let value = store
+                .get(&args[1])
+                .map_or(0, |v| String::from_utf8_lossy(v).parse::<i64>().unwrap())
+                + 1;
+            store.insert(args[1].clone(), value.to_string().into_bytes());
+            format!(":{value}\r\n").into_bytes()
+        }
+        "MGET" => {
+            let mut reply = format!("*{}\r\n", args.len() - 1).into_bytes();
+            for key in &args[1..] {
+                reply.extend(bulk(store.get(key)));
+            }
+            reply
+        }
+        _ => format!("-ERR unknown command '{name}'\r\n").into_bytes(),
+    }
+}
+
+fn bulk(value: Option<&Vec<u8>>) -> Vec<u8> {
+    match value {
+        Some(value) => [format!("${}\r\n", value.len()).as_bytes(), value, b"\r\n"].concat(),
+        None => b"$-1\r\n".to_vec(),
+    }
+}
+
+// the line starting at `pos` and the position after it
+fn line(buf: &[u8], pos: usize) -> Option<(usize, usize)> {
+    let end = buf[pos..].windows(2).position(|w| w == b"\r\n")?;
+    let value = std::str::from_utf8(&buf[pos + 1..pos + end]).unwrap();
+    Some((value.parse().unwrap(), pos + end + 2))
+}
+
+// a command as an array of bulk strings, none until it is complete
+fn parse_command(buf: &[u8]) -> Option<(Vec<Vec<u8>>, usize)> {
+    if buf.is_empty() {
+        return None;
+    }
+    let (count, mut pos) = line(buf, 0)?;
+    let mut args = vec![];
+    for _ in 0..count {
+        let (len, start) = line(buf, pos)?;
+        if buf.len() < start + len + 2 {
+            return None;
+        }
+        args.push(buf[start..start + len].to_vec());
+        pos = start + len + 2;
+    }
+    Some((args, pos))
+}
+
+fn channel(addr: String) -> Channel<Redis> {
+    let options = ChannelOptions {
+        timeout: Some(Duration::from_secs(2)),
+        ..Default::default()
+    };
+    Channel::<Redis>::new(addr).with_options(options)
+}
+
+#[tokio::test]
+async fn pipeline() {
+    let ch = channel(redis_stub().await);
+    let mut cntl = Controller::new();
+    let reply = ch.command(&mut cntl, ["PING"]).await.unwrap();
+    assert_eq!(reply, RedisReply::Status("PONG".to_string()));
+
+    let req = RedisRequest::new()
+        .command(["SET", "a", "1"])
+        .command(["SET".as_bytes(), b"b", b"bin\r\n\0"])
+        .command(["INCR", "a"])
+        .command(["GET", "missing"])
+        .command(["MGET", "a", "b", "missing"])
+        .command(["NOPE"]);
+    let replies = ch.pipeline(&mut cntl, req).await.unwrap();
+    assert_eq!(
+        replies,
+        vec![
+            RedisReply::Status("OK".to_string()),
+            RedisReply::Status("OK".to_string()),
+            RedisReply::Integer(2),
+            RedisReply::Nil,
+            RedisReply::Array(vec![
+                RedisReply::Bulk(b"2".to_vec()),
+                RedisReply::Bulk(b"bin\r\n\0".to_vec()),
+                RedisReply::Nil,
+            ]),
+            RedisReply::Error("ERR unknown command 'NOPE'".to_string()),
+        ]
+    );
+    assert!(ch
+        .pipeline(&mut cntl, RedisRequest::new())
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn concurrent_pipelines() {
+    let ch = channel(redis_stub().await);
+    let calls = (0..20).map(|i| {
+        let ch = &ch;
+        async move {
+            let key = format!("k{i}");
+            let req = RedisRequest::new()
+                .command(["SET", key.as_str(), "v"])
+                .command(["INCR", "n"])
+                .command(["GET", key.as_str()]);
+            let replies = ch.pipeline(&mut Controller::new(), req).await.unwrap();
+            assert_eq!(replies[2].as_bytes(), Some(&b"v"[..]));
+            replies[1].as_integer().unwrap()
+        }
+    });
+    let mut counters = futures_util::future::join_all(calls).await;
+    counters.sort();
+    assert_eq!(counters, (1..=20).collect::<Vec<_>>());
+}
+
+struct Ping;
+
+#[async_trait]
+impl RedisCommandHandler for Ping {
+    async fn run(&self, _ctx: &mut Context, _args: &[Vec<u8>]) -> Result<RedisReply> {
+        Ok(RedisReply::Status("PONG".to_string()))
+    }
+}
+
+#[tokio::test]
+async fn served_only_if_enabled() {
+    let addr = start_server("", |server| server.add_service(Echo).unwrap()).await;
+    let err = channel(addr)
+        .command(&mut Controller::new(), ["PING"])
+        .await;
+    assert!(err.is_err());
+
+    let addr = start_server("", |server| {
+        let mut service = RedisService::new();
+        service.add_command_handler("ping", Ping).unwrap();
+        server.add_protocol(Redis::with_service(service)).unwrap();
+    })
+    .await;
+    let reply = channel(addr)
+        .command(&mut Controller::new(), ["PING"])
+        .await
+        .unwrap();
+    assert_eq!(reply, RedisReply::Status("PONG".to_string()));
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::BytesMut;
+use protobuf::{Message, MessageField};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use server_kit::channel::{Channel, ChannelOptions};
+use server_kit::conf::ChannelConf;
+use server_kit::message::CommonMsg;
+use server_kit::protocol::{Brpc, Protocol};
+use server_kit::Controller;
+use server_kit_protocol::baidu_rpc_meta::{RpcMeta, RpcResponseMeta};
+
+use common::{brpc_request, free_port, logged, start_server, Echo};
+
+// a server which reads every request and drops the connection without answering
+async fn start_dropping_server() -> (String, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&requests);
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 1024];
+            if stream.read(&mut buf).await.unwrap_or_default() > 0 {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    });
+    (addr, requests)
+}
+
+// a server which answers one request per connection and closes it right after,
+// as servers do with connections idle for too long
+async fn start_closing_server() -> (String, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&requests);
+    tokio::spawn(async move {
+        let brpc = Brpc::default();
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = BytesMut::new();
+            while brpc.parse(&mut buf).is_err() {
+                if stream.read_buf(&mut buf).await.unwrap_or_default() == 0 {
+                    break;
+                }
+            }
+            counter.fetch_add(1, Ordering::SeqCst);
+            let mut meta = RpcMeta::new();
+            meta.response = MessageField::some(RpcResponseMeta::new());
+            let mut resp = CommonMsg::new(b"ok".to_vec());
+            resp.with_meta(meta.write_to_bytes().unwrap());
+            let _ = stream.write_all(&brpc.pack_response(resp)).await;
+        }
+    });
+    (addr, requests)
+}
+
+fn retrying(addr: String) -> Channel<Brpc> {
+    let options = ChannelOptions {
+        max_retry: 2,
+        ..Default::default()
+    };
+    Channel::<Brpc>::new(addr).with_options(options)
+}
+
+// round robin over `servers`, starting with the first one
+fn retrying_list(servers: &[&str]) -> Channel<Brpc> {
+    let conf = ChannelConf {
+        ns_url: Some(format!("list://{}", servers.join(","))),
+        max_retry: Some(2),
+        ..Default::default()
+    };
+    Channel::<Brpc>::from_conf(&conf).unwrap()
+}
+
+#[tokio::test]
+async fn no_retry_once_sent() {
+    let (addr, requests) = start_dropping_server().await;
+    let ch = retrying(addr);
+
+    let res = ch
+        .call(&mut Controller::new(), brpc_request("m", b""))
+        .await;
+    assert!(res.is_err());
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn retry_idempotent_once_sent() {
+    let (addr, requests) = start_dropping_server().await;
+    let ch = retrying(addr);
+
+    let mut cntl = Controller::new();
+    cntl.set_idempotent(true);
+    assert!(ch.call(&mut cntl, brpc_request("m", b"")).await.is_err());
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn retry_before_sent() {
+    let backend = start_server("", |server| server.add_service(Echo).unwrap()).await;
+    let dead = format!("127.0.0.1:{}", free_port().await);
+    // the server refusing connections is tried first
+    let ch = retrying_list(&[&dead, &backend]);
+    let resp = ch
+        .call(&mut Controller::new(), brpc_request("m", b"x"))
+        .await
+        .unwrap();
+    assert_eq!(resp, b"mx");
+
+    let ch = retrying_list(&[&dead, &backend]);
+    let mut cntl = Controller::new();
+    ch.call_oneway(&mut cntl, brpc_request("log", b"retried-oneway"))
+        .await
+        .unwrap();
+    assert_eq!(cntl.remote_addr().unwrap().to_string(), backend);
+    assert_eq!(logged(b"retried-oneway").await, 1);
+}
+
+#[tokio::test]
+async fn skip_closed_idle_connection() {
+    let (addr, requests) = start_closing_server().await;
+    // no retries, the second call is only answered on a new connection
+    let ch = Channel::<Brpc>::new(addr);
+
+    for i in 1..=3 {
+        let resp = ch
+            .call(&mut Controller::new(), brpc_request("m", b""))
+            .await
+            .unwrap();
+        assert_eq!(resp, b"ok");
+        assert_eq!(requests.load(Ordering::SeqCst), i);
+        // for the server to be done closing the connection left idle
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
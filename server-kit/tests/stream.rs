@@ -0,0 +1,231 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use futures_util::{SinkExt, StreamExt};
+use protobuf::{Message, MessageField};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Notify;
+
+use server_kit::channel::Channel;
+use server_kit::error::StreamErr;
+use server_kit::message::CommonMsg;
+use server_kit::protocol::{Brpc, Protocol, Streaming};
+use server_kit::stream::StreamOptions;
+use server_kit::{
+    Context, Controller, Error, MethodDescriptor, Result, Service, ServiceDescriptor,
+};
+use server_kit_protocol::baidu_rpc_meta::{RpcMeta, RpcRequestMeta, RpcResponseMeta};
+use server_kit_protocol::streaming_rpc_meta::{FrameType, StreamFrameMeta, StreamSettings};
+
+use common::start_server;
+
+const WINDOW: i64 = 1024;
+
+/// "test.stream", whose "echo" sends back what comes on the stream of the
+/// request, and whose "wait" waits until "count" got as many bytes on its
+/// stream as the request tells.
+#[derive(Default)]
+struct Streams {
+    received: Arc<(AtomicUsize, Notify)>,
+}
+
+#[async_trait]
+impl Service for Streams {
+    fn descriptor(&self) -> ServiceDescriptor {
+        ServiceDescriptor {
+            full_name: "test.stream",
+            methods: vec![
+                MethodDescriptor::new("echo"),
+                MethodDescriptor::new("count"),
+                MethodDescriptor::new("wait"),
+            ],
+        }
+    }
+
+    async fn call_method(&self, ctx: &mut Context, method: &str, req: &[u8]) -> Result<Vec<u8>> {
+        match method {
+            "echo" => {
+                let mut stream = ctx.accept_stream(&StreamOptions {
+                    max_buf_size: WINDOW,
+                })?;
+                tokio::spawn(async move {
+                    while let Some(Ok(data)) = stream.next().await {
+                        if stream.send(data).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            "count" => {
+                let mut stream = ctx.accept_stream(&StreamOptions::default())?;
+                let received = Arc::clone(&self.received);
+                tokio::spawn(async move {
+                    while let Some(Ok(data)) = stream.next().await {
+                        received.0.fetch_add(data.len(), Ordering::SeqCst);
+                        received.1.notify_one();
+                    }
+                });
+            }
+            _ => {
+                let expected = u32::from_le_bytes(req.try_into().unwrap()) as usize;
+                while self.received.0.load(Ordering::SeqCst) < expected {
+                    self.received.1.notified().await;
+                }
+            }
+        }
+        Ok(method.as_bytes().to_vec())
+    }
+}
+
+fn request(method: &str, payload: &[u8]) -> CommonMsg {
+    let mut req_meta = RpcRequestMeta::new();
+    req_meta.set_service_name("test.stream".to_string());
+    req_meta.set_method_name(method.to_string());
+    let mut meta = RpcMeta::new();
+    meta.request = MessageField::some(req_meta);
+    let mut msg = CommonMsg::new(payload.to_vec());
+    msg.with_meta(meta.write_to_bytes().unwrap());
+    msg
+}
+
+fn frame(stream_id: i64, source_stream_id: i64, frame_type: FrameType, data: &[u8]) -> Vec<u8> {
+    let mut meta = StreamFrameMeta::new();
+    meta.set_stream_id(stream_id);
+    meta.set_source_stream_id(source_stream_id);
+    meta.set_frame_type(frame_type);
+    let mut msg = CommonMsg::new(data.to_vec());
+    msg.with_meta(meta.write_to_bytes().unwrap());
+    Streaming.pack_request(&Controller::new(), msg).unwrap()
+}
+
+async fn read_message<P: Protocol>(protocol: &P, stream: &mut TcpStream) -> CommonMsg {
+    let mut buf = BytesMut::new();
+    loop {
+        match protocol.parse(&mut buf) {
+            Ok(msg) => return msg,
+            Err(_) => assert!(stream.read_buf(&mut buf).await.unwrap() > 0),
+        }
+    }
+}
+
+#[tokio::test]
+async fn stream_both_ways() {
+    let addr = start_server("", |server| server.add_service(Streams::default()).unwrap()).await;
+    let ch = Channel::<Brpc>::new(addr);
+
+    let options = StreamOptions {
+        max_buf_size: WINDOW,
+    };
+    let (resp, stream) = ch
+        .call_with_stream(&mut Controller::new(), request("echo", b""), &options)
+        .await
+        .unwrap();
+    assert_eq!(resp, b"echo");
+
+    // sixteen times the window each way, which only goes through on feedback
+    let (mut tx, mut rx) = stream.split();
+    let msgs: Vec<_> = (0..64u8).map(|i| vec![i; 256]).collect();
+    let send = async {
+        for msg in &msgs {
+            tx.send(msg.clone()).await.unwrap();
+        }
+    };
+    let receive = async {
+        for msg in &msgs {
+            assert_eq!(&rx.next().await.unwrap().unwrap(), msg);
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), async {
+        tokio::join!(send, receive)
+    })
+    .await
+    .expect("stream stalled");
+
+    // closing ends both sides
+    tx.close().await.unwrap();
+    assert!(tx.send(vec![0]).await.is_err());
+    assert!(rx.next().await.is_none());
+}
+
+#[tokio::test]
+async fn handler_awaits_stream() {
+    let addr = start_server("", |server| server.add_service(Streams::default()).unwrap()).await;
+    let brpc = Brpc::default();
+    let mut conn = TcpStream::connect(&addr).await.unwrap();
+
+    let mut req = request("count", b"");
+    let mut meta = RpcMeta::parse_from_bytes(&req.meta).unwrap();
+    let mut settings = StreamSettings::new();
+    settings.set_stream_id(100);
+    settings.set_writable(true);
+    meta.stream_settings = MessageField::some(settings);
+    req.with_meta(meta.write_to_bytes().unwrap());
+    let req = brpc.pack_request(&Controller::new(), req).unwrap();
+    conn.write_all(&req).await.unwrap();
+    let meta = RpcMeta::parse_from_bytes(&read_message(&brpc, &mut conn).await.meta).unwrap();
+    let remote = meta.stream_settings.stream_id();
+
+    // the frames "wait" waits on come on the connection after it
+    let size = 4096u32;
+    let req = request("wait", &size.to_le_bytes());
+    let req = brpc.pack_request(&Controller::new(), req).unwrap();
+    conn.write_all(&req).await.unwrap();
+    for _ in 0..size / 512 {
+        let data = frame(remote, 100, FrameType::FRAME_TYPE_DATA, &[0; 512]);
+        conn.write_all(&data).await.unwrap();
+    }
+    let msg = tokio::time::timeout(Duration::from_secs(5), read_message(&brpc, &mut conn))
+        .await
+        .expect("request waiting on its stream stalled the connection");
+    assert_eq!(msg.payload, b"wait");
+}
+
+#[tokio::test]
+async fn peer_reset() {
+    // accepts the stream of the first request and resets it right away
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        let brpc = Brpc::default();
+        let (mut conn, _) = listener.accept().await.unwrap();
+        let req = read_message(&brpc, &mut conn).await;
+        let client_id = RpcMeta::parse_from_bytes(&req.meta)
+            .unwrap()
+            .stream_settings
+            .stream_id();
+
+        let mut settings = StreamSettings::new();
+        settings.set_stream_id(7);
+        settings.set_writable(true);
+        let mut meta = RpcMeta::new();
+        meta.response = MessageField::some(RpcResponseMeta::new());
+        meta.stream_settings = MessageField::some(settings);
+        let mut resp = CommonMsg::new(b"ok".to_vec());
+        resp.with_meta(meta.write_to_bytes().unwrap());
+        conn.write_all(&brpc.pack_response(resp)).await.unwrap();
+        let rst = frame(client_id, 7, FrameType::FRAME_TYPE_RST, b"");
+        conn.write_all(&rst).await.unwrap();
+        let _ = conn.read(&mut [0; 1024]).await;
+    });
+
+    let ch = Channel::<Brpc>::new(addr);
+    let (resp, mut stream) = ch
+        .call_with_stream(
+            &mut Controller::new(),
+            request("echo", b""),
+            &StreamOptions::default(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(resp, b"ok");
+    let err = stream.next().await.unwrap().unwrap_err();
+    assert!(matches!(err, Error::Stream(StreamErr::Reset(_))), "{err}");
+    assert!(stream.next().await.is_none());
+    assert!(stream.send(vec![0]).await.is_err());
+}
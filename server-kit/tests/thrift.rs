@@ -0,0 +1,151 @@
+mod common;
+
+use async_trait::async_trait;
+use bytes::{Buf, BufMut, BytesMut};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use server_kit::channel::Channel;
+use server_kit::error::{RpcErr, ThriftErr};
+use server_kit::protocol::{thrift_exception, Protocol, Thrift};
+use server_kit::{
+    Context, Controller, Error, MethodDescriptor, Result, Service, ServiceDescriptor,
+};
+
+use common::start_server;
+
+/// "test.thrift", whose "echo" returns its args struct as the result, and
+/// whose "fail" fails.
+struct Structs;
+
+#[async_trait]
+impl Service for Structs {
+    fn descriptor(&self) -> ServiceDescriptor {
+        ServiceDescriptor {
+            full_name: "test.thrift",
+            methods: vec![MethodDescriptor::new("echo"), MethodDescriptor::new("fail")],
+        }
+    }
+
+    async fn call_method(&self, _ctx: &mut Context, method: &str, req: &[u8]) -> Result<Vec<u8>> {
+        match method {
+            "echo" => Ok(req.to_vec()),
+            _ => Err(RpcErr::Failed(1, "failed as asked".to_string()).into()),
+        }
+    }
+}
+
+// a struct of one string field and its stop
+fn string_struct(s: &str) -> Vec<u8> {
+    let mut buf = vec![11, 0, 1];
+    buf.put_i32(s.len() as i32);
+    buf.put_slice(s.as_bytes());
+    buf.put_u8(0);
+    buf
+}
+
+fn failed(err: &Error) -> Option<(i32, &str)> {
+    match err {
+        Error::Rpc(RpcErr::Failed(ty, message)) => Some((*ty, message)),
+        _ => None,
+    }
+}
+
+#[tokio::test]
+async fn framed_round_trip() {
+    let addr = start_server("protocols = [\"thrift\"]", |server| {
+        server.add_service(Structs).unwrap()
+    })
+    .await;
+    let ch = Channel::<Thrift>::new(addr);
+
+    for method in ["echo", "test.thrift:echo"] {
+        let args = string_struct(method);
+        let resp = ch
+            .call_method(&mut Controller::new(), method, args.clone())
+            .await
+            .unwrap();
+        assert_eq!(resp, args);
+    }
+
+    // failures are replied as TApplicationException, on the same connection
+    let err = ch
+        .call_method(&mut Controller::new(), "nope", string_struct(""))
+        .await
+        .unwrap_err();
+    let (ty, message) = failed(&err).unwrap();
+    assert_eq!(ty, thrift_exception::UNKNOWN_METHOD);
+    assert!(message.contains("nope"), "{message}");
+
+    let err = ch
+        .call_method(&mut Controller::new(), "fail", string_struct(""))
+        .await
+        .unwrap_err();
+    let (ty, message) = failed(&err).unwrap();
+    assert_eq!(ty, thrift_exception::INTERNAL_ERROR);
+    assert!(message.contains("failed as asked"), "{message}");
+
+    let resp = ch
+        .call_method(&mut Controller::new(), "echo", string_struct("again"))
+        .await
+        .unwrap();
+    assert_eq!(resp, string_struct("again"));
+}
+
+#[tokio::test]
+async fn reply_of_other_call() {
+    // replying with the seqid of each call, but the one of the first call to the third
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buf = BytesMut::new();
+        let mut seq_ids = vec![];
+        loop {
+            let msg = match Thrift::default().parse(&mut buf) {
+                Ok(msg) => msg,
+                Err(_) => match stream.read_buf(&mut buf).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(_) => continue,
+                },
+            };
+            let mut meta = &msg.meta[..];
+            meta.advance(4);
+            let name_len = meta.get_i32() as usize;
+            let name = meta[..name_len].to_vec();
+            meta.advance(name_len);
+            seq_ids.push(meta.get_i32());
+            let seq_id = match seq_ids.len() {
+                3 => seq_ids[0],
+                _ => *seq_ids.last().unwrap(),
+            };
+
+            let mut reply = vec![0x80, 0x01, 0x00, 0x02];
+            reply.put_i32(name_len as i32);
+            reply.put_slice(&name);
+            reply.put_i32(seq_id);
+            reply.put_slice(&msg.payload);
+            let mut frame = vec![];
+            frame.put_u32(reply.len() as u32);
+            frame.put_slice(&reply);
+            stream.write_all(&frame).await.unwrap();
+        }
+    });
+
+    let ch = Channel::<Thrift>::new(addr);
+    for _ in 0..2 {
+        let resp = ch
+            .call_method(&mut Controller::new(), "echo", string_struct("ok"))
+            .await
+            .unwrap();
+        assert_eq!(resp, string_struct("ok"));
+    }
+    let err = ch
+        .call_method(&mut Controller::new(), "echo", string_struct("ok"))
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(err, Error::Thrift(ThriftErr::UnexpectedSeqId(3, 1))),
+        "{err}"
+    );
+}
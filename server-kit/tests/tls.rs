@@ -0,0 +1,167 @@
+mod common;
+
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+
+use server_kit::channel::Channel;
+use server_kit::conf::ChannelTlsConf;
+use server_kit::protocol::Brpc;
+use server_kit::{Context, Controller, MethodDescriptor, Result, Service, ServiceDescriptor};
+
+use common::{brpc_request, start_server};
+
+/// "test.echo", answering with the common name of the client certificate.
+struct Whoami;
+
+#[async_trait]
+impl Service for Whoami {
+    fn descriptor(&self) -> ServiceDescriptor {
+        ServiceDescriptor {
+            full_name: "test.echo",
+            methods: vec![MethodDescriptor::new("m")],
+        }
+    }
+
+    async fn call_method(&self, ctx: &mut Context, _: &str, _: &[u8]) -> Result<Vec<u8>> {
+        let cn = ctx
+            .peer_certificate()
+            .and_then(|cert| cert.common_name.clone());
+        Ok(cn.unwrap_or_default().into_bytes())
+    }
+}
+
+/// PEM files of a CA, a server certificate for "localhost" and a client
+/// certificate issued by it, and a client certificate issued by another CA.
+struct Pki {
+    dir: PathBuf,
+}
+
+impl Pki {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("server_kit_tls_{name}"));
+        std::fs::create_dir_all(&dir).unwrap();
+        let pki = Self { dir };
+
+        let (ca, ca_key) = issuer("ca");
+        pki.write("ca.pem", &ca.pem());
+        let (other_ca, other_ca_key) = issuer("other ca");
+        for (name, cn, issuer, issuer_key) in [
+            ("server", "localhost", &ca, &ca_key),
+            ("client", "client", &ca, &ca_key),
+            ("stranger", "stranger", &other_ca, &other_ca_key),
+        ] {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(vec![cn.to_string()]).unwrap();
+            params.distinguished_name.push(DnType::CommonName, cn);
+            let cert = params.signed_by(&key, issuer, issuer_key).unwrap();
+            pki.write(&format!("{name}.pem"), &cert.pem());
+            pki.write(&format!("{name}.key"), &key.serialize_pem());
+        }
+        pki
+    }
+
+    fn path(&self, file: &str) -> String {
+        self.dir.join(file).to_string_lossy().into_owned()
+    }
+
+    fn write(&self, file: &str, pem: &str) {
+        std::fs::write(self.dir.join(file), pem).unwrap();
+    }
+
+    // `[tls]` of the server configuration, `extra` added to it
+    fn server_conf(&self, extra: &str) -> String {
+        format!(
+            "[tls]\ncert = \"{}\"\nkey = \"{}\"\n{extra}\n",
+            self.path("server.pem"),
+            self.path("server.key"),
+        )
+    }
+
+    // TLS of a channel, presenting the certificate `name` if any
+    fn channel_conf(&self, name: Option<&str>) -> ChannelTlsConf {
+        ChannelTlsConf {
+            ca: self.path("ca.pem"),
+            server_name: "localhost".to_string(),
+            cert: name.map(|name| self.path(&format!("{name}.pem"))),
+            key: name.map(|name| self.path(&format!("{name}.key"))),
+        }
+    }
+}
+
+fn issuer(cn: &str) -> (rcgen::Certificate, KeyPair) {
+    let key = KeyPair::generate().unwrap();
+    let mut params = CertificateParams::new(vec![]).unwrap();
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.distinguished_name.push(DnType::CommonName, cn);
+    (params.self_signed(&key).unwrap(), key)
+}
+
+async fn call(ch: &Channel<Brpc>) -> Result<Vec<u8>> {
+    ch.call(&mut Controller::new(), brpc_request("m", b""))
+        .await
+}
+
+#[tokio::test]
+async fn mtls() {
+    let pki = Pki::new("mtls");
+    let conf = pki.server_conf(&format!("client_ca = \"{}\"", pki.path("ca.pem")));
+    let addr = start_server(&conf, |server| server.add_service(Whoami).unwrap()).await;
+
+    let ch = Channel::<Brpc>::new(addr.clone())
+        .with_tls(&pki.channel_conf(Some("client")))
+        .unwrap();
+    assert_eq!(call(&ch).await.unwrap(), b"client");
+
+    // no certificate, or one of a CA the server does not trust
+    for name in [None, Some("stranger")] {
+        let ch = Channel::<Brpc>::new(addr.clone())
+            .with_tls(&pki.channel_conf(name))
+            .unwrap();
+        assert!(call(&ch).await.is_err(), "{name:?}");
+    }
+
+    // nor plaintext
+    let ch = Channel::<Brpc>::new(addr.clone());
+    assert!(call(&ch).await.is_err());
+
+    // and the server still serves the clients it trusts
+    let ch = Channel::<Brpc>::new(addr)
+        .with_tls(&pki.channel_conf(Some("client")))
+        .unwrap();
+    assert_eq!(call(&ch).await.unwrap(), b"client");
+}
+
+#[tokio::test]
+async fn tls_without_client_auth() {
+    let pki = Pki::new("server_only");
+    let addr = start_server(&pki.server_conf(""), |server| {
+        server.add_service(Whoami).unwrap()
+    })
+    .await;
+
+    let ch = Channel::<Brpc>::new(addr.clone())
+        .with_tls(&pki.channel_conf(None))
+        .unwrap();
+    assert_eq!(call(&ch).await.unwrap(), b"");
+
+    let ch = Channel::<Brpc>::new(addr);
+    assert!(call(&ch).await.is_err());
+}
+
+#[tokio::test]
+async fn allow_plaintext() {
+    let pki = Pki::new("plaintext");
+    let conf = pki.server_conf("allow_plaintext = true");
+    let addr = start_server(&conf, |server| server.add_service(Whoami).unwrap()).await;
+
+    let ch = Channel::<Brpc>::new(addr.clone());
+    assert_eq!(call(&ch).await.unwrap(), b"");
+
+    let ch = Channel::<Brpc>::new(addr)
+        .with_tls(&pki.channel_conf(Some("client")))
+        .unwrap();
+    // no client certificate asked for without client_ca
+    assert_eq!(call(&ch).await.unwrap(), b"");
+}